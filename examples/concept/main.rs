@@ -14,7 +14,7 @@ use amethyst::{
     window::ScreenDimensions,
 };
 
-use amethyst_tiled::{TileGid, TileMapPrefab, TiledFormat};
+use amethyst_tiled::{TileAnimationSystem, TileGid, TileMapPrefab, TiledFormat};
 
 #[derive(Default)]
 pub struct CameraMovementSystem;
@@ -100,6 +100,7 @@ fn main() -> amethyst::Result<()> {
                 .with_bindings_from_file("examples/concept/resources/input.ron")?,
         )?
         .with(CameraMovementSystem::default(), "movement", &[])
+        .with(TileAnimationSystem, "tile_animation", &[])
         .with_bundle(
             RenderingBundle::<DefaultBackend>::new()
                 .with_plugin(
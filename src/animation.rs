@@ -0,0 +1,308 @@
+//! Support for animated tiles using the `<animation>` frames defined in a tileset
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use amethyst::assets::{Handle, WeakHandle};
+use amethyst::core::Time;
+use amethyst::ecs::{Read, System, Write};
+use amethyst::renderer::SpriteSheet;
+use tiled::Map;
+
+#[cfg(feature = "profiler")]
+use thread_profiler::profile_scope;
+
+/// An animation shared by every placement of an animated tile within a map. Frames are stored as
+/// sprite indices of the map's sprite sheet along with how long each frame is shown for.
+#[derive(Debug, Clone)]
+pub struct TileAnimation {
+    frames: Vec<(usize, Duration)>,
+    current: usize,
+    elapsed: Duration,
+}
+
+impl TileAnimation {
+    pub fn new(frames: Vec<(usize, Duration)>) -> Self {
+        assert!(
+            !frames.is_empty(),
+            "A tile animation requires at least one frame"
+        );
+
+        Self {
+            frames,
+            current: 0,
+            elapsed: Duration::default(),
+        }
+    }
+
+    /// The sprite index of the frame currently being shown
+    pub fn sprite(&self) -> usize {
+        self.frames[self.current].0
+    }
+
+    pub fn frames(&self) -> &[(usize, Duration)] {
+        &self.frames
+    }
+
+    /// Move the animation forward by the given amount of time
+    pub fn advance(&mut self, delta: Duration) {
+        let total: Duration = self.frames.iter().map(|(_, duration)| *duration).sum();
+
+        // Frames without a duration would never finish
+        if total == Duration::default() {
+            return;
+        }
+
+        self.elapsed += delta;
+
+        // Skip any full loops so long pauses don't require stepping through every frame
+        if self.elapsed >= total {
+            self.elapsed =
+                Duration::from_nanos((self.elapsed.as_nanos() % total.as_nanos()) as u64);
+        }
+
+        while self.elapsed >= self.frames[self.current].1 {
+            self.elapsed -= self.frames[self.current].1;
+            self.current = (self.current + 1) % self.frames.len();
+        }
+    }
+}
+
+/// An animation along with the sprite sheets of the map it belongs to
+#[derive(Debug)]
+struct OwnedAnimation {
+    animation: TileAnimation,
+    /// The animation is removed once every one of these sheets has been dropped
+    owners: Vec<WeakHandle<SpriteSheet>>,
+}
+
+/// Resource holding the state of every tile animation created while loading maps. Animations are
+/// owned by the sprite sheets of their map, so they are removed along with the map once its
+/// entity and prefab have been dropped, and their ids are reused by later animations.
+#[derive(Debug, Default)]
+pub struct TileAnimations {
+    animations: Vec<Option<OwnedAnimation>>,
+    free: Vec<usize>,
+}
+
+impl TileAnimations {
+    /// Add an animation and get the id used to refer to it from a `TileGid`. The animation is kept
+    /// until it is given owners with `set_owners`.
+    pub fn push(&mut self, animation: TileAnimation) -> usize {
+        let animation = Some(OwnedAnimation {
+            animation,
+            owners: Vec::new(),
+        });
+
+        match self.free.pop() {
+            Some(id) => {
+                self.animations[id] = animation;
+                id
+            }
+            None => {
+                self.animations.push(animation);
+                self.animations.len() - 1
+            }
+        }
+    }
+
+    /// Tie animations to the sprite sheets they are drawn with, replacing any previous owners
+    pub fn set_owners(
+        &mut self,
+        ids: impl IntoIterator<Item = usize>,
+        sheets: &[Handle<SpriteSheet>],
+    ) {
+        for id in ids {
+            if let Some(Some(animation)) = self.animations.get_mut(id) {
+                animation.owners = sheets.iter().map(Handle::downgrade).collect();
+            }
+        }
+    }
+
+    pub fn get(&self, id: usize) -> Option<&TileAnimation> {
+        self.animations
+            .get(id)
+            .and_then(Option::as_ref)
+            .map(|x| &x.animation)
+    }
+
+    /// The sprite index currently shown by an animation
+    pub fn sprite(&self, id: usize) -> Option<usize> {
+        self.get(id).map(TileAnimation::sprite)
+    }
+
+    /// The number of animations which have not been removed
+    pub fn len(&self) -> usize {
+        self.animations.len() - self.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn advance(&mut self, delta: Duration) {
+        for animation in self.animations.iter_mut().flatten() {
+            animation.animation.advance(delta);
+        }
+    }
+
+    /// Remove every animation whose sprite sheets have all been dropped
+    pub fn maintain(&mut self) {
+        #[cfg(feature = "profiler")]
+        profile_scope!("maintain_tile_animations");
+
+        for (id, slot) in self.animations.iter_mut().enumerate() {
+            let is_dead = match slot {
+                Some(animation) => {
+                    !animation.owners.is_empty() && animation.owners.iter().all(WeakHandle::is_dead)
+                }
+                None => false,
+            };
+
+            if is_dead {
+                *slot = None;
+                self.free.push(id);
+            }
+        }
+    }
+}
+
+/// System which steps every tile animation forward using the real time elapsed since the last
+/// frame, and removes the animations of maps which have been dropped.
+#[derive(Debug, Default)]
+pub struct TileAnimationSystem;
+
+impl<'a> System<'a> for TileAnimationSystem {
+    type SystemData = (Read<'a, Time>, Write<'a, TileAnimations>);
+
+    fn run(&mut self, (time, mut animations): Self::SystemData) {
+        #[cfg(feature = "profiler")]
+        profile_scope!("tile_animation_system");
+
+        animations.maintain();
+        animations.advance(time.delta_real_time());
+    }
+}
+
+/// Collect the frames of every animated tile in the map's tilesets. The frames are keyed by the gid
+/// of the animated tile and hold the gid of each frame along with its duration.
+pub fn collect_frames(map: &Map) -> HashMap<u32, Vec<(u32, Duration)>> {
+    #[cfg(feature = "profiler")]
+    profile_scope!("collect_animation_frames");
+
+    let mut animations = HashMap::new();
+
+    for set in &map.tilesets {
+        let set = set.unwrap();
+
+        for tile in &set.tiles {
+            if let Some(frames) = &tile.animation {
+                if frames.is_empty() {
+                    continue;
+                }
+
+                let frames = frames
+                    .iter()
+                    .map(|frame| {
+                        (
+                            set.first_gid + frame.tile_id,
+                            Duration::from_millis(frame.duration as u64),
+                        )
+                    })
+                    .collect();
+
+                animations.insert(set.first_gid + tile.id, frames);
+            }
+        }
+    }
+
+    animations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use amethyst::assets::AssetStorage;
+
+    fn animation(frames: &[(usize, u64)]) -> TileAnimation {
+        TileAnimation::new(
+            frames
+                .iter()
+                .map(|(sprite, ms)| (*sprite, Duration::from_millis(*ms)))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn frames_advance_in_order() {
+        let mut animation = animation(&[(4, 100), (5, 50), (6, 150)]);
+        assert_eq!(animation.sprite(), 4);
+
+        animation.advance(Duration::from_millis(99));
+        assert_eq!(animation.sprite(), 4);
+
+        animation.advance(Duration::from_millis(1));
+        assert_eq!(animation.sprite(), 5);
+
+        animation.advance(Duration::from_millis(200));
+        assert_eq!(animation.sprite(), 4);
+    }
+
+    #[test]
+    fn long_deltas_wrap_around() {
+        let mut animation = animation(&[(4, 100), (5, 50), (6, 150)]);
+
+        // Three full loops followed by the first frame
+        animation.advance(Duration::from_millis(1000));
+        assert_eq!(animation.sprite(), 5);
+
+        animation.advance(Duration::from_millis(49));
+        assert_eq!(animation.sprite(), 5);
+
+        animation.advance(Duration::from_millis(1));
+        assert_eq!(animation.sprite(), 6);
+
+        animation.advance(Duration::from_secs(3600) + Duration::from_millis(150));
+        assert_eq!(animation.sprite(), 4);
+    }
+
+    #[test]
+    fn frames_without_duration_are_skipped() {
+        let mut animation = animation(&[(4, 0), (5, 100), (6, 0)]);
+        animation.advance(Duration::from_millis(50));
+        assert_eq!(animation.sprite(), 5);
+
+        let mut still = self::animation(&[(4, 0), (5, 0)]);
+        still.advance(Duration::from_secs(1));
+        assert_eq!(still.sprite(), 4);
+    }
+
+    #[test]
+    fn animations_of_dropped_sheets_are_removed() {
+        let storage = AssetStorage::<SpriteSheet>::default();
+        let (dropped, kept) = (storage.allocate(), storage.allocate());
+
+        let mut animations = TileAnimations::default();
+        let first = animations.push(animation(&[(1, 100)]));
+        let second = animations.push(animation(&[(2, 100)]));
+        let unowned = animations.push(animation(&[(3, 100)]));
+        animations.set_owners(vec![first], &[dropped.clone(), kept.clone()]);
+        animations.set_owners(vec![second], &[dropped.clone()]);
+
+        drop(dropped);
+        animations.maintain();
+
+        // Animations are kept while any of their sheets is alive, or until they are given owners
+        assert_eq!(animations.len(), 2);
+        assert_eq!(animations.sprite(first), Some(1));
+        assert_eq!(animations.sprite(second), None);
+        assert_eq!(animations.sprite(unowned), Some(3));
+
+        assert_eq!(animations.push(animation(&[(4, 100)])), second);
+        assert_eq!(animations.len(), 3);
+
+        drop(kept);
+        animations.maintain();
+        assert_eq!(animations.sprite(first), None);
+    }
+}
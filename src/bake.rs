@@ -0,0 +1,734 @@
+//! Offline baking of the sprite sheet a map is drawn with, so shipped builds can load the packed
+//! atlas instead of packing the map's tilesets at startup. A baked map is made up of an image and
+//! a sprite sheet description in the RON format used by amethyst's `SpriteSheetFormat` for every
+//! page of the atlas, and a file holding the tiles of every layer along with the gids packed into
+//! the atlas.
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::hash::Hasher;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use std::marker::PhantomData;
+
+use amethyst::assets::{AssetStorage, Directory, Format, Loader, ProgressCounter, Source};
+use amethyst::core::math::Point3;
+use amethyst::ecs::Entity;
+use amethyst::renderer::sprite::Sprite;
+use amethyst::renderer::{SpriteSheetFormat, Texture};
+use amethyst::tiles::{CoordinateEncoder, FlatEncoder};
+use amethyst::Error;
+use image::{load_from_memory, save_buffer, ColorType};
+use log::warn;
+use sheep::{encode, SpriteAnchor};
+use tiled::{Map, Tileset};
+
+use crate::animation::collect_frames;
+use crate::bounds::{layer_tiles, MapBounds};
+use crate::cache::{
+    atlas_key, hash_bytes, read_gids, read_u32, write_gids, write_u32, PackedAtlas, StableHasher,
+};
+use crate::packing::{pack_sparse_tileset_vec, OrderedAnchors, PackSettings};
+use crate::pages::PagedSheet;
+use crate::prefab::{ChildData, TileMapPrefab};
+use crate::strategy::{
+    collect_gid_usage, insert_atlas_map, load_packed, AtlasData, CompressedLoad, LoadStrategy,
+    PackData, PagedMap, StrategyDesc,
+};
+use crate::{load_texture, TiledFormat};
+
+#[cfg(feature = "profiler")]
+use thread_profiler::profile_scope;
+
+const MAGIC: &[u8; 4] = b"TLMP";
+const VERSION: u32 = 4;
+
+/// The extension given to the atlas image of a baked map
+pub const ATLAS_EXTENSION: &str = "atlas.png";
+/// The extension given to the sprite sheet description of a baked map
+pub const SHEET_EXTENSION: &str = "atlas.ron";
+/// The extension given to the tiles of a baked map
+pub const TILES_EXTENSION: &str = "tiles";
+
+/// The kind of atlas baked maps contain, as passed to `cache::atlas_key`
+pub(crate) const ATLAS_KIND: &str = "compressed";
+
+/// The path of a baked file for a map, found by replacing the extension of the map's path
+pub fn baked_path(map: &str, extension: &str) -> String {
+    let mut path = PathBuf::from(map);
+    path.set_extension(extension);
+    path.to_str().unwrap().to_owned()
+}
+
+/// The extension of a file baked for a page of the atlas. The first page uses the extension as is,
+/// while later pages have their number inserted before the file type, as in `atlas.1.png`.
+pub fn page_extension(extension: &str, page: usize) -> String {
+    match (page, extension.rsplit_once('.')) {
+        (0, _) => extension.to_owned(),
+        (_, Some((name, kind))) => format!("{}.{}.{}", name, page, kind),
+        (_, None) => format!("{}.{}", extension, page),
+    }
+}
+
+/// Read a map and its external tilesets from a file in the same way as `TiledFormat`. The returned
+/// source is the directory containing the map, along with the name of the map file within it.
+pub fn read_map(path: &Path) -> Result<(Map, Arc<dyn Source>, String), Error> {
+    let name = match path.file_name().and_then(|name| name.to_str()) {
+        Some(v) => v.to_owned(),
+        None => return Err(Error::from_string(format!("Invalid map path: {:?}", path))),
+    };
+
+    let directory = path.parent().unwrap_or_else(|| Path::new(""));
+    let source: Arc<dyn Source> = Arc::new(Directory::new(directory));
+
+    let value = <TiledFormat as Format<TileMapPrefab<CompressedLoad>>>::import(
+        &TiledFormat,
+        name.clone(),
+        source.clone(),
+        None,
+    )?;
+
+    match value.data {
+        TileMapPrefab::Map(map, ..) => Ok((map, source, name)),
+        TileMapPrefab::Result(..) => unreachable!("Maps are loaded before being processed"),
+    }
+}
+
+/// A version of `CompressedLoad` which uses the atlas baked by `tiled_bake` next to the map file
+/// instead of packing the map's tiles. Maps which were not baked, or whose tiles or tilesets have
+/// changed since they were baked, are packed as usual.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct BakedLoad<E: CoordinateEncoder = FlatEncoder>(PhantomData<E>);
+
+impl<E: CoordinateEncoder> StrategyDesc for BakedLoad<E> {
+    type Result = PagedMap<E>;
+}
+
+impl<'a, E: CoordinateEncoder> LoadStrategy<'a> for BakedLoad<E> {
+    type SystemData = PackData<'a>;
+    type SpawnData = AtlasData<'a, E>;
+
+    fn load(
+        map: &Map,
+        bounds: &MapBounds,
+        source: Arc<dyn Source>,
+        progress: &mut ProgressCounter,
+        system_data: &mut Self::SystemData,
+    ) -> Result<Self::Result, Error> {
+        <CompressedLoad<E> as LoadStrategy<'a>>::load(map, bounds, source, progress, system_data)
+    }
+
+    fn load_file(
+        name: &str,
+        map: &Map,
+        bounds: &MapBounds,
+        source: Arc<dyn Source>,
+        progress: &mut ProgressCounter,
+        system_data: &mut Self::SystemData,
+    ) -> Result<Self::Result, Error> {
+        #[cfg(feature = "profiler")]
+        profile_scope!("BakedLoad::load");
+
+        let (pages, tiles, atlas) =
+            load_packed(map, Some(name), source, progress, bounds, system_data)?;
+
+        Ok(PagedMap::new(map, bounds, &pages, tiles, atlas))
+    }
+
+    fn add_to_entity(
+        result: &Self::Result,
+        entity: Entity,
+        child_data: &mut ChildData<'a>,
+        spawn_data: &mut Self::SpawnData,
+    ) -> Result<(), Error> {
+        insert_atlas_map(result, entity, child_data, spawn_data)
+    }
+}
+
+/// Hash the contents of a map file, which tells if the layers baked from it are stale
+fn hash_map_file(name: &str, source: &dyn Source) -> Result<u64, Error> {
+    let mut hasher = StableHasher::new();
+    hash_bytes(&source.load(name)?[..], &mut hasher);
+    Ok(hasher.finish())
+}
+
+/// Read the baked tiles of the map file `name`. Nothing is returned when the map was not baked, or
+/// was changed since it was baked, or its baked tiles do not cover the bounds of the map.
+pub(crate) fn load_baked_tiles(
+    name: &str,
+    source: &dyn Source,
+    bounds: &MapBounds,
+) -> Option<BakedTiles> {
+    #[cfg(feature = "profiler")]
+    profile_scope!("load_baked_tiles");
+
+    let path = baked_path(name, TILES_EXTENSION);
+    let tiles = match BakedTiles::read(&mut &source.load(&path).ok()?[..]) {
+        Ok(v) => v,
+        Err(e) => {
+            warn!(
+                "Unable to read baked tiles {:?}, packing instead: {}",
+                path, e
+            );
+            return None;
+        }
+    };
+
+    if hash_map_file(name, source).ok()? != tiles.map_hash {
+        return None;
+    }
+
+    match tiles.origin == (bounds.origin.x, bounds.origin.y)
+        && (tiles.width, tiles.height) == (bounds.width, bounds.height)
+    {
+        true => Some(tiles),
+        false => {
+            warn!("The baked tiles {:?} do not match the map's bounds", path);
+            None
+        }
+    }
+}
+
+/// Load the baked sprite sheet of the map file `name` from its baked tiles. Nothing is returned
+/// when the map was baked from different tilesets.
+pub(crate) fn load_baked_sheet<K: FnOnce() -> Result<u64, Error>>(
+    name: &str,
+    source: &dyn Source,
+    key: K,
+    tiles: &BakedTiles,
+    loader: &Loader,
+    progress: &mut ProgressCounter,
+    storage: &AssetStorage<Texture>,
+) -> Result<Option<PagedSheet>, Error> {
+    #[cfg(feature = "profiler")]
+    profile_scope!("load_baked_sheet");
+
+    if tiles.key != key()? {
+        return Ok(None);
+    }
+
+    let mismatch = || {
+        Error::from_string(format!(
+            "The baked sprite sheet of {:} does not match its tiles",
+            name
+        ))
+    };
+
+    if tiles.pages.len() != tiles.gids.len() + tiles.transposed.len() {
+        warn!(
+            "The baked tiles {:?} do not list a page for every sprite",
+            baked_path(name, TILES_EXTENSION)
+        );
+        return Ok(None);
+    }
+
+    // Read every page before creating any textures in case some of them are missing
+    let page_count = tiles.pages.iter().max().map_or(1, |x| *x as usize + 1);
+    let mut files = Vec::with_capacity(page_count);
+    for page in 0..page_count {
+        let image = source.load(&baked_path(name, &page_extension(ATLAS_EXTENSION, page)));
+        let sprites = source.load(&baked_path(name, &page_extension(SHEET_EXTENSION, page)));
+
+        match (image, sprites) {
+            (Ok(image), Ok(sprites)) => files.push((image, sprites)),
+            _ => return Ok(None),
+        }
+    }
+
+    let mut textures = Vec::with_capacity(page_count);
+    let mut sprites: Vec<Option<Sprite>> = vec![None; tiles.pages.len()];
+
+    for (page, (image, ron)) in files.into_iter().enumerate() {
+        let image = load_from_memory(&image[..])?.to_rgba8();
+        let (width, height) = image.dimensions();
+        let texture = load_texture(image.into_raw(), width, height, loader, progress, storage);
+        let sheet = SpriteSheetFormat(texture).import_simple(ron)?;
+
+        // The sprites of each page are listed in the order of their index in the atlas
+        let slots: Vec<usize> = (0..tiles.pages.len())
+            .filter(|x| tiles.pages[*x] as usize == page)
+            .collect();
+
+        if slots.len() != sheet.sprites.len() {
+            return Err(mismatch());
+        }
+
+        for (slot, sprite) in slots.into_iter().zip(sheet.sprites) {
+            sprites[slot] = Some(sprite);
+        }
+        textures.push(sheet.texture);
+    }
+
+    Ok(Some(PagedSheet {
+        textures,
+        sprites: sprites
+            .into_iter()
+            .collect::<Option<Vec<Sprite>>>()
+            .ok_or_else(mismatch)?,
+        pages: tiles.pages.clone(),
+    }))
+}
+
+/// A map whose tiles have been packed ahead of time
+pub struct BakedMap {
+    pub atlas: PackedAtlas,
+    /// The position of every sprite within its page in the same order as `atlas.sprites`
+    pub anchors: Vec<SpriteAnchor>,
+    pub tiles: BakedTiles,
+}
+
+impl BakedMap {
+    /// Pack the tiles used by a map the same way `CompressedLoad` does. `name` is the map file
+    /// within `source` the map was read from.
+    pub fn bake(
+        name: &str,
+        map: &Map,
+        source: Arc<dyn Source>,
+        settings: PackSettings,
+    ) -> Result<Self, Error> {
+        #[cfg(feature = "profiler")]
+        profile_scope!("bake_map_atlas");
+
+        let frames = collect_frames(map);
+        let usage = collect_gid_usage(map, &frames);
+        let gids: Vec<u32> = usage.gids.into_iter().collect();
+        let transposed: Vec<u32> = usage.transposed.into_iter().collect();
+
+        let sets = map
+            .tilesets
+            .iter()
+            .map(|x| x.unwrap().clone())
+            .collect::<Vec<Tileset>>();
+        let key = atlas_key(ATLAS_KIND, &sets, &*source, &gids, &transposed, settings)?;
+        let map_hash = hash_map_file(name, &*source)?;
+        let packed = pack_sparse_tileset_vec(&sets, source, &gids, &transposed, settings)?;
+
+        let mut anchors: Vec<SpriteAnchor> = packed
+            .iter()
+            .flat_map(|x| encode::<OrderedAnchors>(x, settings))
+            .collect();
+        anchors.sort_by_key(|x| x.id);
+
+        let atlas = PackedAtlas::new(packed, settings, gids, transposed);
+        let tiles = BakedTiles::of(map, key, map_hash, &atlas);
+
+        Ok(Self {
+            atlas,
+            anchors,
+            tiles,
+        })
+    }
+
+    /// Write the image and sprite sheet of every page of the atlas and the tiles next to each
+    /// other, using the given path with the extension of each file
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory)?;
+        }
+
+        for (n, page) in self.atlas.pages.iter().enumerate() {
+            save_buffer(
+                path.with_extension(page_extension(ATLAS_EXTENSION, n)),
+                &page.pixels[..],
+                page.width,
+                page.height,
+                ColorType::Rgba8,
+            )?;
+
+            let sheet = path.with_extension(page_extension(SHEET_EXTENSION, n));
+            let mut writer = BufWriter::new(File::create(sheet)?);
+            self.write_sprite_sheet(n, &mut writer)?;
+            writer.flush()?;
+        }
+
+        let mut writer = BufWriter::new(File::create(path.with_extension(TILES_EXTENSION))?);
+        self.tiles.write(&mut writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Describe the sprites on a page of the atlas as a sprite list which can be loaded by
+    /// `SpriteSheetFormat`
+    pub fn write_sprite_sheet<W: Write>(&self, page: usize, writer: &mut W) -> Result<(), Error> {
+        let texture = &self.atlas.pages[page];
+        writeln!(writer, "List((")?;
+        writeln!(writer, "    texture_width: {},", texture.width)?;
+        writeln!(writer, "    texture_height: {},", texture.height)?;
+        writeln!(writer, "    sprites: [")?;
+
+        let anchors = self
+            .anchors
+            .iter()
+            .zip(&self.atlas.sprite_pages)
+            .filter(|(_, x)| **x as usize == page);
+
+        for (anchor, _) in anchors {
+            let (x, y) = anchor.position;
+            let (width, height) = anchor.dimensions;
+
+            // The offsets match the sprites created by `AmethystOrderedFormat`
+            writeln!(
+                writer,
+                "        (x: {}, y: {}, width: {}, height: {}, offsets: Some((1.0, 1.0))),",
+                x, y, width, height
+            )?;
+        }
+
+        writeln!(writer, "    ],")?;
+        writeln!(writer, "))")?;
+        Ok(())
+    }
+}
+
+/// The raw gids placed in every layer of a map along with the gids which were packed into its
+/// baked atlas and the page of every sprite
+#[derive(Debug, Clone)]
+pub struct BakedTiles {
+    /// The hash of the tiles and tilesets the atlas was packed from, used to tell if it is stale
+    pub key: u64,
+    /// The hash of the map file the layers were read from, used to tell if they are stale
+    pub map_hash: u64,
+    pub gids: Vec<u32>,
+    pub transposed: Vec<u32>,
+    /// The page of the atlas every sprite was packed into
+    pub pages: Vec<u32>,
+    /// The tile coordinate in Tiled of the top left corner of the layers
+    pub origin: (i32, i32),
+    pub width: u32,
+    pub height: u32,
+    /// The index of each layer and its gids, including their flip flags, in rows from the top
+    pub layers: Vec<(u32, Vec<u32>)>,
+}
+
+impl BakedTiles {
+    pub fn of(map: &Map, key: u64, map_hash: u64, atlas: &PackedAtlas) -> Self {
+        let bounds = MapBounds::of(map);
+        let len = bounds.width as usize * bounds.height as usize;
+
+        let layers = map
+            .layers
+            .iter()
+            .map(|layer| {
+                let mut tiles = vec![0; len];
+
+                for (x, y, tile) in layer_tiles(layer) {
+                    if let Some((x, y)) = bounds.to_local(x, y) {
+                        tiles[(y * bounds.width + x) as usize] = tile.gid;
+                    }
+                }
+
+                (layer.layer_index, tiles)
+            })
+            .collect();
+
+        Self {
+            key,
+            map_hash,
+            gids: atlas.gids.clone(),
+            transposed: atlas.transposed.clone(),
+            pages: atlas.sprite_pages.clone(),
+            origin: (bounds.origin.x, bounds.origin.y),
+            width: bounds.width,
+            height: bounds.height,
+            layers,
+        }
+    }
+
+    /// Whether the frames of every animated tile were packed along with it, which is no longer
+    /// the case once animations have been added to a tileset since the map was baked
+    pub(crate) fn packs_frames(&self, frames: &HashMap<u32, Vec<(u32, Duration)>>) -> bool {
+        let packs = |gids: &[u32]| {
+            gids.iter().all(|gid| {
+                frames
+                    .get(gid)
+                    .into_iter()
+                    .flatten()
+                    .all(|(frame, _)| gids.binary_search(frame).is_ok())
+            })
+        };
+
+        packs(&self.gids[..]) && packs(&self.transposed[..])
+    }
+
+    /// The raw gid placed at a position within the bounds of the map
+    pub fn get(&self, layer: usize, x: u32, y: u32) -> Option<u32> {
+        match x < self.width && y < self.height {
+            true => self
+                .layers
+                .get(layer)
+                .map(|(_, tiles)| tiles[(y * self.width + x) as usize]),
+            false => None,
+        }
+    }
+
+    /// Iterate over the position within the bounds of the map and the raw gid of every tile which
+    /// is not empty, where the z coordinate is the index of its layer
+    pub fn tiles(&self) -> impl Iterator<Item = (Point3<u32>, u32)> + '_ {
+        let width = self.width.max(1);
+
+        self.layers.iter().flat_map(move |(index, tiles)| {
+            tiles
+                .iter()
+                .enumerate()
+                .filter(|(_, gid)| **gid != 0)
+                .map(move |(n, gid)| {
+                    let n = n as u32;
+                    (Point3::new(n % width, n / width, *index), *gid)
+                })
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        Self::read(&mut BufReader::new(File::open(path)?))
+    }
+
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        writer.write_all(MAGIC)?;
+        write_u32(writer, VERSION)?;
+        writer.write_all(&self.key.to_le_bytes())?;
+        writer.write_all(&self.map_hash.to_le_bytes())?;
+        write_gids(writer, &self.gids)?;
+        write_gids(writer, &self.transposed)?;
+        write_gids(writer, &self.pages)?;
+
+        write_u32(writer, self.origin.0 as u32)?;
+        write_u32(writer, self.origin.1 as u32)?;
+        write_u32(writer, self.width)?;
+        write_u32(writer, self.height)?;
+
+        write_u32(writer, self.layers.len() as u32)?;
+        for (index, tiles) in &self.layers {
+            write_u32(writer, *index)?;
+            for gid in tiles {
+                write_u32(writer, *gid)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn read<R: Read>(reader: &mut R) -> Result<Self, Error> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+
+        if magic != *MAGIC || read_u32(reader)? != VERSION {
+            return Err(Error::from_string("Not a baked tile map".to_owned()));
+        }
+
+        let mut key = [0; 8];
+        reader.read_exact(&mut key)?;
+        let mut map_hash = [0; 8];
+        reader.read_exact(&mut map_hash)?;
+        let gids = read_gids(reader)?;
+        let transposed = read_gids(reader)?;
+        let pages = read_gids(reader)?;
+
+        let origin = (read_u32(reader)? as i32, read_u32(reader)? as i32);
+        let width = read_u32(reader)?;
+        let height = read_u32(reader)?;
+        let len = width as usize * height as usize;
+
+        let layer_count = read_u32(reader)?;
+        let mut layers = Vec::new();
+        for _ in 0..layer_count {
+            let index = read_u32(reader)?;
+            let tiles = (0..len)
+                .map(|_| read_u32(reader))
+                .collect::<Result<Vec<u32>, Error>>()?;
+            layers.push((index, tiles));
+        }
+
+        Ok(Self {
+            key: u64::from_le_bytes(key),
+            map_hash: u64::from_le_bytes(map_hash),
+            gids,
+            transposed,
+            pages,
+            origin,
+            width,
+            height,
+            layers,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::animation::TileAnimations;
+    use crate::bounds::tile_depth;
+    use crate::cache::AtlasCache;
+    use amethyst::ecs::{SystemData, World, WorldExt};
+    use amethyst::renderer::SpriteSheet;
+    use amethyst::tiles::MapStorage;
+
+    const MAP: &str = "prefab/example_map.tmx";
+    const FILES: [&str; 3] = [
+        MAP,
+        "prefab/example_tileset.tsx",
+        "texture/great_tileset.png",
+    ];
+
+    /// Copy the example map into a new directory and bake it
+    fn baked_directory(name: &str) -> PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("amethyst-tiled-{}-{}", name, std::process::id()));
+        let assets = PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/examples/assets"));
+
+        for file in FILES.iter() {
+            let path = directory.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::copy(assets.join(file), path).unwrap();
+        }
+
+        let (map, source, name) = read_map(&directory.join(MAP)).unwrap();
+        BakedMap::bake(&name, &map, source, PackSettings::default())
+            .unwrap()
+            .save(&directory.join(MAP))
+            .unwrap();
+
+        directory
+    }
+
+    /// Load the map in a directory with `BakedLoad`, giving the loaded map and whether it had to be
+    /// packed
+    fn load_baked(directory: &Path) -> (PagedMap, bool) {
+        let mut world = World::new();
+        let pool = Arc::new(rayon::ThreadPoolBuilder::new().build().unwrap());
+        world.insert(Loader::new(directory, pool));
+        world.insert(AssetStorage::<Texture>::default());
+        world.insert(AssetStorage::<SpriteSheet>::default());
+        world.insert(TileAnimations::default());
+        world.insert(PackSettings::default());
+
+        let cache = AtlasCache::new(directory.join("cache"));
+        world.insert(cache.clone());
+
+        let source: Arc<dyn Source> = Arc::new(Directory::new(directory));
+        let value = <TiledFormat as Format<TileMapPrefab<BakedLoad>>>::import(
+            &TiledFormat,
+            MAP.to_owned(),
+            source.clone(),
+            None,
+        )
+        .unwrap();
+        let map = match value.data {
+            TileMapPrefab::Map(map, ..) => map,
+            TileMapPrefab::Result(..) => unreachable!("Maps are loaded before being processed"),
+        };
+
+        let mut progress = ProgressCounter::new();
+        let mut data = PackData::fetch(&world);
+        let result = <BakedLoad as LoadStrategy>::load_file(
+            MAP,
+            &map,
+            &MapBounds::of(&map),
+            source,
+            &mut progress,
+            &mut data,
+        )
+        .expect("The map should load");
+
+        (result, cache.directory().exists())
+    }
+
+    #[test]
+    fn baked_atlas_is_loaded() {
+        let directory = baked_directory("bake-load");
+
+        let (result, packed) = load_baked(&directory);
+        assert_eq!(result.pages.len(), 1);
+        assert!(!packed);
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn baked_tiles_round_trip() {
+        let directory = baked_directory("bake-tiles");
+        let (map, _, _) = read_map(&directory.join(MAP)).unwrap();
+        let bounds = MapBounds::of(&map);
+
+        let tiles = BakedTiles::load(&directory.join(baked_path(MAP, TILES_EXTENSION))).unwrap();
+        assert_eq!(tiles.origin, (bounds.origin.x, bounds.origin.y));
+        assert_eq!((tiles.width, tiles.height), (bounds.width, bounds.height));
+        assert_eq!(tiles.layers.len(), map.layers.len());
+
+        for (n, layer) in map.layers.iter().enumerate() {
+            assert_eq!(tiles.layers[n].0, layer.layer_index);
+
+            for (x, y, tile) in layer_tiles(layer) {
+                let (x, y) = bounds.to_local(x, y).unwrap();
+                assert_eq!(tiles.get(n, x, y), Some(tile.gid));
+            }
+        }
+        assert_eq!(tiles.get(0, bounds.width, 0), None);
+
+        let mut bytes = Vec::new();
+        tiles.write(&mut bytes).unwrap();
+        let read = BakedTiles::read(&mut &bytes[..]).unwrap();
+        assert_eq!((read.key, read.map_hash), (tiles.key, tiles.map_hash));
+        assert_eq!(read.layers, tiles.layers);
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn baked_layers_match_the_map() {
+        let directory = baked_directory("bake-layers");
+        let (baked, packed) = load_baked(&directory);
+        assert!(!packed);
+
+        // Without its baked tiles the map is packed and its layers are read from the map file
+        fs::remove_file(directory.join(baked_path(MAP, TILES_EXTENSION))).unwrap();
+        let (loaded, packed) = load_baked(&directory);
+        assert!(packed);
+
+        let (map, _, _) = read_map(&directory.join(MAP)).unwrap();
+        let bounds = MapBounds::of(&map);
+        let (baked, loaded) = (&baked.pages[0].0, &loaded.pages[0].0);
+
+        for z in 0..tile_depth(&map) {
+            for y in 0..bounds.height {
+                for x in 0..bounds.width {
+                    let point = Point3::new(x, y, z);
+                    assert_eq!(baked.get(&point), loaded.get(&point));
+                }
+            }
+        }
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn edited_maps_are_packed_again() {
+        let directory = baked_directory("bake-edited");
+        let path = directory.join(MAP);
+
+        let mut bytes = fs::read(&path).unwrap();
+        bytes.extend_from_slice(b"\n");
+        fs::write(&path, bytes).unwrap();
+
+        let (result, packed) = load_baked(&directory);
+        assert_eq!(result.pages.len(), 1);
+        assert!(packed);
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn damaged_baked_tiles_are_packed_again() {
+        let directory = baked_directory("bake-damaged");
+        let tiles = directory.join(baked_path(MAP, TILES_EXTENSION));
+
+        let bytes = fs::read(&tiles).unwrap();
+        fs::write(&tiles, &bytes[..bytes.len() / 2]).unwrap();
+
+        let (result, packed) = load_baked(&directory);
+        assert_eq!(result.pages.len(), 1);
+        assert!(packed);
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
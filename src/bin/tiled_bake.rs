@@ -0,0 +1,80 @@
+//! Bake the atlas of a Tiled map ahead of time.
+//!
+//! Usage: `tiled_bake [--padding <pixels>] [--extrusion <pixels>] [--max-size <pixels>] <map>
+//! [output]`
+//!
+//! The atlas images, sprite sheets and tiles are written next to the map unless an output path is
+//! given, in which case they take its name with the extension of each file. The padding, extrusion
+//! and maximum size must match the `PackSettings` the map is loaded with for the bake to be used.
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::exit;
+
+use amethyst::Error;
+use amethyst_tiled::bake::{read_map, BakedMap};
+use amethyst_tiled::packing::PackSettings;
+
+const USAGE: &str =
+    "Usage: tiled_bake [--padding <pixels>] [--extrusion <pixels>] [--max-size <pixels>] <map> [output]";
+
+fn main() {
+    let mut settings = PackSettings::default();
+    let mut paths = Vec::new();
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            paths.push(PathBuf::from(arg));
+            continue;
+        }
+
+        let value = match args.next().and_then(|x| x.parse().ok()) {
+            Some(v) => v,
+            None => {
+                eprintln!("{} expects a number of pixels\n{}", arg, USAGE);
+                exit(2);
+            }
+        };
+
+        match &arg[..] {
+            "--padding" => settings.padding = value,
+            "--extrusion" => settings.extrusion = value,
+            "--max-size" => settings.max_size = Some(value),
+            _ => {
+                eprintln!("Unknown option {}\n{}", arg, USAGE);
+                exit(2);
+            }
+        }
+    }
+
+    let (map, output) = match &paths[..] {
+        [map] => (map, map),
+        [map, output] => (map, output),
+        _ => {
+            eprintln!("{}", USAGE);
+            exit(2);
+        }
+    };
+
+    if let Err(e) = bake(map, output, settings) {
+        eprintln!("Unable to bake {:?}: {}", map, e);
+        exit(1);
+    }
+}
+
+fn bake(map: &Path, output: &Path, settings: PackSettings) -> Result<(), Error> {
+    let (map, source, name) = read_map(map)?;
+    let baked = BakedMap::bake(&name, &map, source, settings)?;
+    baked.save(output)?;
+
+    println!(
+        "Packed {} sprites into {} atlas pages",
+        baked.atlas.sprites.len(),
+        baked.atlas.pages.len()
+    );
+
+    for (n, page) in baked.atlas.pages.iter().enumerate() {
+        println!("Page {}: {}x{}", n, page.width, page.height);
+    }
+    Ok(())
+}
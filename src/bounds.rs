@@ -0,0 +1,234 @@
+//! Support for the area covered by a map, including infinite maps which store their tiles in chunks
+
+use amethyst::core::math::Point2;
+use amethyst::ecs::{Component, DenseVecStorage};
+use tiled::{Layer, LayerTile, Map};
+
+use crate::orientation::MapOrientation;
+
+#[cfg(feature = "profiler")]
+use thread_profiler::profile_scope;
+
+/// The area of a map which contains tiles. For fixed size maps this is the map itself, while the
+/// chunks of an infinite map may be placed anywhere including negative coordinates. This component
+/// is added to the map entity so tile coordinates from Tiled can be found again.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MapBounds {
+    /// The tile coordinate in Tiled of the top left corner of the bounds
+    pub origin: Point2<i32>,
+    /// The width of the bounds in tiles
+    pub width: u32,
+    /// The height of the bounds in tiles
+    pub height: u32,
+    pub tile_width: u32,
+    pub tile_height: u32,
+    /// How tile coordinates are converted to positions on the screen
+    pub orientation: MapOrientation,
+}
+
+impl Component for MapBounds {
+    type Storage = DenseVecStorage<Self>;
+}
+
+impl MapBounds {
+    /// Find the bounding box of every tile layer in the map
+    pub fn of(map: &Map) -> Self {
+        #[cfg(feature = "profiler")]
+        profile_scope!("find_map_bounds");
+
+        let mut min: Option<(i32, i32)> = None;
+        let mut max = (0, 0);
+
+        let mut include = |x: i32, y: i32, width: usize, height: usize| {
+            if width == 0 || height == 0 {
+                return;
+            }
+
+            let (min_x, min_y) = min.unwrap_or((x, y));
+            min = Some((min_x.min(x), min_y.min(y)));
+            max = (max.0.max(x + width as i32), max.1.max(y + height as i32));
+        };
+
+        for layer in &map.layers {
+            include(0, 0, row_width(&layer.tiles), layer.tiles.len());
+
+            for chunk in &layer.chunks {
+                include(chunk.x, chunk.y, row_width(&chunk.tiles), chunk.tiles.len());
+            }
+        }
+
+        match min {
+            Some((x, y)) => Self {
+                origin: Point2::new(x, y),
+                width: (max.0 - x) as u32,
+                height: (max.1 - y) as u32,
+                tile_width: map.tile_width,
+                tile_height: map.tile_height,
+                orientation: MapOrientation::of(map),
+            },
+            None => Self {
+                origin: Point2::new(0, 0),
+                width: map.width,
+                height: map.height,
+                tile_width: map.tile_width,
+                tile_height: map.tile_height,
+                orientation: MapOrientation::of(map),
+            },
+        }
+    }
+
+    /// Convert a tile coordinate used by Tiled to the matching coordinate within the bounds
+    pub fn to_local(&self, x: i32, y: i32) -> Option<(u32, u32)> {
+        let x = x.checked_sub(self.origin.x)?;
+        let y = y.checked_sub(self.origin.y)?;
+
+        match x >= 0 && y >= 0 && (x as u32) < self.width && (y as u32) < self.height {
+            true => Some((x as u32, y as u32)),
+            false => None,
+        }
+    }
+
+    /// Convert a coordinate within the bounds back to the tile coordinate used by Tiled
+    pub fn to_tiled(&self, x: u32, y: u32) -> (i32, i32) {
+        (self.origin.x + x as i32, self.origin.y + y as i32)
+    }
+
+    pub fn pixel_width(&self) -> u32 {
+        self.pixel_size().0.ceil() as u32
+    }
+
+    pub fn pixel_height(&self) -> u32 {
+        self.pixel_size().1.ceil() as u32
+    }
+}
+
+/// The number of layers a `TileMap` needs to place the tiles of every layer at its `layer_index`.
+/// Object and image layers are counted by `layer_index`, so this can be more than the number of
+/// tile layers.
+pub fn tile_depth(map: &Map) -> u32 {
+    map.layers
+        .iter()
+        .map(|x| x.layer_index + 1)
+        .max()
+        .unwrap_or(0)
+}
+
+fn row_width(rows: &[Vec<LayerTile>]) -> usize {
+    rows.first().map(Vec::len).unwrap_or(0)
+}
+
+/// Iterate over every cell of a layer along with its tile coordinate in Tiled. This covers both
+/// the regular tile data of fixed size maps and the chunks of infinite maps.
+pub fn layer_tiles(layer: &Layer) -> impl Iterator<Item = (i32, i32, &LayerTile)> + '_ {
+    let chunks = layer.chunks.iter().flat_map(|chunk| {
+        chunk.tiles.iter().enumerate().flat_map(move |(y, row)| {
+            row.iter()
+                .enumerate()
+                .map(move |(x, tile)| (chunk.x + x as i32, chunk.y + y as i32, tile))
+        })
+    });
+
+    layer
+        .tiles
+        .iter()
+        .enumerate()
+        .flat_map(|(y, row)| {
+            row.iter()
+                .enumerate()
+                .map(move |(x, tile)| (x as i32, y as i32, tile))
+        })
+        .chain(chunks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tiled::parse;
+
+    fn map(infinite: bool, data: &str) -> Map {
+        let document = format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<map version="1.2" orientation="orthogonal" renderorder="right-down" width="3" height="2" tilewidth="16" tileheight="8" infinite="{}" nextlayerid="2" nextobjectid="1">
+ <layer id="1" name="Ground" width="3" height="2">
+  <data encoding="csv">{}</data>
+ </layer>
+</map>"#,
+            infinite as u8, data
+        );
+
+        parse(document.as_bytes()).unwrap()
+    }
+
+    fn infinite_map() -> Map {
+        map(
+            true,
+            r#"
+   <chunk x="-16" y="-16" width="2" height="2">
+1,2,
+3,4
+</chunk>
+   <chunk x="0" y="16" width="2" height="1">
+5,0
+</chunk>
+  "#,
+        )
+    }
+
+    #[test]
+    fn fixed_size_maps_start_at_the_origin() {
+        let bounds = MapBounds::of(&map(false, "\n1,2,3,\n4,5,6\n"));
+        assert_eq!(bounds.origin, Point2::new(0, 0));
+        assert_eq!((bounds.width, bounds.height), (3, 2));
+        assert_eq!((bounds.tile_width, bounds.tile_height), (16, 8));
+    }
+
+    #[test]
+    fn chunks_with_negative_origins_are_covered() {
+        let bounds = MapBounds::of(&infinite_map());
+        assert_eq!(bounds.origin, Point2::new(-16, -16));
+        assert_eq!((bounds.width, bounds.height), (18, 33));
+
+        assert_eq!(bounds.to_local(-16, -16), Some((0, 0)));
+        assert_eq!(bounds.to_local(1, 16), Some((17, 32)));
+        assert_eq!(bounds.to_tiled(17, 32), (1, 16));
+        assert_eq!(bounds.to_tiled(0, 0), (-16, -16));
+    }
+
+    #[test]
+    fn cells_outside_the_bounds_are_not_local() {
+        let bounds = MapBounds::of(&infinite_map());
+
+        assert_eq!(bounds.to_local(-17, 0), None);
+        assert_eq!(bounds.to_local(0, -17), None);
+        assert_eq!(bounds.to_local(2, 0), None);
+        assert_eq!(bounds.to_local(0, 17), None);
+        assert_eq!(bounds.to_local(i32::MIN, i32::MAX), None);
+    }
+
+    #[test]
+    fn layer_tiles_use_tiled_coordinates() {
+        let map = infinite_map();
+        let tiles: Vec<(i32, i32, u32)> = layer_tiles(&map.layers[0])
+            .map(|(x, y, tile)| (x, y, tile.gid))
+            .collect();
+
+        assert_eq!(
+            tiles,
+            vec![
+                (-16, -16, 1),
+                (-15, -16, 2),
+                (-16, -15, 3),
+                (-15, -15, 4),
+                (0, 16, 5),
+                (1, 16, 0),
+            ]
+        );
+
+        let map = self::map(false, "\n1,2,3,\n4,5,6\n");
+        let tiles: Vec<(i32, i32, u32)> = layer_tiles(&map.layers[0])
+            .map(|(x, y, tile)| (x, y, tile.gid))
+            .collect();
+        assert_eq!(tiles[4], (1, 1, 5));
+        assert_eq!(tiles.len(), 6);
+    }
+}
@@ -0,0 +1,711 @@
+//! On-disk cache of packed sprite sheets, so maps whose tiles and tilesets have not changed can skip
+//! decoding their images and packing them on the next launch
+use std::fs::{self, File};
+use std::hash::Hasher;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use amethyst::assets::{AssetStorage, Loader, ProgressCounter, Source};
+use amethyst::renderer::sprite::{Sprite, TextureCoordinates};
+use amethyst::renderer::{SpriteSheet, Texture};
+use amethyst::Error;
+use log::warn;
+use sheep::{encode, SpriteSheet as PackedSpriteSheet};
+use tiled::Tileset;
+
+use crate::packing::{AmethystOrderedFormat, OrderedAnchors, PackSettings};
+use crate::pages::PagedSheet;
+use crate::{hash_tileset, load_texture};
+
+#[cfg(feature = "profiler")]
+use thread_profiler::profile_scope;
+
+const MAGIC: &[u8; 4] = b"TLAS";
+const VERSION: u32 = 3;
+
+/// A 64 bit FNV-1a hasher which gives the same hash on every platform and with every version of
+/// Rust, so keys written to disk stay valid. Integers are hashed as their little endian bytes and
+/// `usize` is widened to 64 bits. Values should be written explicitly rather than through `Hash`,
+/// whose output for standard types is not guaranteed to stay the same.
+#[derive(Debug, Copy, Clone)]
+pub struct StableHasher(u64);
+
+impl StableHasher {
+    pub fn new() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Default for StableHasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Hasher for StableHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn write_u8(&mut self, i: u8) {
+        self.write(&[i]);
+    }
+
+    fn write_u16(&mut self, i: u16) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        self.write_u64(i as u64);
+    }
+}
+
+/// Hash a list of bytes after its length, so neighbouring lists can not be confused
+pub(crate) fn hash_bytes<H: Hasher>(bytes: &[u8], hasher: &mut H) {
+    hasher.write_u64(bytes.len() as u64);
+    hasher.write(bytes);
+}
+
+fn hash_u32s<H: Hasher>(values: &[u32], hasher: &mut H) {
+    hasher.write_u64(values.len() as u64);
+    for value in values {
+        hasher.write_u32(*value);
+    }
+}
+
+/// Resource which enables caching the atlases packed by `FlatLoad`, `CompressedLoad` and the
+/// strategies built on them in the given directory
+#[derive(Debug, Clone)]
+pub struct AtlasCache {
+    directory: PathBuf,
+}
+
+impl AtlasCache {
+    pub fn new<P: Into<PathBuf>>(directory: P) -> Self {
+        Self {
+            directory: directory.into(),
+        }
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    /// The file an atlas with the given key is stored in
+    pub fn path(&self, key: u64) -> PathBuf {
+        self.directory.join(format!("{:016x}.atlas", key))
+    }
+
+    /// Read a cached atlas. Atlases which are missing or can not be read are treated as not cached.
+    pub fn load(&self, key: u64) -> Option<PackedAtlas> {
+        #[cfg(feature = "profiler")]
+        profile_scope!("load_cached_atlas");
+
+        let file = File::open(self.path(key)).ok()?;
+        let len = file.metadata().ok()?.len();
+        PackedAtlas::read(&mut BufReader::new(file), len).ok()
+    }
+
+    /// Write an atlas to the cache. It is written to a temporary file first, so other processes
+    /// never read an atlas which was only partly written.
+    pub fn store(&self, key: u64, atlas: &PackedAtlas) -> Result<(), Error> {
+        #[cfg(feature = "profiler")]
+        profile_scope!("store_cached_atlas");
+
+        fs::create_dir_all(&self.directory)?;
+
+        let path = self.path(key);
+        let temporary = path.with_extension(format!("atlas.{}.tmp", std::process::id()));
+
+        let written = File::create(&temporary)
+            .map_err(Error::new)
+            .and_then(|file| {
+                let mut writer = BufWriter::new(file);
+                atlas.write(&mut writer)?;
+                writer.flush()?;
+                Ok(())
+            });
+
+        match written {
+            Ok(()) => Ok(fs::rename(&temporary, &path)?),
+            Err(e) => {
+                let _ = fs::remove_file(&temporary);
+                Err(e)
+            }
+        }
+    }
+
+    /// Remove every cached atlas
+    pub fn clear(&self) -> Result<(), Error> {
+        match self.directory.exists() {
+            true => Ok(fs::remove_dir_all(&self.directory)?),
+            false => Ok(()),
+        }
+    }
+}
+
+/// The pages of a packed sprite sheet along with its sprites and the gids they were packed from.
+/// The sprite for `gids[n]` is at index `n`, followed by a diagonally flipped copy of every gid in
+/// `transposed`.
+#[derive(Debug, Clone)]
+pub struct PackedAtlas {
+    pub pages: Vec<AtlasPage>,
+    pub sprites: Vec<Sprite>,
+    /// The page every sprite was packed into
+    pub sprite_pages: Vec<u32>,
+    pub gids: Vec<u32>,
+    pub transposed: Vec<u32>,
+}
+
+/// The rgba8 pixels of a single texture of a packed atlas
+#[derive(Debug, Clone)]
+pub struct AtlasPage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl PackedAtlas {
+    pub fn new(
+        packed: Vec<PackedSpriteSheet>,
+        settings: PackSettings,
+        gids: Vec<u32>,
+        transposed: Vec<u32>,
+    ) -> Self {
+        let mut sprites = Vec::new();
+        let mut pages = Vec::with_capacity(packed.len());
+
+        for (page, packed) in packed.into_iter().enumerate() {
+            let anchors = encode::<OrderedAnchors>(&packed, settings);
+            let page_sprites = encode::<AmethystOrderedFormat>(&packed, settings);
+
+            for (anchor, sprite) in anchors.into_iter().zip(page_sprites) {
+                sprites.push((anchor.id, page as u32, sprite));
+            }
+
+            let (width, height) = packed.dimensions;
+            pages.push(AtlasPage {
+                width,
+                height,
+                pixels: packed.bytes,
+            });
+        }
+
+        // The ids of the anchors are the indices of the sprites given to the packer
+        sprites.sort_by_key(|(id, _, _)| *id);
+
+        Self {
+            pages,
+            sprite_pages: sprites.iter().map(|(_, page, _)| *page).collect(),
+            sprites: sprites.into_iter().map(|(_, _, sprite)| sprite).collect(),
+            gids,
+            transposed,
+        }
+    }
+
+    /// Upload the pixels of every page as a texture
+    pub fn into_pages(
+        self,
+        loader: &Loader,
+        progress: &mut ProgressCounter,
+        storage: &AssetStorage<Texture>,
+    ) -> PagedSheet {
+        let textures = self
+            .pages
+            .into_iter()
+            .map(|page| {
+                load_texture(
+                    page.pixels,
+                    page.width,
+                    page.height,
+                    loader,
+                    progress,
+                    storage,
+                )
+            })
+            .collect();
+
+        PagedSheet {
+            textures,
+            sprites: self.sprites,
+            pages: self.sprite_pages,
+        }
+    }
+
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        writer.write_all(MAGIC)?;
+        write_u32(writer, VERSION)?;
+
+        write_u32(writer, self.pages.len() as u32)?;
+        for page in &self.pages {
+            write_u32(writer, page.width)?;
+            write_u32(writer, page.height)?;
+        }
+
+        write_u32(writer, self.sprites.len() as u32)?;
+        for (sprite, page) in self.sprites.iter().zip(&self.sprite_pages) {
+            let coords = &sprite.tex_coords;
+            let values = [
+                sprite.width,
+                sprite.height,
+                sprite.offsets[0],
+                sprite.offsets[1],
+                coords.left,
+                coords.right,
+                coords.bottom,
+                coords.top,
+            ];
+
+            for value in values.iter() {
+                write_u32(writer, value.to_bits())?;
+            }
+            write_u32(writer, *page)?;
+        }
+
+        write_gids(writer, &self.gids)?;
+        write_gids(writer, &self.transposed)?;
+
+        for page in &self.pages {
+            writer.write_all(&page.pixels[..])?;
+        }
+        Ok(())
+    }
+
+    /// Read an atlas from a file of `len` bytes. The size of every part of the atlas is checked
+    /// against the length before it is read.
+    pub fn read<R: Read>(reader: &mut R, len: u64) -> Result<Self, Error> {
+        let mut remaining = len;
+
+        // The magic number, version and the number of pages
+        take_len(&mut remaining, 3, 4)?;
+
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+
+        if magic != *MAGIC || read_u32(reader)? != VERSION {
+            return Err(Error::from_string("Not a packed atlas".to_owned()));
+        }
+
+        let page_count = read_u32(reader)?;
+        take_len(&mut remaining, page_count as u64, 8)?;
+        let mut pages = Vec::with_capacity(page_count as usize);
+        for _ in 0..page_count {
+            let width = read_u32(reader)?;
+            let height = read_u32(reader)?;
+            pages.push(AtlasPage {
+                width,
+                height,
+                pixels: Vec::new(),
+            });
+        }
+
+        take_len(&mut remaining, 1, 4)?;
+        let sprite_count = read_u32(reader)?;
+        take_len(&mut remaining, sprite_count as u64, 36)?;
+        let mut sprites = Vec::with_capacity(sprite_count as usize);
+        let mut sprite_pages = Vec::with_capacity(sprite_count as usize);
+        for _ in 0..sprite_count {
+            let mut values = [0.0; 8];
+            for value in values.iter_mut() {
+                *value = f32::from_bits(read_u32(reader)?);
+            }
+
+            sprites.push(Sprite {
+                width: values[0],
+                height: values[1],
+                offsets: [values[2], values[3]],
+                tex_coords: TextureCoordinates {
+                    left: values[4],
+                    right: values[5],
+                    bottom: values[6],
+                    top: values[7],
+                },
+            });
+
+            match read_u32(reader)? {
+                page if page < page_count => sprite_pages.push(page),
+                _ => return Err(Error::from_string("Not a packed atlas".to_owned())),
+            }
+        }
+
+        let mut read_sized_gids = |reader: &mut R| {
+            take_len(&mut remaining, 1, 4)?;
+            let len = read_u32(reader)?;
+            take_len(&mut remaining, len as u64, 4)?;
+            (0..len)
+                .map(|_| read_u32(reader))
+                .collect::<Result<Vec<_>, Error>>()
+        };
+        let gids = read_sized_gids(reader)?;
+        let transposed = read_sized_gids(reader)?;
+
+        for page in &mut pages {
+            let size = page.width as u64 * page.height as u64;
+            take_len(&mut remaining, size, 4)?;
+
+            page.pixels = vec![0; size as usize * 4];
+            reader.read_exact(&mut page.pixels[..])?;
+        }
+
+        if remaining != 0 {
+            return Err(Error::from_string(
+                "The packed atlas is followed by unknown data".to_owned(),
+            ));
+        }
+
+        Ok(Self {
+            pages,
+            sprites,
+            sprite_pages,
+            gids,
+            transposed,
+        })
+    }
+}
+
+/// Hash everything used to pack an atlas. `kind` separates strategies which pack the same tiles
+/// differently.
+pub fn atlas_key(
+    kind: &str,
+    sets: &[Tileset],
+    source: &dyn Source,
+    gids: &[u32],
+    transposed: &[u32],
+    settings: PackSettings,
+) -> Result<u64, Error> {
+    #[cfg(feature = "profiler")]
+    profile_scope!("atlas_key");
+
+    let mut hasher = StableHasher::new();
+    hasher.write_u32(VERSION);
+    hash_bytes(kind.as_bytes(), &mut hasher);
+    hash_u32s(gids, &mut hasher);
+    hash_u32s(transposed, &mut hasher);
+    hasher.write_u32(settings.padding);
+    hasher.write_u32(settings.extrusion);
+    match settings.max_size {
+        Some(v) => {
+            hasher.write_u8(1);
+            hasher.write_u32(v);
+        }
+        None => hasher.write_u8(0),
+    }
+
+    hasher.write_u64(sets.len() as u64);
+    for set in sets {
+        hasher.write_u32(set.first_gid);
+        hash_tileset(set, source, &mut hasher)?;
+    }
+
+    Ok(hasher.finish())
+}
+
+/// Load an atlas from the cache when one is given and has a matching entry, otherwise pack it and
+/// store the result. `gids` are the gids which will be packed when they are known up front.
+pub(crate) fn cached_atlas<K, F>(
+    cache: Option<&AtlasCache>,
+    key: K,
+    gids: Option<&[u32]>,
+    transposed: &[u32],
+    pack: F,
+) -> Result<PackedAtlas, Error>
+where
+    K: FnOnce() -> Result<u64, Error>,
+    F: FnOnce() -> Result<PackedAtlas, Error>,
+{
+    let cache = match cache {
+        Some(v) => v,
+        None => return pack(),
+    };
+
+    let key = key()?;
+    if let Some(atlas) = cache.load(key) {
+        // Guard against hash collisions by checking the atlas was packed from the same tiles
+        let matches = match gids {
+            Some(gids) => gids == &atlas.gids[..],
+            None => true,
+        };
+
+        if matches && transposed == &atlas.transposed[..] {
+            return Ok(atlas);
+        }
+    }
+
+    let atlas = pack()?;
+
+    // The atlas is still usable when it can not be cached
+    if let Err(e) = cache.store(key, &atlas) {
+        warn!("Unable to cache packed atlas {:016x}: {}", key, e);
+    }
+
+    Ok(atlas)
+}
+
+/// Take the size of `count` entries of `size` bytes from the number of bytes left in a file,
+/// failing when the file is too short to hold them
+fn take_len(remaining: &mut u64, count: u64, size: u64) -> Result<(), Error> {
+    match count.checked_mul(size).filter(|len| *len <= *remaining) {
+        Some(len) => {
+            *remaining -= len;
+            Ok(())
+        }
+        None => Err(Error::from_string(
+            "The packed atlas is larger than its file".to_owned(),
+        )),
+    }
+}
+
+pub(crate) fn write_u32<W: Write>(writer: &mut W, value: u32) -> Result<(), Error> {
+    writer.write_all(&value.to_le_bytes())?;
+    Ok(())
+}
+
+pub(crate) fn read_u32<R: Read>(reader: &mut R) -> Result<u32, Error> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+pub(crate) fn write_gids<W: Write>(writer: &mut W, gids: &[u32]) -> Result<(), Error> {
+    write_u32(writer, gids.len() as u32)?;
+    for gid in gids {
+        write_u32(writer, *gid)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn read_gids<R: Read>(reader: &mut R) -> Result<Vec<u32>, Error> {
+    let len = read_u32(reader)?;
+    (0..len).map(|_| read_u32(reader)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packing::pack_pages;
+    use image::{GenericImageView, Rgba, RgbaImage};
+    use sheep::InputSprite;
+
+    fn sprite(left: f32, right: f32) -> Sprite {
+        Sprite {
+            width: 2.0,
+            height: 2.0,
+            offsets: [1.0, 1.0],
+            tex_coords: TextureCoordinates {
+                left,
+                right,
+                bottom: 1.0,
+                top: 0.0,
+            },
+        }
+    }
+
+    fn example_atlas() -> PackedAtlas {
+        PackedAtlas {
+            pages: vec![
+                AtlasPage {
+                    width: 4,
+                    height: 2,
+                    pixels: (0..32).collect(),
+                },
+                AtlasPage {
+                    width: 2,
+                    height: 2,
+                    pixels: (100..116).collect(),
+                },
+            ],
+            sprites: vec![sprite(0.0, 0.5), sprite(0.5, 1.0), sprite(0.0, 1.0)],
+            sprite_pages: vec![0, 0, 1],
+            gids: vec![1, 5, 9],
+            transposed: vec![5],
+        }
+    }
+
+    fn write_atlas(atlas: &PackedAtlas) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        atlas.write(&mut bytes).unwrap();
+        bytes
+    }
+
+    fn read_atlas(bytes: &[u8]) -> Result<PackedAtlas, Error> {
+        PackedAtlas::read(&mut &bytes[..], bytes.len() as u64)
+    }
+
+    fn temporary_cache(name: &str) -> AtlasCache {
+        AtlasCache::new(std::env::temp_dir().join(format!(
+            "amethyst-tiled-{}-{}",
+            name,
+            std::process::id()
+        )))
+    }
+
+    fn hash(write: impl Fn(&mut StableHasher)) -> u64 {
+        let mut hasher = StableHasher::new();
+        write(&mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    fn stable_hashes_are_fnv_1a() {
+        assert_eq!(hash(|_| {}), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(hash(|x| x.write(b"a")), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(hash(|x| x.write(b"foobar")), 0x8594_4171_f739_67e8);
+    }
+
+    #[test]
+    fn stable_hashes_use_little_endian_integers() {
+        let bytes = hash(|x| x.write(&[0x78, 0x56, 0x34, 0x12]));
+        assert_eq!(hash(|x| x.write_u32(0x1234_5678)), bytes);
+
+        let bytes = hash(|x| x.write(&7u64.to_le_bytes()));
+        assert_eq!(hash(|x| x.write_usize(7)), bytes);
+    }
+
+    #[test]
+    fn hashed_bytes_are_separated_by_their_length() {
+        let split = |a: &[u8], b: &[u8]| {
+            hash(|x| {
+                hash_bytes(a, x);
+                hash_bytes(b, x);
+            })
+        };
+
+        assert_ne!(split(b"ab", b"c"), split(b"a", b"bc"));
+        assert_eq!(split(b"ab", b"c"), split(b"ab", b"c"));
+    }
+
+    #[test]
+    fn atlas_round_trip() {
+        let atlas = example_atlas();
+        let read = read_atlas(&write_atlas(&atlas)).expect("The atlas should be read");
+
+        assert_eq!(read.gids, atlas.gids);
+        assert_eq!(read.transposed, atlas.transposed);
+        assert_eq!(read.sprite_pages, atlas.sprite_pages);
+        assert_eq!(read.sprites, atlas.sprites);
+        assert_eq!(read.pages.len(), atlas.pages.len());
+
+        for (a, b) in read.pages.iter().zip(&atlas.pages) {
+            assert_eq!((a.width, a.height), (b.width, b.height));
+            assert_eq!(a.pixels, b.pixels);
+        }
+    }
+
+    #[test]
+    fn atlases_not_matching_their_file_are_rejected() {
+        let bytes = write_atlas(&example_atlas());
+
+        assert!(read_atlas(&bytes[..bytes.len() - 1]).is_err());
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(read_atlas(&trailing).is_err());
+        assert!(read_atlas(&bytes[..6]).is_err());
+
+        // A page count far larger than the file is rejected before anything is allocated
+        let mut large = bytes.clone();
+        large[8..12].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(read_atlas(&large).is_err());
+
+        // As is a page with more pixels than the file holds
+        let mut large = bytes;
+        large[12..16].copy_from_slice(&u32::MAX.to_le_bytes());
+        large[16..20].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(read_atlas(&large).is_err());
+    }
+
+    #[test]
+    fn stored_atlases_are_loaded() {
+        let cache = temporary_cache("atlas-cache");
+        let atlas = example_atlas();
+
+        cache.store(7, &atlas).expect("The atlas should be stored");
+        let loaded = cache.load(7).expect("The atlas should be cached");
+        assert_eq!(loaded.gids, atlas.gids);
+        assert!(cache.load(8).is_none());
+
+        // Only the atlas itself is left in the directory
+        let files: Vec<_> = fs::read_dir(cache.directory()).unwrap().collect();
+        assert_eq!(files.len(), 1);
+
+        // Damaged files are treated as not cached
+        let bytes = fs::read(cache.path(7)).unwrap();
+        fs::write(cache.path(7), &bytes[..bytes.len() / 2]).unwrap();
+        assert!(cache.load(7).is_none());
+
+        cache.clear().unwrap();
+    }
+
+    #[test]
+    fn atlases_are_used_when_they_can_not_be_cached() {
+        // The cache directory can not be created where a file already exists
+        let cache = temporary_cache("atlas-cache-file");
+        fs::write(cache.directory(), b"not a directory").unwrap();
+
+        let atlas = cached_atlas(Some(&cache), || Ok(3), None, &[], || Ok(example_atlas()))
+            .expect("The packed atlas should be used");
+        assert_eq!(atlas.gids, example_atlas().gids);
+
+        fs::remove_file(cache.directory()).unwrap();
+    }
+
+    #[test]
+    fn packed_sprites_keep_their_index() {
+        // Sprites of different sizes are reordered by the packer
+        let tiles: Vec<RgbaImage> = (0..8u32)
+            .map(|id| {
+                RgbaImage::from_fn(4 + id % 3 * 4, 4 + id * 2, |x, y| {
+                    Rgba([id as u8, x as u8, y as u8, 255])
+                })
+            })
+            .collect();
+        let sprites: Vec<InputSprite> = tiles
+            .iter()
+            .map(|x| InputSprite {
+                dimensions: x.dimensions(),
+                bytes: x.clone().into_raw(),
+            })
+            .collect();
+
+        for max_size in [None, Some(24)].iter() {
+            let settings = PackSettings {
+                padding: 1,
+                extrusion: 1,
+                max_size: *max_size,
+            };
+            let packed = pack_pages(sprites.clone(), settings).unwrap();
+            let atlas = PackedAtlas::new(packed, settings, Vec::new(), Vec::new());
+
+            assert_eq!(atlas.pages.len() > 1, max_size.is_some());
+            assert_eq!(atlas.sprites.len(), tiles.len());
+
+            for (index, tile) in tiles.iter().enumerate() {
+                let page = &atlas.pages[atlas.sprite_pages[index] as usize];
+                let image = RgbaImage::from_raw(page.width, page.height, page.pixels.clone());
+                let image = image.unwrap();
+
+                let sprite = &atlas.sprites[index];
+                let coords = &sprite.tex_coords;
+                let x = (coords.left.min(coords.right) * page.width as f32).round() as u32;
+                let y = (coords.top.min(coords.bottom) * page.height as f32).round() as u32;
+                let (width, height) = (sprite.width as u32, sprite.height as u32);
+
+                assert_eq!(&image.view(x, y, width, height).to_image(), tile);
+            }
+        }
+    }
+}
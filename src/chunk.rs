@@ -0,0 +1,474 @@
+//! Streaming of large maps in fixed size chunks around the active camera
+
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+use amethyst::assets::{ProgressCounter, Source};
+use amethyst::core::math::{Point3, Vector3};
+use amethyst::core::{Parent, Transform};
+use amethyst::ecs::{
+    Component, DenseVecStorage, Entities, Entity, Join, Read, ReadStorage, System, WriteStorage,
+};
+use amethyst::renderer::camera::{ActiveCamera, Camera};
+use amethyst::tiles::{CoordinateEncoder, FlatEncoder, MapStorage, TileMap};
+use amethyst::Error;
+use tiled::Map;
+
+use crate::bounds::{tile_depth, MapBounds};
+use crate::pages::AtlasPages;
+use crate::prefab::ChildData;
+use crate::strategy::{load_compressed, LoadStrategy, PackData, StrategyDesc};
+use crate::TileGid;
+
+#[cfg(feature = "profiler")]
+use thread_profiler::profile_scope;
+
+/// Resource controlling how maps loaded with `ChunkedLoad` are split and streamed
+#[derive(Debug, Copy, Clone)]
+pub struct ChunkSettings {
+    /// The width and height of each chunk in tiles
+    pub chunk_size: u32,
+    /// Chunks with a center within this distance of the camera are loaded
+    pub load_radius: f32,
+    /// Chunks with a center further than this distance from the camera are unloaded. This should
+    /// be larger than `load_radius` to stop chunks on the edge from being repeatedly recreated.
+    pub unload_radius: f32,
+}
+
+impl Default for ChunkSettings {
+    fn default() -> Self {
+        Self {
+            chunk_size: 32,
+            load_radius: 1024.0,
+            unload_radius: 1536.0,
+        }
+    }
+}
+
+/// A map split into chunks. The tiles of every chunk are kept in memory, but a `TileMap` entity is
+/// only created for the chunks near the active camera by `ChunkStreamingSystem`. Every chunk shares
+/// the same sprite sheet so chunks can be created without repacking any images. When the sheet was
+/// split into pages, a chunk has an entity for every page it has tiles from.
+pub struct ChunkedMap<E: CoordinateEncoder = FlatEncoder> {
+    pages: AtlasPages,
+    bounds: MapBounds,
+    layers: u32,
+    chunk_size: u32,
+    chunks: Arc<HashMap<(u32, u32), Vec<TileGid>>>,
+    loaded: HashMap<(u32, u32), Vec<Entity>>,
+    _encoder: PhantomData<E>,
+}
+
+impl<E: CoordinateEncoder> Clone for ChunkedMap<E> {
+    fn clone(&self) -> Self {
+        Self {
+            pages: self.pages.clone(),
+            bounds: self.bounds,
+            layers: self.layers,
+            chunk_size: self.chunk_size,
+            chunks: self.chunks.clone(),
+            loaded: self.loaded.clone(),
+            _encoder: PhantomData,
+        }
+    }
+}
+
+impl<E: CoordinateEncoder> Component for ChunkedMap<E> {
+    type Storage = DenseVecStorage<Self>;
+}
+
+impl<E: CoordinateEncoder> ChunkedMap<E> {
+    pub fn chunk_size(&self) -> u32 {
+        self.chunk_size
+    }
+
+    pub fn bounds(&self) -> &MapBounds {
+        &self.bounds
+    }
+
+    /// The entities of the chunks which are currently loaded
+    pub fn loaded(&self) -> &HashMap<(u32, u32), Vec<Entity>> {
+        &self.loaded
+    }
+
+    /// The position of a chunk's center relative to the map entity
+    pub fn chunk_center(&self, (x, y): (u32, u32)) -> Vector3<f32> {
+        let size = self.chunk_size as f32;
+        let tile_width = self.bounds.tile_width as f32;
+        let tile_height = self.bounds.tile_height as f32;
+
+        Vector3::new(
+            (x as f32 + 0.5) * size * tile_width - self.bounds.pixel_width() as f32 / 2.0,
+            self.bounds.pixel_height() as f32 / 2.0 - (y as f32 + 0.5) * size * tile_height,
+            0.0,
+        )
+    }
+
+    /// Find the chunks containing tiles with a center within the given distance of a position
+    /// relative to the map entity.
+    pub fn chunks_within(&self, position: &Vector3<f32>, radius: f32) -> Vec<(u32, u32)> {
+        let columns = (self.bounds.width + self.chunk_size - 1) / self.chunk_size;
+        let rows = (self.bounds.height + self.chunk_size - 1) / self.chunk_size;
+
+        let mut chunks = Vec::new();
+        for y in 0..rows {
+            for x in 0..columns {
+                if !self.chunks.contains_key(&(x, y)) {
+                    continue;
+                }
+
+                let offset = self.chunk_center((x, y)) - position;
+                if offset.xy().norm() <= radius {
+                    chunks.push((x, y));
+                }
+            }
+        }
+
+        chunks
+    }
+
+    /// Create the tile maps used to display a chunk, one for every page it has tiles from
+    fn create_tilemaps(&self, chunk: (u32, u32)) -> Vec<TileMap<TileGid, E>> {
+        let size = self.chunk_size;
+        let tiles = match self.chunks.get(&chunk) {
+            Some(v) => v,
+            None => return Vec::new(),
+        };
+
+        let mut tilemaps: Vec<Option<TileMap<TileGid, E>>> =
+            (0..self.pages.len()).map(|_| None).collect();
+
+        for z in 0..self.layers {
+            for y in 0..size {
+                for x in 0..size {
+                    let tile = tiles[((z * size + y) * size + x) as usize];
+                    if tile == TileGid::default() {
+                        continue;
+                    }
+
+                    let page = self.pages.page_of(tile.sprite_index());
+                    let tilemap = tilemaps[page].get_or_insert_with(|| {
+                        TileMap::new(
+                            Vector3::new(size, size, self.layers),
+                            Vector3::new(self.bounds.tile_width, self.bounds.tile_height, 1),
+                            Some(self.pages.sheets()[page].clone()),
+                        )
+                    });
+
+                    if let Some(v) = tilemap.get_mut(&Point3::new(x, y, z)) {
+                        *v = tile;
+                    }
+                }
+            }
+        }
+
+        tilemaps.into_iter().flatten().collect()
+    }
+}
+
+/// A version of CompressedLoad which splits the map into chunks of `ChunkSettings::chunk_size`
+/// tiles. Only the chunks near the camera are displayed, so `ChunkStreamingSystem` must be added to
+/// the dispatcher for anything to be shown.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct ChunkedLoad<E: CoordinateEncoder = FlatEncoder>(PhantomData<E>);
+
+impl<E: CoordinateEncoder> StrategyDesc for ChunkedLoad<E> {
+    type Result = ChunkedMap<E>;
+}
+
+impl<'a, E: CoordinateEncoder> LoadStrategy<'a> for ChunkedLoad<E> {
+    type SystemData = (PackData<'a>, Read<'a, ChunkSettings>);
+    type SpawnData = WriteStorage<'a, ChunkedMap<E>>;
+
+    fn load(
+        map: &Map,
+        bounds: &MapBounds,
+        source: Arc<dyn Source>,
+        progress: &mut ProgressCounter,
+        system_data: &mut Self::SystemData,
+    ) -> Result<Self::Result, Error> {
+        #[cfg(feature = "profiler")]
+        profile_scope!("ChunkedLoad::load");
+
+        let (pack_data, settings) = system_data;
+        let size = settings.chunk_size.max(1);
+        // Tiles are placed at their layer index, which also counts object and image layers
+        let layers = tile_depth(map);
+
+        let (pages, tiles, _) = load_compressed(map, source, progress, bounds, pack_data)?;
+
+        let mut chunks = HashMap::new();
+        for (point, tile) in tiles {
+            // Chunks without any tiles are never created
+            if tile == TileGid::default() {
+                continue;
+            }
+
+            let chunk = chunks
+                .entry((point.x / size, point.y / size))
+                .or_insert_with(|| vec![TileGid::default(); (size * size * layers) as usize]);
+
+            let (x, y) = (point.x % size, point.y % size);
+            chunk[((point.z * size + y) * size + x) as usize] = tile;
+        }
+
+        Ok(ChunkedMap {
+            pages,
+            bounds: *bounds,
+            layers,
+            chunk_size: size,
+            chunks: Arc::new(chunks),
+            loaded: HashMap::new(),
+            _encoder: PhantomData,
+        })
+    }
+
+    fn add_to_entity(
+        result: &Self::Result,
+        entity: Entity,
+        _child_data: &mut ChildData<'a>,
+        spawn_data: &mut Self::SpawnData,
+    ) -> Result<(), Error> {
+        spawn_data.insert(entity, result.clone())?;
+        Ok(())
+    }
+}
+
+/// System which creates and removes the chunk entities of every `ChunkedMap` depending on their
+/// distance from the active camera.
+#[derive(Debug, Default)]
+pub struct ChunkStreamingSystem<E: CoordinateEncoder = FlatEncoder>(PhantomData<E>);
+
+impl<'a, E: CoordinateEncoder> System<'a> for ChunkStreamingSystem<E> {
+    type SystemData = (
+        Entities<'a>,
+        Read<'a, ActiveCamera>,
+        Read<'a, ChunkSettings>,
+        ReadStorage<'a, Camera>,
+        WriteStorage<'a, ChunkedMap<E>>,
+        WriteStorage<'a, TileMap<TileGid, E>>,
+        WriteStorage<'a, Transform>,
+        WriteStorage<'a, Parent>,
+    );
+
+    fn run(&mut self, data: Self::SystemData) {
+        #[cfg(feature = "profiler")]
+        profile_scope!("chunk_streaming_system");
+
+        let (
+            entities,
+            active,
+            settings,
+            cameras,
+            mut maps,
+            mut tilemaps,
+            mut transforms,
+            mut parents,
+        ) = data;
+
+        let camera = active.entity.or_else(|| {
+            (&entities, &cameras)
+                .join()
+                .next()
+                .map(|(entity, _)| entity)
+        });
+
+        let camera_position = match camera.and_then(|x| transforms.get(x)) {
+            Some(v) => v.global_matrix().transform_point(&Point3::origin()),
+            None => return,
+        };
+
+        for (entity, map) in (&entities, &mut maps).join() {
+            // Find the camera relative to the map so moving or scaling the map is accounted for
+            let position = match transforms.get(entity) {
+                Some(v) => match v.global_matrix().try_inverse() {
+                    Some(inverse) => inverse.transform_point(&camera_position).coords,
+                    None => continue,
+                },
+                None => camera_position.coords,
+            };
+
+            let unload: Vec<(u32, u32)> = map
+                .loaded
+                .keys()
+                .filter(|x| (map.chunk_center(**x) - position).xy().norm() > settings.unload_radius)
+                .copied()
+                .collect();
+
+            for chunk in unload {
+                for chunk_entity in map.loaded.remove(&chunk).into_iter().flatten() {
+                    // The chunk may have already been removed along with the rest of the scene
+                    let _ = entities.delete(chunk_entity);
+                }
+            }
+
+            for chunk in map.chunks_within(&position, settings.load_radius) {
+                if map.loaded.contains_key(&chunk) {
+                    continue;
+                }
+
+                let mut transform = Transform::default();
+                transform.set_translation(map.chunk_center(chunk));
+
+                let chunk_entities = map
+                    .create_tilemaps(chunk)
+                    .into_iter()
+                    .map(|tilemap| {
+                        entities
+                            .build_entity()
+                            .with(tilemap, &mut tilemaps)
+                            .with(transform.clone(), &mut transforms)
+                            .with(Parent::new(entity), &mut parents)
+                            .build()
+                    })
+                    .collect();
+
+                map.loaded.insert(chunk, chunk_entities);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::animation::TileAnimations;
+    use crate::packing::PackSettings;
+    use crate::prefab::TileMapPrefab;
+    use crate::TiledFormat;
+    use amethyst::assets::{AssetStorage, Directory, Format, Loader};
+    use amethyst::ecs::{SystemData, World, WorldExt};
+    use amethyst::renderer::{SpriteSheet, Texture};
+    use std::fs;
+    use std::path::PathBuf;
+
+    /// A 4x3 map whose tile layers follow an object group, with tiles in the top left and bottom
+    /// right chunks when split into chunks of 2x2 tiles
+    const MAP: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<map version="1.2" orientation="orthogonal" renderorder="right-down" width="4" height="3" tilewidth="16" tileheight="16" infinite="0" nextlayerid="4" nextobjectid="1">
+ <tileset firstgid="1" source="example_tileset.tsx"/>
+ <objectgroup id="1" name="Objects"/>
+ <layer id="2" name="Ground" width="4" height="3">
+  <data encoding="csv">
+1,0,0,0,
+0,0,0,0,
+0,0,0,2
+</data>
+ </layer>
+ <layer id="3" name="Top" width="4" height="3">
+  <data encoding="csv">
+0,3,0,0,
+0,0,0,0,
+0,0,0,0
+</data>
+ </layer>
+</map>
+"#;
+
+    fn load_chunked(name: &str) -> ChunkedMap {
+        let directory =
+            std::env::temp_dir().join(format!("amethyst-tiled-{}-{}", name, std::process::id()));
+        let assets = PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/examples/assets"));
+
+        for file in ["prefab/example_tileset.tsx", "texture/great_tileset.png"].iter() {
+            let path = directory.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::copy(assets.join(file), path).unwrap();
+        }
+        fs::write(directory.join("prefab/chunks.tmx"), MAP).unwrap();
+
+        let mut world = World::new();
+        let pool = Arc::new(rayon::ThreadPoolBuilder::new().build().unwrap());
+        world.insert(Loader::new(&directory, pool));
+        world.insert(AssetStorage::<Texture>::default());
+        world.insert(AssetStorage::<SpriteSheet>::default());
+        world.insert(TileAnimations::default());
+        world.insert(PackSettings::default());
+        world.insert(ChunkSettings {
+            chunk_size: 2,
+            ..ChunkSettings::default()
+        });
+
+        let source: Arc<dyn Source> = Arc::new(Directory::new(&directory));
+        let value = <TiledFormat as Format<TileMapPrefab<ChunkedLoad>>>::import(
+            &TiledFormat,
+            "prefab/chunks.tmx".to_owned(),
+            source.clone(),
+            None,
+        )
+        .unwrap();
+        let map = match value.data {
+            TileMapPrefab::Map(map, ..) => map,
+            TileMapPrefab::Result(..) => unreachable!("Maps are loaded before being processed"),
+        };
+        assert_eq!(map.layers[0].layer_index, 1);
+
+        let mut progress = ProgressCounter::new();
+        let mut data = <(PackData<'_>, Read<'_, ChunkSettings>) as SystemData>::fetch(&world);
+        let chunked = <ChunkedLoad as LoadStrategy>::load(
+            &map,
+            &MapBounds::of(&map),
+            source,
+            &mut progress,
+            &mut data,
+        )
+        .expect("Maps with layers before their tile layers should load");
+
+        fs::remove_dir_all(directory).unwrap();
+        chunked
+    }
+
+    #[test]
+    fn tiles_are_placed_at_their_layer_index() {
+        let map = load_chunked("chunk-layers");
+        assert_eq!(map.layers, 3);
+
+        let tilemaps = map.create_tilemaps((0, 0));
+        assert_eq!(tilemaps.len(), 1);
+
+        let placed = |x, y, z| *tilemaps[0].get(&Point3::new(x, y, z)).unwrap();
+        assert_ne!(placed(0, 0, 1), TileGid::default());
+        assert_ne!(placed(1, 0, 2), TileGid::default());
+        assert_eq!(placed(1, 0, 1), TileGid::default());
+        assert_eq!(placed(0, 0, 2), TileGid::default());
+
+        let tilemaps = map.create_tilemaps((1, 1));
+        assert_eq!(tilemaps.len(), 1);
+        assert_ne!(
+            *tilemaps[0].get(&Point3::new(1, 0, 1)).unwrap(),
+            TileGid::default()
+        );
+
+        // Chunks without tiles have nothing to show
+        assert!(map.create_tilemaps((1, 0)).is_empty());
+        assert!(map.create_tilemaps((5, 5)).is_empty());
+    }
+
+    #[test]
+    fn chunk_centers_are_relative_to_the_map_center() {
+        let map = load_chunked("chunk-centers");
+
+        // The map is 64x48 pixels and every chunk covers 32x32 pixels
+        assert_eq!(map.chunk_center((0, 0)), Vector3::new(-16.0, 8.0, 0.0));
+        assert_eq!(map.chunk_center((1, 1)), Vector3::new(16.0, -24.0, 0.0));
+        assert_eq!(map.chunk_center((1, 0)), Vector3::new(16.0, 8.0, 0.0));
+    }
+
+    #[test]
+    fn only_chunks_with_tiles_are_within_range() {
+        let map = load_chunked("chunk-range");
+
+        let top_left = Vector3::new(-16.0, 8.0, 0.0);
+        assert_eq!(map.chunks_within(&top_left, 1.0), vec![(0, 0)]);
+        assert_eq!(map.chunks_within(&top_left, 100.0), vec![(0, 0), (1, 1)]);
+
+        // The empty chunk at (1, 0) is the only one within range
+        let top_right = Vector3::new(16.0, 8.0, 0.0);
+        assert!(map.chunks_within(&top_right, 1.0).is_empty());
+
+        // Distance along z is ignored
+        let above = Vector3::new(16.0, -24.0, 500.0);
+        assert_eq!(map.chunks_within(&above, 1.0), vec![(1, 1)]);
+    }
+}
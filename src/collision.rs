@@ -0,0 +1,666 @@
+//! Collision shapes drawn on tiles with Tiled's collision editor
+
+use std::collections::{HashMap, HashSet};
+
+use amethyst::core::math::{Matrix4, Point2, Point3, Vector2};
+use amethyst::ecs::{Component, DenseVecStorage};
+use tiled::{Map, Object, ObjectShape, PropertyValue, Tileset};
+
+use crate::bounds::{layer_tiles, MapBounds};
+use crate::Flip;
+
+#[cfg(feature = "profiler")]
+use thread_profiler::profile_scope;
+
+/// Rectangles which are transformed into something other than a rectangle become polygons, while
+/// corners within this distance of each other are treated as aligned.
+const ALIGNMENT_EPSILON: f32 = 1e-4;
+
+/// A collision shape. Tile shapes are given in the pixel coordinates of the tile image with the y
+/// axis pointing down, while the shapes of a placed map use the y up space of the map entity.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CollisionShape {
+    /// An axis aligned rectangle. Rotated rectangles are stored as polygons.
+    Rect { min: Point2<f32>, max: Point2<f32> },
+    /// An ellipse where `rotation` is the angle in radians from the x axis to the first radius
+    Ellipse {
+        center: Point2<f32>,
+        radii: Vector2<f32>,
+        rotation: f32,
+    },
+    /// A closed polygon
+    Polygon(Vec<Point2<f32>>),
+    /// An open line made of connected segments
+    Polyline(Vec<Point2<f32>>),
+}
+
+impl CollisionShape {
+    /// Read the shape of an object from a tile's object group. Point objects have no area and are
+    /// skipped.
+    pub fn from_object(object: &Object) -> Option<Self> {
+        let origin = Point2::new(object.x, object.y);
+        let (sin, cos) = object.rotation.to_radians().sin_cos();
+
+        // Tiled rotates objects clockwise around their position
+        let rotate = |x: f32, y: f32| origin + Vector2::new(x * cos - y * sin, x * sin + y * cos);
+
+        let shape = match &object.shape {
+            ObjectShape::Rect { width, height } if object.rotation == 0.0 => CollisionShape::Rect {
+                min: origin,
+                max: origin + Vector2::new(*width, *height),
+            },
+            ObjectShape::Rect { width, height } => CollisionShape::Polygon(vec![
+                rotate(0.0, 0.0),
+                rotate(*width, 0.0),
+                rotate(*width, *height),
+                rotate(0.0, *height),
+            ]),
+            ObjectShape::Ellipse { width, height } => CollisionShape::Ellipse {
+                center: rotate(width / 2.0, height / 2.0),
+                radii: Vector2::new(width / 2.0, height / 2.0),
+                rotation: object.rotation.to_radians(),
+            },
+            ObjectShape::Polygon { points } => {
+                CollisionShape::Polygon(points.iter().map(|(x, y)| rotate(*x, *y)).collect())
+            }
+            ObjectShape::Polyline { points } => {
+                CollisionShape::Polyline(points.iter().map(|(x, y)| rotate(*x, *y)).collect())
+            }
+            ObjectShape::Point(..) => return None,
+        };
+
+        Some(shape)
+    }
+
+    /// Apply a transformation to every point of the shape. Rectangles stay rectangles as long as
+    /// they remain axis aligned and ellipses are fitted to the transformed radii.
+    pub fn transform<F: Fn(Point2<f32>) -> Point2<f32>>(&self, f: F) -> Self {
+        match self {
+            CollisionShape::Rect { min, max } => {
+                let corners = [
+                    f(*min),
+                    f(Point2::new(max.x, min.y)),
+                    f(*max),
+                    f(Point2::new(min.x, max.y)),
+                ];
+
+                let aligned = |a: f32, b: f32| (a - b).abs() <= ALIGNMENT_EPSILON;
+                let [a, b, c, d] = corners;
+
+                if (aligned(a.x, d.x) && aligned(b.x, c.x) && aligned(a.y, b.y))
+                    || (aligned(a.x, b.x) && aligned(c.x, d.x) && aligned(a.y, d.y))
+                {
+                    CollisionShape::Rect {
+                        min: Point2::new(a.x.min(c.x), a.y.min(c.y)),
+                        max: Point2::new(a.x.max(c.x), a.y.max(c.y)),
+                    }
+                } else {
+                    CollisionShape::Polygon(corners.to_vec())
+                }
+            }
+            CollisionShape::Ellipse {
+                center,
+                radii,
+                rotation,
+            } => {
+                let (sin, cos) = rotation.sin_cos();
+                let new_center = f(*center);
+                let axis_x = f(center + Vector2::new(cos, sin) * radii.x) - new_center;
+                let axis_y = f(center + Vector2::new(-sin, cos) * radii.y) - new_center;
+
+                CollisionShape::Ellipse {
+                    center: new_center,
+                    radii: Vector2::new(axis_x.norm(), axis_y.norm()),
+                    rotation: axis_x.y.atan2(axis_x.x),
+                }
+            }
+            CollisionShape::Polygon(points) => {
+                CollisionShape::Polygon(points.iter().map(|x| f(*x)).collect())
+            }
+            CollisionShape::Polyline(points) => {
+                CollisionShape::Polyline(points.iter().map(|x| f(*x)).collect())
+            }
+        }
+    }
+
+    /// Apply a flip to a shape given in the pixel coordinates of a tile with the given size. The
+    /// diagonal flip is applied first, followed by the horizontal and vertical flips.
+    pub fn flip(&self, flip: Flip, width: f32, height: f32) -> Self {
+        if !flip.is_flipped() {
+            return self.clone();
+        }
+
+        // A diagonal flip swaps the axes, so the tile is flipped within its transposed size
+        let (width, height) = match flip.diagonal {
+            true => (height, width),
+            false => (width, height),
+        };
+
+        self.transform(|point| {
+            let mut point = match flip.diagonal {
+                true => Point2::new(point.y, point.x),
+                false => point,
+            };
+
+            if flip.horizontal {
+                point.x = width - point.x;
+            }
+
+            if flip.vertical {
+                point.y = height - point.y;
+            }
+
+            point
+        })
+    }
+}
+
+/// The collision shapes of a single tile along with the size of the tile image they are placed on
+#[derive(Debug, Clone, Default)]
+pub struct TileShapes {
+    pub width: u32,
+    pub height: u32,
+    pub shapes: Vec<CollisionShape>,
+}
+
+impl TileShapes {
+    /// The shapes of the tile after it has been flipped
+    pub fn flipped(&self, flip: Flip) -> Vec<CollisionShape> {
+        let (width, height) = (self.width as f32, self.height as f32);
+        self.shapes
+            .iter()
+            .map(|x| x.flip(flip, width, height))
+            .collect()
+    }
+}
+
+/// Collect the collision shapes of every tile in a tileset which has any, keyed by gid
+pub fn tileset_shapes(set: &Tileset) -> HashMap<u32, TileShapes> {
+    let mut shapes = HashMap::new();
+
+    for tile in &set.tiles {
+        let group = match &tile.objectgroup {
+            Some(v) => v,
+            None => continue,
+        };
+
+        let tile_shapes: Vec<CollisionShape> = group
+            .objects
+            .iter()
+            .filter_map(CollisionShape::from_object)
+            .collect();
+
+        if !tile_shapes.is_empty() {
+            shapes.insert(
+                set.first_gid + tile.id,
+                TileShapes {
+                    width: set.tile_width,
+                    height: set.tile_height,
+                    shapes: tile_shapes,
+                },
+            );
+        }
+    }
+
+    shapes
+}
+
+/// A collision shape of a tile placed in a map
+#[derive(Debug, Clone)]
+pub struct PlacedShape {
+    /// The tile coordinate in Tiled along with the index of the layer
+    pub tile: Point3<i32>,
+    pub gid: u32,
+    pub shape: CollisionShape,
+}
+
+/// Resource choosing which cells are merged into the solid rectangles of `MapCollision`
+#[derive(Debug, Clone)]
+pub enum SolidCells {
+    /// Cells holding a tile with this boolean property set to true, or any tile of a layer with
+    /// the property set to true
+    Property(String),
+    /// Cells holding any tile in the layer with this name
+    Layer(String),
+}
+
+impl Default for SolidCells {
+    fn default() -> Self {
+        SolidCells::Property("solid".to_owned())
+    }
+}
+
+fn is_true(value: Option<&PropertyValue>) -> bool {
+    matches!(value, Some(PropertyValue::BoolValue(true)))
+}
+
+/// A grid marking which cells of a map are solid
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SolidGrid {
+    width: u32,
+    height: u32,
+    cells: Vec<bool>,
+}
+
+impl SolidGrid {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            cells: vec![false; (width * height) as usize],
+        }
+    }
+
+    /// Mark the solid cells of a map. Coordinates are relative to the origin of the bounds.
+    pub fn of(map: &Map, bounds: &MapBounds, solid: &SolidCells) -> Self {
+        #[cfg(feature = "profiler")]
+        profile_scope!("find_solid_cells");
+
+        let mut grid = Self::new(bounds.width, bounds.height);
+
+        let mut solid_gids = HashSet::new();
+        if let SolidCells::Property(name) = solid {
+            for set in &map.tilesets {
+                let set = set.unwrap();
+                for tile in &set.tiles {
+                    if is_true(tile.properties.get(name)) {
+                        solid_gids.insert(set.first_gid + tile.id);
+                    }
+                }
+            }
+        }
+
+        for layer in &map.layers {
+            let whole_layer = match solid {
+                SolidCells::Property(name) => is_true(layer.properties.get(name)),
+                SolidCells::Layer(name) => &layer.name == name,
+            };
+
+            if !whole_layer && solid_gids.is_empty() {
+                continue;
+            }
+
+            for (x, y, tile) in layer_tiles(layer) {
+                let (gid, _) = Flip::decode(tile.gid);
+                if gid == 0 || !(whole_layer || solid_gids.contains(&gid)) {
+                    continue;
+                }
+
+                if let Some((x, y)) = bounds.to_local(x, y) {
+                    grid.set(x, y, true);
+                }
+            }
+        }
+
+        grid
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> bool {
+        x < self.width && y < self.height && self.cells[(y * self.width + x) as usize]
+    }
+
+    pub fn set(&mut self, x: u32, y: u32, solid: bool) {
+        assert!(
+            x < self.width && y < self.height,
+            "Cell is outside of the grid"
+        );
+        self.cells[(y * self.width + x) as usize] = solid;
+    }
+}
+
+/// A rectangle of cells measured in tiles
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct CellRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Greedily merge the solid cells of a grid into rectangles. Starting from the top left, each
+/// rectangle is grown as far as possible along its row and then down for as long as every cell
+/// below it is solid. Every solid cell is covered by exactly one rectangle.
+pub fn merge_cells(grid: &SolidGrid) -> Vec<CellRect> {
+    #[cfg(feature = "profiler")]
+    profile_scope!("merge_solid_cells");
+
+    let mut used = vec![false; grid.cells.len()];
+    let mut rects = Vec::new();
+
+    let free =
+        |used: &[bool], x: u32, y: u32| grid.get(x, y) && !used[(y * grid.width + x) as usize];
+
+    for y in 0..grid.height {
+        let mut x = 0;
+        while x < grid.width {
+            if !free(&used, x, y) {
+                x += 1;
+                continue;
+            }
+
+            let mut width = 1;
+            while x + width < grid.width && free(&used, x + width, y) {
+                width += 1;
+            }
+
+            let mut height = 1;
+            while y + height < grid.height
+                && (x..x + width).all(|cell| free(&used, cell, y + height))
+            {
+                height += 1;
+            }
+
+            for cell_y in y..y + height {
+                for cell_x in x..x + width {
+                    used[(cell_y * grid.width + cell_x) as usize] = true;
+                }
+            }
+
+            rects.push(CellRect {
+                x,
+                y,
+                width,
+                height,
+            });
+            x += width;
+        }
+    }
+
+    rects
+}
+
+/// Component added to the map entity holding the collision shapes of every placed tile in the
+/// local space of the map entity along with the merged rectangles of its solid cells. Maps without
+/// any collision shapes or solid cells do not get this component.
+#[derive(Debug, Clone, Default)]
+pub struct MapCollision {
+    tiles: HashMap<u32, TileShapes>,
+    shapes: Vec<PlacedShape>,
+    solid: Vec<CollisionShape>,
+}
+
+impl Component for MapCollision {
+    type Storage = DenseVecStorage<Self>;
+}
+
+impl MapCollision {
+    pub fn new(map: &Map, bounds: &MapBounds, solid: &SolidCells) -> Self {
+        #[cfg(feature = "profiler")]
+        profile_scope!("collect_collision_shapes");
+
+        let mut tiles = HashMap::new();
+        for set in &map.tilesets {
+            tiles.extend(tileset_shapes(set.unwrap()));
+        }
+
+        let mut shapes = Vec::new();
+        for (index, layer) in map.layers.iter().enumerate() {
+            if tiles.is_empty() {
+                break;
+            }
+
+            for (x, y, tile) in layer_tiles(layer) {
+                let (gid, flip) = Flip::decode(tile.gid);
+                let tile_shapes = match tiles.get(&gid) {
+                    Some(v) => v,
+                    None => continue,
+                };
+
+                // Tile images are aligned to the bottom left corner of their cell
+                let (left, top) = bounds.tile_to_pixel(x, y);
+                let image_height = match flip.diagonal {
+                    true => tile_shapes.width,
+                    false => tile_shapes.height,
+                } as f32;
+                let left = left + layer.offset_x;
+                let top = top + layer.offset_y + bounds.tile_height as f32 - image_height;
+
+                for shape in tile_shapes.flipped(flip) {
+                    shapes.push(PlacedShape {
+                        tile: Point3::new(x, y, index as i32),
+                        gid,
+                        shape: shape.transform(|point| {
+                            let local = bounds.local_to_world(left + point.x, top + point.y);
+                            Point2::new(local.x, local.y)
+                        }),
+                    });
+                }
+            }
+        }
+
+        let (tile_width, tile_height) = (bounds.tile_width as f32, bounds.tile_height as f32);
+        let solid = merge_cells(&SolidGrid::of(map, bounds, solid))
+            .into_iter()
+            .map(|rect| {
+                let cells = CollisionShape::Rect {
+                    min: Point2::new(rect.x as f32, rect.y as f32),
+                    max: Point2::new((rect.x + rect.width) as f32, (rect.y + rect.height) as f32),
+                };
+
+                cells.transform(|point| {
+                    let local = bounds.local_to_world(point.x * tile_width, point.y * tile_height);
+                    Point2::new(local.x, local.y)
+                })
+            })
+            .collect();
+
+        Self {
+            tiles,
+            shapes,
+            solid,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shapes.is_empty() && self.solid.is_empty()
+    }
+
+    /// The collision shapes of a tile in the pixel coordinates of its image, before any flips
+    pub fn tile_shapes(&self, gid: u32) -> Option<&TileShapes> {
+        self.tiles.get(&gid)
+    }
+
+    /// The shapes of every placed tile relative to the map entity
+    pub fn shapes(&self) -> &[PlacedShape] {
+        &self.shapes
+    }
+
+    /// The shapes of every placed tile in world space, given the global matrix of the map entity's
+    /// `Transform`
+    pub fn world_shapes(&self, global_matrix: &Matrix4<f32>) -> Vec<PlacedShape> {
+        #[cfg(feature = "profiler")]
+        profile_scope!("world_collision_shapes");
+
+        self.shapes
+            .iter()
+            .map(|placed| PlacedShape {
+                shape: placed.shape.transform(|point| {
+                    let world = global_matrix.transform_point(&Point3::new(point.x, point.y, 0.0));
+                    Point2::new(world.x, world.y)
+                }),
+                ..placed.clone()
+            })
+            .collect()
+    }
+
+    /// The merged rectangles covering the solid cells of the map relative to the map entity. The
+    /// rectangles follow the tile grid, so they only line up with orthogonal maps.
+    pub fn solid_rects(&self) -> &[CollisionShape] {
+        &self.solid
+    }
+
+    /// The merged rectangles of the solid cells in world space, given the global matrix of the map
+    /// entity's `Transform`
+    pub fn world_solid_rects(&self, global_matrix: &Matrix4<f32>) -> Vec<CollisionShape> {
+        self.solid
+            .iter()
+            .map(|shape| {
+                shape.transform(|point| {
+                    let world = global_matrix.transform_point(&Point3::new(point.x, point.y, 0.0));
+                    Point2::new(world.x, world.y)
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build a grid from rows where `#` marks a solid cell
+    fn grid(rows: &[&str]) -> SolidGrid {
+        let width = rows.first().map(|x| x.len()).unwrap_or(0) as u32;
+        let mut grid = SolidGrid::new(width, rows.len() as u32);
+
+        for (y, row) in rows.iter().enumerate() {
+            for (x, cell) in row.chars().enumerate() {
+                grid.set(x as u32, y as u32, cell == '#');
+            }
+        }
+
+        grid
+    }
+
+    fn rect(x: u32, y: u32, width: u32, height: u32) -> CellRect {
+        CellRect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// Check every solid cell is covered by exactly one rectangle and no empty cell is covered
+    fn assert_exact_cover(grid: &SolidGrid, rects: &[CellRect]) {
+        for y in 0..grid.height() {
+            for x in 0..grid.width() {
+                let covering = rects
+                    .iter()
+                    .filter(|r| x >= r.x && x < r.x + r.width && y >= r.y && y < r.y + r.height)
+                    .count();
+
+                match grid.get(x, y) {
+                    true => assert_eq!(covering, 1, "cell ({}, {}) should be covered once", x, y),
+                    false => assert_eq!(covering, 0, "cell ({}, {}) should be empty", x, y),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn empty_grid_has_no_rects() {
+        let grid = grid(&["....", "....", "...."]);
+        assert!(merge_cells(&grid).is_empty());
+        assert!(merge_cells(&SolidGrid::new(0, 0)).is_empty());
+    }
+
+    #[test]
+    fn full_grid_is_one_rect() {
+        let grid = grid(&["####", "####", "####"]);
+        assert_eq!(merge_cells(&grid), vec![rect(0, 0, 4, 3)]);
+    }
+
+    #[test]
+    fn single_cells_stay_separate() {
+        let grid = grid(&["#.#", "...", "#.#"]);
+        let rects = merge_cells(&grid);
+
+        assert_eq!(rects.len(), 4);
+        assert_exact_cover(&grid, &rects);
+    }
+
+    #[test]
+    fn rows_merge_into_columns() {
+        let grid = grid(&["##..", "##..", "####"]);
+        assert_eq!(merge_cells(&grid), vec![rect(0, 0, 2, 3), rect(2, 2, 2, 1)]);
+    }
+
+    #[test]
+    fn l_shape_uses_two_rects() {
+        let grid = grid(&["#...", "#...", "####"]);
+        let rects = merge_cells(&grid);
+
+        assert_eq!(rects, vec![rect(0, 0, 1, 3), rect(1, 2, 3, 1)]);
+        assert_exact_cover(&grid, &rects);
+    }
+
+    #[test]
+    fn room_walls() {
+        let grid = grid(&[
+            "######", //
+            "#....#", //
+            "#....#", //
+            "######", //
+        ]);
+        let rects = merge_cells(&grid);
+
+        assert_eq!(rects.len(), 4);
+        assert_exact_cover(&grid, &rects);
+    }
+
+    #[test]
+    fn checkerboard_covers_every_cell() {
+        let grid = grid(&["#.#.#", ".#.#.", "#.#.#", ".#.#."]);
+        let rects = merge_cells(&grid);
+
+        assert_eq!(rects.len(), 10);
+        assert!(rects.iter().all(|r| r.width == 1 && r.height == 1));
+        assert_exact_cover(&grid, &rects);
+    }
+
+    #[test]
+    fn irregular_shape_is_covered_exactly() {
+        let grid = grid(&[
+            "..###...#", //
+            ".#####..#", //
+            "###.###.#", //
+            "#########", //
+            "....##...", //
+        ]);
+
+        assert_exact_cover(&grid, &merge_cells(&grid));
+    }
+
+    #[test]
+    fn flip_rect_within_tile() {
+        let shape = CollisionShape::Rect {
+            min: Point2::new(0.0, 0.0),
+            max: Point2::new(4.0, 2.0),
+        };
+
+        let flip = Flip {
+            horizontal: true,
+            vertical: false,
+            diagonal: false,
+        };
+        assert_eq!(
+            shape.flip(flip, 16.0, 16.0),
+            CollisionShape::Rect {
+                min: Point2::new(12.0, 0.0),
+                max: Point2::new(16.0, 2.0),
+            }
+        );
+
+        let flip = Flip {
+            horizontal: false,
+            vertical: false,
+            diagonal: true,
+        };
+        assert_eq!(
+            shape.flip(flip, 16.0, 16.0),
+            CollisionShape::Rect {
+                min: Point2::new(0.0, 0.0),
+                max: Point2::new(2.0, 4.0),
+            }
+        );
+    }
+}
@@ -0,0 +1,849 @@
+//! Placing tiles at runtime in maps loaded with `FlatLoad` or `CompressedLoad`
+
+use std::collections::HashMap;
+use std::fmt;
+use std::iter::once;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use amethyst::assets::{AssetStorage, Handle, Loader, ProgressCounter, Source};
+use amethyst::core::math::Point3;
+use amethyst::ecs::{Entity, Read, ReadExpect, Write, WriteStorage};
+use amethyst::renderer::{sprite::Sprite, SpriteSheet, Texture};
+use amethyst::tiles::{CoordinateEncoder, MapStorage, TileMap};
+use amethyst::Error;
+use sheep::{encode, InputSprite};
+use tiled::Tileset;
+
+use crate::animation::TileAnimations;
+use crate::navigation::NavigationGrids;
+use crate::packing::{
+    mirror_sprite, pack_sprites, AmethystOrderedFormat, FlipVariants, PackSettings, TileImages,
+};
+use crate::strategy::{AtlasData, TileAtlas, TileResolver};
+use crate::{load_texture, Flip, TileGid};
+
+#[cfg(feature = "profiler")]
+use thread_profiler::profile_scope;
+
+/// The data used to add tiles to the sprite sheet of a map after it has been loaded
+pub type SheetData<'a> = (
+    ReadExpect<'a, Loader>,
+    Read<'a, AssetStorage<Texture>>,
+    Write<'a, AssetStorage<SpriteSheet>>,
+    Write<'a, TileAnimations>,
+);
+
+/// The data used to edit the tiles of a map loaded with `FlatLoad` or `CompressedLoad`, along with
+/// the navigation grids which are updated with the edited tiles
+pub type EditData<'a, E> = (SheetData<'a>, AtlasData<'a, E>, Write<'a, NavigationGrids>);
+
+/// A sprite of a packed sprite sheet which has its own pixels rather than mirroring another sprite
+#[derive(Debug, Copy, Clone)]
+struct PackedSprite {
+    index: usize,
+    /// The gid of the tile, where 0 is the see through placeholder
+    gid: u32,
+    transposed: bool,
+}
+
+impl PackedSprite {
+    fn pixels(&self, tiles: &TileImages, tile_size: (u32, u32)) -> Result<InputSprite, Error> {
+        if self.gid == 0 {
+            return Ok(InputSprite {
+                bytes: vec![0; (tile_size.0 * tile_size.1 * 4) as usize],
+                dimensions: tile_size,
+            });
+        }
+
+        let flip = Flip {
+            diagonal: self.transposed,
+            ..Flip::NONE
+        };
+
+        match tiles.tile(self.gid, flip) {
+            Some(image) => Ok(InputSprite {
+                dimensions: image.dimensions(),
+                bytes: image.into_raw(),
+            }),
+            None => Err(Error::from_string(format!(
+                "Unable to find tile with gid: {:}",
+                self.gid
+            ))),
+        }
+    }
+}
+
+/// The tile size of the tileset which owns a gid. The see through placeholder has no tileset and
+/// is sized like the tiles of the first tileset, as it was when the sheet was first packed.
+fn tile_size(sets: &[Tileset], gid: u32) -> (u32, u32) {
+    let set = match gid {
+        0 => sets.first(),
+        _ => sets
+            .iter()
+            .filter(|x| x.first_gid <= gid)
+            .max_by_key(|x| x.first_gid)
+            .or_else(|| sets.first()),
+    };
+
+    set.map(|x| (x.tile_width, x.tile_height)).unwrap_or((0, 0))
+}
+
+/// Everything needed to rebuild the sprite sheet of a map with extra tiles while keeping the
+/// sprite index of every tile which was already packed, so tiles already placed in the map and
+/// their animations stay valid.
+pub(crate) struct AtlasLayout {
+    sheet: Handle<SpriteSheet>,
+    sets: Vec<Tileset>,
+    source: Arc<dyn Source>,
+    frames: HashMap<u32, Vec<(u32, Duration)>>,
+    /// The sprite index of every unflipped tile which has been packed
+    indices: HashMap<u32, usize>,
+    pixels: Vec<PackedSprite>,
+    variants: FlipVariants,
+    /// The padding the sheet was packed with
+    settings: PackSettings,
+    /// The decoded images of the tilesets, loaded the first time the sheet is repacked and kept
+    /// for later repacks
+    images: Option<TileImages>,
+}
+
+impl fmt::Debug for AtlasLayout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AtlasLayout")
+            .field("sheet", &self.sheet)
+            .field("indices", &self.indices)
+            .field("pixels", &self.pixels)
+            .field("variants", &self.variants)
+            .field("settings", &self.settings)
+            .finish()
+    }
+}
+
+impl AtlasLayout {
+    /// Describe a sprite sheet where the tile `gids[n]` was packed at sprite index `n` and any
+    /// other sprites were added through `variants`
+    pub(crate) fn new(
+        sheet: Handle<SpriteSheet>,
+        sets: Vec<Tileset>,
+        source: Arc<dyn Source>,
+        frames: HashMap<u32, Vec<(u32, Duration)>>,
+        gids: &[u32],
+        variants: FlipVariants,
+    ) -> Self {
+        let mut pixels: Vec<PackedSprite> = gids
+            .iter()
+            .enumerate()
+            .map(|(index, gid)| PackedSprite {
+                index,
+                gid: *gid,
+                transposed: false,
+            })
+            .chain(variants.transposed().map(|(base, index)| PackedSprite {
+                index,
+                gid: gids[base],
+                transposed: true,
+            }))
+            .collect();
+        pixels.sort_by_key(|x| x.index);
+
+        Self {
+            sheet,
+            sets,
+            source,
+            frames,
+            indices: gids.iter().enumerate().map(|(n, gid)| (*gid, n)).collect(),
+            pixels,
+            variants,
+            settings: PackSettings::default(),
+            images: None,
+        }
+    }
+
+    /// Use the padding a sheet was packed with when it is packed again
+    pub(crate) fn with_settings(mut self, settings: PackSettings) -> Self {
+        self.settings = settings;
+        self
+    }
+
+    /// Find the sprites which must be added to the sheet before the given tiles can be drawn.
+    /// `len` is the current number of sprites in the sheet.
+    fn missing_sprites(&self, tiles: &[(u32, Flip)], len: usize) -> Vec<PackedSprite> {
+        let mut added: Vec<PackedSprite> = Vec::new();
+
+        for (gid, flip) in tiles {
+            let frames = self.frames.get(gid).into_iter().flatten();
+
+            for gid in once(*gid).chain(frames.map(|(frame, _)| *frame)) {
+                let is_added = |added: &[PackedSprite], transposed: bool| {
+                    added
+                        .iter()
+                        .any(|x| x.gid == gid && x.transposed == transposed)
+                };
+
+                if !self.indices.contains_key(&gid) && !is_added(&added, false) {
+                    added.push(PackedSprite {
+                        index: len + added.len(),
+                        gid,
+                        transposed: false,
+                    });
+                }
+
+                let transposed = match self.indices.get(&gid) {
+                    Some(index) => self.variants.is_transposed(*index),
+                    None => false,
+                };
+
+                if flip.diagonal && !transposed && !is_added(&added, true) {
+                    added.push(PackedSprite {
+                        index: len + added.len(),
+                        gid,
+                        transposed: true,
+                    });
+                }
+            }
+        }
+
+        added
+    }
+
+    /// Pack every sprite with its own pixels again along with the new sprites and update the
+    /// sprites and texture of the sheet. Mirrored sprites are recreated from their new source.
+    fn repack(
+        &mut self,
+        added: Vec<PackedSprite>,
+        sheet: &mut SpriteSheet,
+        loader: &Loader,
+        textures: &AssetStorage<Texture>,
+    ) -> Result<(), Error> {
+        #[cfg(feature = "profiler")]
+        profile_scope!("repack_tile_atlas");
+
+        let (packed, sprites) = self.pack_sheet(added, sheet.sprites.len())?;
+
+        let (width, height) = packed.dimensions;
+        let mut progress = ProgressCounter::new();
+        sheet.texture = load_texture(packed.bytes, width, height, loader, &mut progress, textures);
+        sheet.sprites = sprites;
+
+        Ok(())
+    }
+
+    /// Pack the image of a sheet with `len` sprites along with the new sprites, giving the packed
+    /// image and the sprites of every index in the sheet
+    fn pack_sheet(
+        &mut self,
+        added: Vec<PackedSprite>,
+        len: usize,
+    ) -> Result<(sheep::SpriteSheet, Vec<Sprite>), Error> {
+        if self.images.is_none() {
+            self.images = Some(TileImages::load(&self.sets[..], self.source.clone())?);
+        }
+
+        let tiles = match &self.images {
+            Some(v) => v,
+            None => unreachable!("The tileset images should have been loaded"),
+        };
+
+        let inputs = self
+            .pixels
+            .iter()
+            .chain(&added)
+            .map(|x| x.pixels(tiles, tile_size(&self.sets[..], x.gid)))
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        let packed = pack_sprites(inputs, self.settings);
+
+        // Atlases are only extended while they fit in a single page
+        if let Some(max_size) = self.settings.max_size {
+            let (width, height) = packed.dimensions;
+
+            if width > max_size || height > max_size {
+                return Err(Error::from_string(format!(
+                    "Unable to add tiles without the sprite sheet exceeding {}x{} pixels",
+                    max_size, max_size
+                )));
+            }
+        }
+
+        let packed_sprites = encode::<AmethystOrderedFormat>(&packed, self.settings);
+
+        for sprite in &added {
+            match sprite.transposed {
+                true => self
+                    .variants
+                    .transpose(self.indices[&sprite.gid], sprite.index),
+                false => {
+                    self.indices.insert(sprite.gid, sprite.index);
+                }
+            }
+        }
+
+        let mut sprites: Vec<Option<Sprite>> = vec![None; len + added.len()];
+        self.pixels.extend(added);
+
+        for (sprite, origin) in packed_sprites.into_iter().zip(&self.pixels) {
+            sprites[origin.index] = Some(sprite);
+        }
+
+        for (index, source, flip) in self.variants.mirrors() {
+            let sprite = match &sprites[source] {
+                Some(v) => mirror_sprite(v, flip),
+                None => unreachable!("Mirrored sprites should always have a packed source"),
+            };
+            sprites[index] = Some(sprite);
+        }
+
+        let sprites = sprites
+            .into_iter()
+            .map(|x| x.expect("Every sprite in the sheet should have an origin"))
+            .collect();
+
+        Ok((packed, sprites))
+    }
+}
+
+impl TileAtlas {
+    pub(crate) fn with_layout(mut self, layout: AtlasLayout) -> Self {
+        self.layout = Some(Arc::new(Mutex::new(layout)));
+        self
+    }
+
+    /// Find the gid from Tiled, including its flip flags, of a tile placed in the map
+    pub fn gid_of(&self, tile: &TileGid) -> Option<u32> {
+        match *tile == TileGid::default() {
+            true => Some(0),
+            false => self.iter().find(|(_, x)| x == tile).map(|(gid, _)| gid),
+        }
+    }
+
+    /// Make sure every gid, including its flip flags, can be placed in the map. The sprite sheet is
+    /// repacked once if any tiles were never packed, which replaces its texture so the map is not
+    /// drawn until the new texture has loaded. Orientations of packed tiles which have not been
+    /// used yet only add sprites to the sheet.
+    ///
+    /// Every copy of the atlas created from the same map shares its sprite sheet, so tiles packed
+    /// through one copy can be used by the others.
+    pub fn pack(&mut self, gids: &[u32], data: &mut SheetData<'_>) -> Result<(), Error> {
+        #[cfg(feature = "profiler")]
+        profile_scope!("pack_atlas_tiles");
+
+        let mut missing: Vec<(u32, Flip)> = gids
+            .iter()
+            .filter(|x| !self.contains(**x))
+            .map(|x| Flip::decode(*x))
+            .collect();
+        missing.sort_by_key(|(gid, flip)| flip.encode(*gid));
+        missing.dedup();
+
+        if missing.is_empty() {
+            return Ok(());
+        }
+
+        let layout = match &self.layout {
+            Some(v) => v.clone(),
+            None => {
+                return Err(Error::from_string(
+                    "The map was not loaded with a sprite sheet which can be extended".to_owned(),
+                ))
+            }
+        };
+
+        let mut layout = layout.lock().unwrap();
+        let (loader, textures, sheets, animations) = data;
+
+        let sheet = match sheets.get_mut(&layout.sheet) {
+            Some(v) => v,
+            None => {
+                return Err(Error::from_string(
+                    "Unable to add tiles before the map's sprite sheet has loaded".to_owned(),
+                ))
+            }
+        };
+
+        let added = layout.missing_sprites(&missing[..], sheet.sprites.len());
+        if !added.is_empty() {
+            layout.repack(added, sheet, loader, textures)?;
+        }
+
+        let AtlasLayout {
+            frames,
+            indices,
+            variants,
+            ..
+        } = &mut *layout;
+
+        let mut resolver = TileResolver {
+            sprites: &mut sheet.sprites,
+            variants,
+            frames,
+            animations,
+            atlas: TileAtlas {
+                tiles: std::mem::take(&mut self.tiles),
+                ..TileAtlas::default()
+            },
+        };
+
+        let index_of = |gid: u32| indices.get(&gid).copied();
+        for (gid, flip) in missing {
+            resolver.resolve(&index_of, gid, flip);
+        }
+
+        self.tiles = resolver.atlas.tiles;
+        animations.set_owners(self.animations(), &[layout.sheet.clone()]);
+        Ok(())
+    }
+}
+
+/// Place a tile in the `TileMap` of a map entity using its gid from Tiled, including its flip
+/// flags. The z coordinate of the position is the index of the layer within the map.
+pub fn set_tile<E: CoordinateEncoder>(
+    data: &mut EditData<'_, E>,
+    map: Entity,
+    position: Point3<u32>,
+    gid: u32,
+) -> Result<(), Error> {
+    set_tiles(data, map, &[(position, gid)])
+}
+
+/// Place several tiles at once, adding any tiles which were never packed to the map's sprite sheet
+/// with a single repack. When the sheet was split into pages, each tile is placed in the page it
+/// was packed into and tiles which were never packed can not be added.
+pub fn set_tiles<E: CoordinateEncoder>(
+    data: &mut EditData<'_, E>,
+    map: Entity,
+    tiles: &[(Point3<u32>, u32)],
+) -> Result<(), Error> {
+    #[cfg(feature = "profiler")]
+    profile_scope!("set_tiles");
+
+    let (sheet_data, (tilemaps, atlases), grids) = data;
+
+    let pages = atlases
+        .get(map)
+        .map(|x| x.pages.clone())
+        .unwrap_or_default();
+    match pages.is_empty() {
+        true => set_packed_tiles(sheet_data, tilemaps, atlases, map, tiles)?,
+        false => set_paged_tiles(tilemaps, atlases, map, pages, tiles)?,
+    }
+
+    // Keep the walkability of the edited cells up to date
+    if let Some(grid) = grids.get_mut(map) {
+        for (position, gid) in tiles {
+            grid.place_tile(position, *gid);
+        }
+    }
+
+    Ok(())
+}
+
+/// Place tiles in a map with a single page, packing any tiles which are missing from its sheet
+fn set_packed_tiles<E: CoordinateEncoder>(
+    sheet_data: &mut SheetData<'_>,
+    tilemaps: &mut WriteStorage<'_, TileMap<TileGid, E>>,
+    atlases: &mut WriteStorage<'_, TileAtlas>,
+    map: Entity,
+    tiles: &[(Point3<u32>, u32)],
+) -> Result<(), Error> {
+    let (tilemap, atlas) = match (tilemaps.get_mut(map), atlases.get_mut(map)) {
+        (Some(tilemap), Some(atlas)) => (tilemap, atlas),
+        _ => {
+            return Err(Error::from_string(format!(
+                "Unable to find a tile map with an atlas on entity: {:?}",
+                map
+            )))
+        }
+    };
+
+    if let Some((position, _)) = tiles.iter().find(|(x, _)| tilemap.get(x).is_none()) {
+        return Err(Error::from_string(format!(
+            "Unable to find cell {:?} in the tile map",
+            position
+        )));
+    }
+
+    let gids: Vec<u32> = tiles.iter().map(|(_, gid)| *gid).collect();
+    atlas.pack(&gids[..], sheet_data)?;
+
+    for (position, gid) in tiles {
+        let tile = match atlas.get(*gid) {
+            Some(v) => v,
+            None => unreachable!("Every tile should have been packed"),
+        };
+
+        if let Some(v) = tilemap.get_mut(position) {
+            *v = tile;
+        }
+    }
+
+    Ok(())
+}
+
+/// Place tiles in a map whose sprite sheet was split into pages. Each tile is placed in the
+/// `TileMap` of the page it was packed into and removed from the other pages.
+fn set_paged_tiles<E: CoordinateEncoder>(
+    tilemaps: &mut WriteStorage<'_, TileMap<TileGid, E>>,
+    atlases: &WriteStorage<'_, TileAtlas>,
+    map: Entity,
+    pages: Vec<Entity>,
+    tiles: &[(Point3<u32>, u32)],
+) -> Result<(), Error> {
+    let pages: Vec<Entity> = once(map).chain(pages).collect();
+
+    let mut placed = Vec::with_capacity(tiles.len());
+    for (position, gid) in tiles {
+        if tilemaps.get(map).and_then(|x| x.get(position)).is_none() {
+            return Err(Error::from_string(format!(
+                "Unable to find cell {:?} in the tile map",
+                position
+            )));
+        }
+
+        let found = pages.iter().enumerate().find_map(|(page, entity)| {
+            atlases
+                .get(*entity)
+                .and_then(|atlas| atlas.get(*gid))
+                .map(|tile| (page, tile))
+        });
+
+        match found {
+            Some((page, tile)) => placed.push((position, page, tile)),
+            None => {
+                return Err(Error::from_string(format!(
+                    "Unable to add tile {} to a map whose sprite sheet was split into pages",
+                    gid
+                )))
+            }
+        }
+    }
+
+    for (position, page, tile) in placed {
+        for (n, entity) in pages.iter().enumerate() {
+            if let Some(v) = tilemaps.get_mut(*entity).and_then(|x| x.get_mut(position)) {
+                *v = match n == page {
+                    true => tile,
+                    false => TileGid::default(),
+                };
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bounds::MapBounds;
+    use crate::navigation::{NavGrid, PathOptions};
+    use crate::prefab::TileMapPrefab;
+    use crate::strategy::CompressedLoad;
+    use crate::TiledFormat;
+    use amethyst::assets::{Directory, Format};
+    use amethyst::core::math::Vector3;
+    use amethyst::ecs::{Builder, SystemData, World, WorldExt};
+    use amethyst::tiles::FlatEncoder;
+    use image::{GenericImageView, RgbaImage};
+    use tiled::parse;
+
+    fn example_layout(gids: &[u32], frames: HashMap<u32, Vec<(u32, Duration)>>) -> AtlasLayout {
+        let source: Arc<dyn Source> = Arc::new(Directory::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/examples/assets"
+        )));
+
+        let value = <TiledFormat as Format<TileMapPrefab<CompressedLoad>>>::import(
+            &TiledFormat,
+            "prefab/example_map.tmx".to_owned(),
+            source.clone(),
+            None,
+        )
+        .expect("The example map should load");
+
+        let sets = match value.data {
+            TileMapPrefab::Map(map, ..) => map.tilesets,
+            TileMapPrefab::Result(..) => unreachable!("Maps are loaded before being processed"),
+        };
+
+        let sheet = AssetStorage::<SpriteSheet>::default().allocate();
+        let variants = FlipVariants::new(&[], gids.len());
+        AtlasLayout::new(sheet, sets, source, frames, gids, variants)
+    }
+
+    /// The pixels a sprite covers in a packed image
+    fn sprite_pixels(packed: &sheep::SpriteSheet, sprite: &Sprite) -> RgbaImage {
+        let (width, height) = packed.dimensions;
+        let image = RgbaImage::from_raw(width, height, packed.bytes.clone()).unwrap();
+
+        let coords = &sprite.tex_coords;
+        let x = (coords.left.min(coords.right) * width as f32).round() as u32;
+        let y = (coords.top.min(coords.bottom) * height as f32).round() as u32;
+
+        image
+            .view(x, y, sprite.width as u32, sprite.height as u32)
+            .to_image()
+    }
+
+    fn added(sprites: &[PackedSprite]) -> Vec<(usize, u32, bool)> {
+        sprites
+            .iter()
+            .map(|x| (x.index, x.gid, x.transposed))
+            .collect()
+    }
+
+    #[test]
+    fn missing_sprites_only_adds_unpacked_tiles() {
+        let frames = vec![(
+            9,
+            vec![
+                (10, Duration::from_millis(100)),
+                (11, Duration::from_millis(100)),
+            ],
+        )]
+        .into_iter()
+        .collect();
+        let layout = example_layout(&[0, 5, 7], frames);
+
+        let diagonal = Flip {
+            diagonal: true,
+            ..Flip::NONE
+        };
+        let tiles = [
+            (5, Flip::NONE),
+            (8, Flip::NONE),
+            (5, diagonal),
+            (9, Flip::NONE),
+        ];
+
+        assert_eq!(
+            added(&layout.missing_sprites(&tiles, 3)),
+            vec![
+                (3, 8, false),
+                (4, 5, true),
+                (5, 9, false),
+                (6, 10, false),
+                (7, 11, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_sprites_reuses_packed_and_added_sprites() {
+        let mut layout = example_layout(&[0, 5], HashMap::new());
+        layout.variants.transpose(1, 2);
+
+        let diagonal = Flip {
+            diagonal: true,
+            ..Flip::NONE
+        };
+        let tiles = [(5, diagonal), (8, diagonal), (8, Flip::NONE), (8, diagonal)];
+
+        assert_eq!(
+            added(&layout.missing_sprites(&tiles, 3)),
+            vec![(3, 8, false), (4, 8, true)]
+        );
+        assert!(layout.missing_sprites(&[(5, Flip::NONE)], 3).is_empty());
+    }
+
+    #[test]
+    fn repacking_keeps_sprite_indices() {
+        let gids = [12281, 1, 130, 2500];
+        let mut layout = example_layout(&gids, HashMap::new());
+        let tiles = TileImages::load(&layout.sets[..], layout.source.clone()).unwrap();
+
+        assert!(layout.images.is_none());
+        let (packed, sprites) = layout.pack_sheet(Vec::new(), gids.len()).unwrap();
+        assert!(layout.images.is_some());
+        let before: Vec<RgbaImage> = sprites.iter().map(|x| sprite_pixels(&packed, x)).collect();
+
+        let diagonal = Flip {
+            diagonal: true,
+            ..Flip::NONE
+        };
+        let added = layout.missing_sprites(&[(3000, Flip::NONE), (130, diagonal)], gids.len());
+        let (packed, sprites) = layout.pack_sheet(added, gids.len()).unwrap();
+        assert_eq!(sprites.len(), gids.len() + 2);
+
+        for (index, gid) in gids.iter().enumerate() {
+            let pixels = sprite_pixels(&packed, &sprites[index]);
+            let tile = tiles.tile(*gid, Flip::NONE).unwrap();
+
+            assert_eq!(pixels.as_raw(), before[index].as_raw());
+            assert_eq!(pixels.as_raw(), tile.as_raw());
+        }
+
+        let tile = tiles.tile(3000, Flip::NONE).unwrap();
+        assert_eq!(layout.indices[&3000], gids.len());
+        assert_eq!(sprite_pixels(&packed, &sprites[4]).as_raw(), tile.as_raw());
+
+        let tile = tiles.tile(130, diagonal).unwrap();
+        assert!(layout.variants.is_transposed(2));
+        assert_eq!(sprite_pixels(&packed, &sprites[5]).as_raw(), tile.as_raw());
+    }
+
+    #[test]
+    fn tiles_are_sized_by_their_own_tileset() {
+        let document = r#"<?xml version="1.0" encoding="UTF-8"?>
+<map version="1.2" orientation="orthogonal" renderorder="right-down" width="1" height="1" tilewidth="16" tileheight="16" infinite="0" nextlayerid="2" nextobjectid="1">
+ <tileset firstgid="1" name="small" tilewidth="16" tileheight="16" tilecount="4" columns="2">
+  <image source="small.png" width="32" height="32"/>
+ </tileset>
+ <tileset firstgid="5" name="large" tilewidth="32" tileheight="48" tilecount="2" columns="2">
+  <image source="large.png" width="64" height="48"/>
+ </tileset>
+ <layer id="1" name="Ground" width="1" height="1">
+  <data encoding="csv">0</data>
+ </layer>
+</map>"#;
+        let sets = parse(document.as_bytes()).unwrap().tilesets;
+
+        assert_eq!(tile_size(&sets[..], 0), (16, 16));
+        assert_eq!(tile_size(&sets[..], 4), (16, 16));
+        assert_eq!(tile_size(&sets[..], 5), (32, 48));
+        assert_eq!(tile_size(&sets[..], 6), (32, 48));
+        assert_eq!(tile_size(&[], 1), (0, 0));
+    }
+
+    #[test]
+    fn paged_tiles_are_placed_in_their_page() {
+        let mut world = World::new();
+        world.register::<TileMap<TileGid, FlatEncoder>>();
+        world.register::<TileAtlas>();
+        let pool = Arc::new(rayon::ThreadPoolBuilder::new().build().unwrap());
+        world.insert(Loader::new(env!("CARGO_MANIFEST_DIR"), pool));
+        world.insert(AssetStorage::<Texture>::default());
+        world.insert(AssetStorage::<SpriteSheet>::default());
+        world.insert(TileAnimations::default());
+        world.insert(NavigationGrids::default());
+
+        let (map_size, tile_size) = (Vector3::new(4, 4, 1), Vector3::new(16, 16, 1));
+        let pages: Vec<Entity> = [5, 7]
+            .iter()
+            .enumerate()
+            .map(|(page, gid)| {
+                let atlas = TileAtlas {
+                    tiles: vec![((*gid, Flip::NONE), TileGid::new(page + 1, Flip::NONE))]
+                        .into_iter()
+                        .collect(),
+                    ..TileAtlas::default()
+                };
+
+                world
+                    .create_entity()
+                    .with(TileMap::<TileGid, FlatEncoder>::new(
+                        map_size, tile_size, None,
+                    ))
+                    .with(atlas)
+                    .build()
+            })
+            .collect();
+        world
+            .write_storage::<TileAtlas>()
+            .get_mut(pages[0])
+            .unwrap()
+            .pages = vec![pages[1]];
+
+        let mut data = <EditData<'_, FlatEncoder> as SystemData>::fetch(&world);
+        let cell = Point3::new(1, 2, 0);
+        let placed = |data: &EditData<'_, FlatEncoder>| -> Vec<TileGid> {
+            pages
+                .iter()
+                .map(|page| *(data.1).0.get(*page).unwrap().get(&cell).unwrap())
+                .collect()
+        };
+
+        set_tiles(&mut data, pages[0], &[(cell, 5)]).unwrap();
+        assert_eq!(
+            placed(&data),
+            vec![TileGid::new(1, Flip::NONE), TileGid::default()]
+        );
+
+        set_tiles(&mut data, pages[0], &[(cell, 7)]).unwrap();
+        assert_eq!(
+            placed(&data),
+            vec![TileGid::default(), TileGid::new(2, Flip::NONE)]
+        );
+
+        set_tiles(&mut data, pages[0], &[(cell, 0)]).unwrap();
+        assert_eq!(placed(&data), vec![TileGid::default(); 2]);
+
+        assert!(set_tiles(&mut data, pages[0], &[(cell, 9)]).is_err());
+        assert!(set_tiles(&mut data, pages[0], &[(Point3::new(0, 0, 1), 5)]).is_err());
+    }
+
+    #[test]
+    fn placed_tiles_update_the_navigation_grid() {
+        let document = r#"<?xml version="1.0" encoding="UTF-8"?>
+<map version="1.2" orientation="orthogonal" renderorder="right-down" width="3" height="3" tilewidth="16" tileheight="16" infinite="0" nextlayerid="2" nextobjectid="1">
+ <tileset firstgid="1" name="walls" tilewidth="16" tileheight="16" tilecount="8" columns="4">
+  <image source="walls.png" width="64" height="32"/>
+  <tile id="4">
+   <properties>
+    <property name="walkable" type="bool" value="false"/>
+   </properties>
+  </tile>
+ </tileset>
+ <layer id="1" name="Ground" width="3" height="3">
+  <data encoding="csv">
+1,1,1,
+1,1,1,
+1,1,1
+</data>
+ </layer>
+</map>"#;
+        let tiled = parse(document.as_bytes()).unwrap();
+        let z = tiled.layers[0].layer_index;
+
+        let mut world = World::new();
+        world.register::<TileMap<TileGid, FlatEncoder>>();
+        world.register::<TileAtlas>();
+        let pool = Arc::new(rayon::ThreadPoolBuilder::new().build().unwrap());
+        world.insert(Loader::new(env!("CARGO_MANIFEST_DIR"), pool));
+        world.insert(AssetStorage::<Texture>::default());
+        world.insert(AssetStorage::<SpriteSheet>::default());
+        world.insert(TileAnimations::default());
+        world.insert(NavigationGrids::default());
+
+        let atlas = TileAtlas {
+            tiles: vec![
+                ((1, Flip::NONE), TileGid::new(1, Flip::NONE)),
+                ((5, Flip::NONE), TileGid::new(2, Flip::NONE)),
+            ]
+            .into_iter()
+            .collect(),
+            ..TileAtlas::default()
+        };
+        let map = world
+            .create_entity()
+            .with(TileMap::<TileGid, FlatEncoder>::new(
+                Vector3::new(3, 3, z + 1),
+                Vector3::new(16, 16, 1),
+                None,
+            ))
+            .with(atlas)
+            .build();
+
+        let grid = NavGrid::of(&tiled, &MapBounds::of(&tiled));
+        world.write_resource::<NavigationGrids>().insert(map, grid);
+
+        let mut data = <EditData<'_, FlatEncoder> as SystemData>::fetch(&world);
+        let cost = |data: &EditData<'_, FlatEncoder>| {
+            data.2
+                .get(map)
+                .unwrap()
+                .find_path((0, 1), (2, 1), &PathOptions::default())
+                .map(|x| x.cost)
+        };
+        assert_eq!(cost(&data), Some(2.0));
+
+        let cell = Point3::new(1, 1, z);
+        set_tile(&mut data, map, cell, 5).unwrap();
+        assert!(!data.2.get(map).unwrap().is_walkable(1, 1));
+        assert_eq!(cost(&data), Some(4.0));
+
+        set_tile(&mut data, map, cell, 1).unwrap();
+        assert!(data.2.get(map).unwrap().is_walkable(1, 1));
+        assert_eq!(cost(&data), Some(2.0));
+    }
+}
@@ -1,22 +1,27 @@
 use std::path::PathBuf;
 use std::sync::Arc;
 
-use amethyst::assets::{Format, FormatValue, Prefab, SingleFile, Source};
+use amethyst::assets::{Format, FormatValue, Prefab, Reload, Source};
 use amethyst::Error;
 use image::{load_from_memory, DynamicImage, RgbaImage};
 use tiled::{parse, parse_tileset, TilesetRef};
 
+use crate::json::read_document;
+use crate::orientation::parse_orientation;
 use crate::prefab::TileMapPrefab;
+use crate::reload::{Dependencies, MapReload, PrefabReload};
 use crate::strategy::StrategyDesc;
+use crate::wang::parse_wang_sets;
 
 #[cfg(feature = "profiler")]
 use thread_profiler::profile_scope;
 
-/// Format for loading *.tmx and *.tsx files
+/// Format for loading *.tmx and *.tsx files, along with the *.tmj and *.tsj files Tiled exports as
+/// JSON. The format of each file is chosen by its extension.
 #[derive(Debug, Copy, Clone)]
 pub struct TiledFormat;
 
-impl<T: 'static> Format<Prefab<T>> for TiledFormat
+impl<T: Send + Sync + 'static> Format<Prefab<T>> for TiledFormat
 where
     TiledFormat: Format<T>,
 {
@@ -28,10 +33,17 @@ where
         &self,
         name: String,
         source: Arc<dyn Source>,
-        _: Option<Box<dyn Format<Prefab<T>>>>,
+        create_reload: Option<Box<dyn Format<Prefab<T>>>>,
     ) -> Result<FormatValue<Prefab<T>>, Error> {
-        let value = <Self as Format<T>>::import(self, name, source, None)?;
-        Ok(FormatValue::data(Prefab::new_main(value.data)))
+        let reload = create_reload.map(|_| Box::new(*self) as Box<dyn Format<T>>);
+        let value = <Self as Format<T>>::import(self, name, source, reload)?;
+
+        Ok(FormatValue {
+            data: Prefab::new_main(value.data),
+            reload: value
+                .reload
+                .map(|reload| Box::new(PrefabReload(reload)) as Box<dyn Reload<Prefab<T>>>),
+        })
     }
 }
 
@@ -50,18 +62,29 @@ impl<T: 'static + StrategyDesc> Format<TileMapPrefab<T>> for TiledFormat {
         profile_scope!("import_tiled_format");
 
         let (b, m) = source.load_with_metadata(&name)?;
+        let b = read_document(&name, b)?;
+
+        let mut dependencies = Dependencies::new(source.clone());
+        dependencies.push(name.clone(), m);
 
         let mut map = match parse(&b[..]) {
             Ok(v) => v,
             Err(e) => return Err(Error::new(e)),
         };
 
+        // Wang sets and stagger settings are not read by the tiled crate, so they are found in the
+        // files separately
+        let mut wang_sets = parse_wang_sets(&b[..], 1)?;
+        let orientation = parse_orientation(&b[..], &map)?;
+
         for tileset in &mut map.tilesets {
             if let TilesetRef::Path(path, gid) = tileset {
                 let file = shift_path(&name, path);
-                let source = source.load(&file)?;
+                dependencies.track(&file);
+                let source = read_document(&file, source.load(&file)?)?;
 
                 let mut set = parse_tileset(&source[..], *gid)?;
+                wang_sets.extend(parse_wang_sets(&source[..], *gid)?);
 
                 for image in &mut set.images {
                     image.source = shift_path(&file, &image.source);
@@ -71,13 +94,27 @@ impl<T: 'static + StrategyDesc> Format<TileMapPrefab<T>> for TiledFormat {
             }
         }
 
+        for layer in &mut map.image_layers {
+            if let Some(image) = &mut layer.image {
+                image.source = shift_path(&name, &image.source);
+            }
+        }
+
         if let Some(boxed_format) = create_reload {
+            dependencies.track_map(&map);
+
             Ok(FormatValue {
-                data: TileMapPrefab::Map(map, source.clone()),
-                reload: Some(Box::new(SingleFile::new(boxed_format, m, name, source))),
+                data: TileMapPrefab::Map(map, source, wang_sets, name.clone(), orientation),
+                reload: Some(Box::new(MapReload::new(boxed_format, name, dependencies))),
             })
         } else {
-            Ok(FormatValue::data(TileMapPrefab::Map(map, source)))
+            Ok(FormatValue::data(TileMapPrefab::Map(
+                map,
+                source,
+                wang_sets,
+                name,
+                orientation,
+            )))
         }
     }
 }
@@ -96,7 +133,7 @@ impl Format<RgbaImage> for TiledFormat {
 }
 
 /// Get an adjusted path based on a reference
-fn shift_path(reference: &str, path: &str) -> String {
+pub(crate) fn shift_path(reference: &str, path: &str) -> String {
     let mut path_buf = PathBuf::from(reference);
     path_buf.set_file_name(path);
     path_buf.to_str().unwrap().to_owned()
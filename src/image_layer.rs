@@ -0,0 +1,133 @@
+//! Sprite entities created from the image layers of a map
+
+use std::sync::Arc;
+
+use amethyst::assets::{AssetStorage, Handle, Loader, ProgressCounter, Source};
+use amethyst::core::Transform;
+use amethyst::ecs::{Entity, Read, ReadExpect};
+use amethyst::renderer::palette::Srgba;
+use amethyst::renderer::resources::Tint;
+use amethyst::renderer::sprite::Sprite;
+use amethyst::renderer::transparent::Transparent;
+use amethyst::renderer::{SpriteRender, SpriteSheet, Texture};
+use amethyst::Error;
+use tiled::{Map, Properties};
+
+use crate::bounds::MapBounds;
+use crate::load_texture;
+use crate::packing::open_image;
+use crate::prefab::{create_child, ChildData};
+
+#[cfg(feature = "profiler")]
+use thread_profiler::profile_scope;
+
+/// The data required to load the image layers of a map
+pub type ImageLayerData<'a> = (
+    ReadExpect<'a, Loader>,
+    Read<'a, AssetStorage<Texture>>,
+    Read<'a, AssetStorage<SpriteSheet>>,
+);
+
+struct ImageSpawn {
+    name: String,
+    sheet: Handle<SpriteSheet>,
+    transform: Transform,
+    opacity: f32,
+    visible: bool,
+    properties: Properties,
+}
+
+/// The loaded image layers of a map which get spawned as children of the map entity
+#[derive(Default)]
+pub struct ImageLayers(Vec<ImageSpawn>);
+
+impl ImageLayers {
+    /// Load the image of every image layer using the same source as the map's tilesets
+    pub fn load(
+        map: &Map,
+        bounds: &MapBounds,
+        source: Arc<dyn Source>,
+        progress: &mut ProgressCounter,
+        system_data: &ImageLayerData<'_>,
+    ) -> Result<Self, Error> {
+        #[cfg(feature = "profiler")]
+        profile_scope!("load_image_layers");
+
+        let (loader, textures, sheets) = system_data;
+        let mut spawns = Vec::new();
+
+        for layer in &map.image_layers {
+            let image = match &layer.image {
+                Some(v) => v,
+                None => continue,
+            };
+
+            let pixels = open_image(image, source.clone())?;
+            let (width, height) = pixels.dimensions();
+
+            let texture =
+                load_texture(pixels.into_raw(), width, height, loader, progress, textures);
+
+            let sheet = SpriteSheet {
+                texture,
+                sprites: vec![Sprite::from_pixel_values(
+                    width, height, width, height, 0, 0, [0.0; 2], false, false,
+                )],
+            };
+
+            // Sprites are drawn around their center while Tiled uses the top left corner
+            let mut translation = bounds.pixel_to_world(
+                layer.offset_x + width as f32 / 2.0,
+                layer.offset_y + height as f32 / 2.0,
+            );
+            translation.z = layer.layer_index as f32;
+
+            let mut transform = Transform::default();
+            transform.set_translation(translation);
+
+            spawns.push(ImageSpawn {
+                name: layer.name.to_owned(),
+                sheet: loader.load_from_data(sheet, &mut *progress, &**sheets),
+                transform,
+                opacity: layer.opacity,
+                visible: layer.visible,
+                properties: layer.properties.clone(),
+            });
+        }
+
+        Ok(Self(spawns))
+    }
+
+    /// Create a sprite entity for every image layer as a child of the map entity
+    pub fn spawn(&self, parent: Entity, child_data: &mut ChildData<'_>) -> Result<(), Error> {
+        #[cfg(feature = "profiler")]
+        profile_scope!("spawn_image_layers");
+
+        for spawn in &self.0 {
+            let entity = create_child(
+                child_data,
+                parent,
+                &spawn.name,
+                spawn.transform.clone(),
+                spawn.visible,
+                &spawn.properties,
+            )?;
+
+            child_data.sprites.insert(
+                entity,
+                SpriteRender {
+                    sprite_sheet: spawn.sheet.clone(),
+                    sprite_number: 0,
+                },
+            )?;
+
+            if spawn.opacity < 1.0 {
+                let tint = Tint(Srgba::new(1.0, 1.0, 1.0, spawn.opacity));
+                child_data.tints.insert(entity, tint)?;
+                child_data.transparent.insert(entity, Transparent)?;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,606 @@
+//! Support for the JSON maps and tilesets exported by Tiled. They are converted to the equivalent
+//! TMX and TSX documents so they are read by the same parser as XML files.
+
+use std::io::Write;
+use std::path::Path;
+
+use amethyst::Error;
+use log::warn;
+use serde_json::Value;
+use xml::common::XmlVersion;
+use xml::writer::{EmitterConfig, EventWriter, XmlEvent};
+
+use crate::writer::{end, start};
+
+#[cfg(feature = "profiler")]
+use thread_profiler::profile_scope;
+
+type Attributes = Vec<(&'static str, String)>;
+
+/// Check if a map or tileset file was exported as JSON based on its extension
+pub fn is_json<P: AsRef<Path>>(path: P) -> bool {
+    match path.as_ref().extension().and_then(|x| x.to_str()) {
+        Some(extension) => ["tmj", "tsj", "json"]
+            .iter()
+            .any(|x| extension.eq_ignore_ascii_case(x)),
+        None => false,
+    }
+}
+
+/// Get the XML document for a map or tileset file, converting it first if it is JSON
+pub(crate) fn read_document<P: AsRef<Path>>(path: P, bytes: Vec<u8>) -> Result<Vec<u8>, Error> {
+    match is_json(&path) {
+        true => json_to_xml(&bytes[..])
+            .map_err(|e| Error::from_string(format!("Unable to read {:?}: {:}", path.as_ref(), e))),
+        false => Ok(bytes),
+    }
+}
+
+/// Convert a map or tileset exported by Tiled as JSON into the equivalent TMX or TSX document.
+/// External tilesets are kept as references, so their paths are resolved the same way as maps
+/// saved as XML.
+pub fn json_to_xml(bytes: &[u8]) -> Result<Vec<u8>, Error> {
+    #[cfg(feature = "profiler")]
+    profile_scope!("json_to_xml");
+
+    let value: Value = serde_json::from_slice(bytes).map_err(Error::new)?;
+    let mut document = Vec::new();
+
+    {
+        let mut writer = EmitterConfig::new()
+            .perform_indent(true)
+            .create_writer(&mut document);
+
+        writer
+            .write(XmlEvent::StartDocument {
+                version: XmlVersion::Version10,
+                encoding: Some("UTF-8"),
+                standalone: None,
+            })
+            .map_err(Error::new)?;
+
+        match value.get("type").and_then(Value::as_str) {
+            Some("map") => write_map(&mut writer, &value)?,
+            Some("tileset") => write_tileset(&mut writer, &value)?,
+            kind => {
+                return Err(Error::from_string(format!(
+                    "Expected a JSON map or tileset, but found type: {:?}",
+                    kind
+                )))
+            }
+        }
+    }
+
+    Ok(document)
+}
+
+/// The text of a JSON value as it would appear in an XML attribute
+fn text(value: &Value) -> Option<String> {
+    match value {
+        Value::String(v) => Some(v.clone()),
+        Value::Number(v) => Some(v.to_string()),
+        Value::Bool(v) => Some((*v as u8).to_string()),
+        _ => None,
+    }
+}
+
+/// Copy the fields of a JSON object which are present to XML attributes. Each entry gives the
+/// name of the attribute followed by the name of the field.
+fn attributes(value: &Value, fields: &[(&'static str, &str)]) -> Attributes {
+    fields
+        .iter()
+        .filter_map(|(attribute, field)| Some((*attribute, text(value.get(*field)?)?)))
+        .collect()
+}
+
+/// Iterate over an array field, treating a missing field as empty
+fn array<'v>(value: &'v Value, field: &str) -> impl Iterator<Item = &'v Value> {
+    value
+        .get(field)
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+}
+
+fn empty<W: Write>(
+    writer: &mut EventWriter<W>,
+    name: &str,
+    attributes: &[(&str, String)],
+) -> Result<(), Error> {
+    start(writer, name, attributes)?;
+    end(writer)
+}
+
+fn write_map<W: Write>(writer: &mut EventWriter<W>, map: &Value) -> Result<(), Error> {
+    let mut map_attributes = attributes(
+        map,
+        &[
+            ("version", "version"),
+            ("tiledversion", "tiledversion"),
+            ("orientation", "orientation"),
+            ("renderorder", "renderorder"),
+            ("width", "width"),
+            ("height", "height"),
+            ("tilewidth", "tilewidth"),
+            ("tileheight", "tileheight"),
+            ("hexsidelength", "hexsidelength"),
+            ("staggeraxis", "staggeraxis"),
+            ("staggerindex", "staggerindex"),
+            ("backgroundcolor", "backgroundcolor"),
+            ("infinite", "infinite"),
+            ("nextlayerid", "nextlayerid"),
+            ("nextobjectid", "nextobjectid"),
+        ],
+    );
+
+    // Older exports store the version as a number, which the XML format never did
+    for (name, value) in &mut map_attributes {
+        if *name == "version" && !value.contains('.') {
+            value.push_str(".0");
+        }
+    }
+
+    start(writer, "map", &map_attributes)?;
+    write_properties(writer, map)?;
+
+    for tileset in array(map, "tilesets") {
+        match tileset.get("source") {
+            Some(_) => empty(
+                writer,
+                "tileset",
+                &attributes(tileset, &[("firstgid", "firstgid"), ("source", "source")]),
+            )?,
+            None => write_tileset(writer, tileset)?,
+        }
+    }
+
+    for layer in array(map, "layers") {
+        write_layer(writer, layer)?;
+    }
+
+    end(writer)
+}
+
+fn write_properties<W: Write>(writer: &mut EventWriter<W>, value: &Value) -> Result<(), Error> {
+    let properties: Vec<&Value> = array(value, "properties").collect();
+
+    if properties.is_empty() {
+        return Ok(());
+    }
+
+    start(writer, "properties", &[])?;
+
+    for property in properties {
+        // Class properties hold nested objects which the tiled crate can not read
+        if property.get("value").map_or(false, Value::is_object) {
+            warn!(
+                "Skipping class property {:?} which can not be read",
+                property.get("name").and_then(text).unwrap_or_default()
+            );
+            continue;
+        }
+
+        let mut property_attributes = attributes(property, &[("name", "name"), ("type", "type")]);
+
+        if let Some(value) = property.get("value") {
+            let value = match value {
+                Value::Bool(v) => v.to_string(),
+                v => text(v).unwrap_or_default(),
+            };
+            property_attributes.push(("value", value));
+        }
+
+        empty(writer, "property", &property_attributes)?;
+    }
+
+    end(writer)
+}
+
+/// Write the image of a tileset, tile or image layer, which JSON stores in fields of its owner
+fn write_image<W: Write>(writer: &mut EventWriter<W>, owner: &Value) -> Result<(), Error> {
+    if owner.get("image").is_none() {
+        return Ok(());
+    }
+
+    let mut image_attributes = attributes(
+        owner,
+        &[
+            ("source", "image"),
+            ("width", "imagewidth"),
+            ("height", "imageheight"),
+        ],
+    );
+
+    if let Some(colour) = owner.get("transparentcolor").and_then(Value::as_str) {
+        image_attributes.push(("trans", colour.trim_start_matches('#').to_owned()));
+    }
+
+    empty(writer, "image", &image_attributes)
+}
+
+fn write_tileset<W: Write>(writer: &mut EventWriter<W>, tileset: &Value) -> Result<(), Error> {
+    let tileset_attributes = attributes(
+        tileset,
+        &[
+            ("firstgid", "firstgid"),
+            ("name", "name"),
+            ("tilewidth", "tilewidth"),
+            ("tileheight", "tileheight"),
+            ("spacing", "spacing"),
+            ("margin", "margin"),
+            ("tilecount", "tilecount"),
+            ("columns", "columns"),
+            ("objectalignment", "objectalignment"),
+        ],
+    );
+
+    start(writer, "tileset", &tileset_attributes)?;
+
+    if let Some(offset) = tileset.get("tileoffset") {
+        empty(
+            writer,
+            "tileoffset",
+            &attributes(offset, &[("x", "x"), ("y", "y")]),
+        )?;
+    }
+
+    write_properties(writer, tileset)?;
+    write_image(writer, tileset)?;
+
+    for tile in array(tileset, "tiles") {
+        let mut tile_attributes = attributes(tile, &[("id", "id"), ("probability", "probability")]);
+
+        // Tiled 1.9 renamed the type of a tile to its class
+        if let Some(class) = tile
+            .get("type")
+            .or_else(|| tile.get("class"))
+            .and_then(text)
+        {
+            tile_attributes.push(("type", class));
+        }
+
+        start(writer, "tile", &tile_attributes)?;
+        write_properties(writer, tile)?;
+        write_image(writer, tile)?;
+
+        if let Some(group) = tile.get("objectgroup") {
+            write_layer(writer, group)?;
+        }
+
+        let frames: Vec<&Value> = array(tile, "animation").collect();
+        if !frames.is_empty() {
+            start(writer, "animation", &[])?;
+
+            for frame in frames {
+                let frame_attributes =
+                    attributes(frame, &[("tileid", "tileid"), ("duration", "duration")]);
+                empty(writer, "frame", &frame_attributes)?;
+            }
+
+            end(writer)?;
+        }
+
+        end(writer)?;
+    }
+
+    let wang_sets: Vec<&Value> = array(tileset, "wangsets").collect();
+    if !wang_sets.is_empty() {
+        start(writer, "wangsets", &[])?;
+
+        for set in wang_sets {
+            let set_attributes =
+                attributes(set, &[("name", "name"), ("type", "type"), ("tile", "tile")]);
+            start(writer, "wangset", &set_attributes)?;
+
+            for colour in array(set, "colors") {
+                let colour_attributes = attributes(
+                    colour,
+                    &[
+                        ("name", "name"),
+                        ("color", "color"),
+                        ("tile", "tile"),
+                        ("probability", "probability"),
+                    ],
+                );
+                empty(writer, "wangcolor", &colour_attributes)?;
+            }
+
+            for tile in array(set, "wangtiles") {
+                let mut tile_attributes = attributes(tile, &[("tileid", "tileid")]);
+                let wang_id: Vec<String> = array(tile, "wangid").filter_map(text).collect();
+                tile_attributes.push(("wangid", wang_id.join(",")));
+                empty(writer, "wangtile", &tile_attributes)?;
+            }
+
+            end(writer)?;
+        }
+
+        end(writer)?;
+    }
+
+    end(writer)
+}
+
+/// The attributes shared by every kind of layer
+const LAYER_FIELDS: [(&str, &str); 6] = [
+    ("id", "id"),
+    ("name", "name"),
+    ("opacity", "opacity"),
+    ("visible", "visible"),
+    ("offsetx", "offsetx"),
+    ("offsety", "offsety"),
+];
+
+fn write_layer<W: Write>(writer: &mut EventWriter<W>, layer: &Value) -> Result<(), Error> {
+    let mut layer_attributes = attributes(layer, &LAYER_FIELDS);
+
+    match layer.get("type").and_then(Value::as_str) {
+        Some("tilelayer") => {
+            layer_attributes.extend(attributes(
+                layer,
+                &[("width", "width"), ("height", "height")],
+            ));
+            start(writer, "layer", &layer_attributes)?;
+            write_properties(writer, layer)?;
+
+            let data_attributes = attributes(
+                layer,
+                &[("encoding", "encoding"), ("compression", "compression")],
+            );
+            let data_attributes = match data_attributes.is_empty() {
+                true => vec![("encoding", "csv".to_owned())],
+                false => data_attributes,
+            };
+
+            start(writer, "data", &data_attributes)?;
+
+            match layer.get("chunks") {
+                Some(_) => {
+                    for chunk in array(layer, "chunks") {
+                        let chunk_attributes = attributes(
+                            chunk,
+                            &[
+                                ("x", "x"),
+                                ("y", "y"),
+                                ("width", "width"),
+                                ("height", "height"),
+                            ],
+                        );
+                        start(writer, "chunk", &chunk_attributes)?;
+                        write_tile_data(writer, chunk)?;
+                        end(writer)?;
+                    }
+                }
+                None => write_tile_data(writer, layer)?,
+            }
+
+            end(writer)?;
+        }
+        Some("imagelayer") => {
+            start(writer, "imagelayer", &layer_attributes)?;
+            write_properties(writer, layer)?;
+            write_image(writer, layer)?;
+        }
+        Some("objectgroup") | None => {
+            layer_attributes.extend(attributes(
+                layer,
+                &[("color", "color"), ("draworder", "draworder")],
+            ));
+            start(writer, "objectgroup", &layer_attributes)?;
+            write_properties(writer, layer)?;
+
+            for object in array(layer, "objects") {
+                write_object(writer, object)?;
+            }
+        }
+        Some("group") => {
+            start(writer, "group", &layer_attributes)?;
+            write_properties(writer, layer)?;
+
+            for child in array(layer, "layers") {
+                write_layer(writer, child)?;
+            }
+        }
+        Some(kind) => {
+            return Err(Error::from_string(format!(
+                "Unable to read layer of type: {:?}",
+                kind
+            )))
+        }
+    }
+
+    end(writer)
+}
+
+/// Write the tiles of a layer or chunk, which are either an array of gids or an encoded string
+fn write_tile_data<W: Write>(writer: &mut EventWriter<W>, owner: &Value) -> Result<(), Error> {
+    let data = match owner.get("data") {
+        Some(Value::String(v)) => v.clone(),
+        Some(Value::Array(gids)) => {
+            let width = owner
+                .get("width")
+                .and_then(Value::as_u64)
+                .unwrap_or(gids.len() as u64)
+                .max(1) as usize;
+
+            let rows: Vec<String> = gids
+                .chunks(width)
+                .map(|row| row.iter().filter_map(text).collect::<Vec<_>>().join(","))
+                .collect();
+
+            format!("\n{}\n", rows.join(",\n"))
+        }
+        _ => {
+            return Err(Error::from_string(
+                "Unable to find the tiles of a tile layer".to_owned(),
+            ))
+        }
+    };
+
+    writer
+        .write(XmlEvent::characters(&data))
+        .map_err(Error::new)
+}
+
+fn write_object<W: Write>(writer: &mut EventWriter<W>, object: &Value) -> Result<(), Error> {
+    let mut object_attributes = attributes(
+        object,
+        &[
+            ("id", "id"),
+            ("name", "name"),
+            ("gid", "gid"),
+            ("x", "x"),
+            ("y", "y"),
+            ("width", "width"),
+            ("height", "height"),
+            ("rotation", "rotation"),
+            ("visible", "visible"),
+        ],
+    );
+
+    // Tiled 1.9 renamed the type of an object to its class
+    if let Some(class) = object
+        .get("type")
+        .or_else(|| object.get("class"))
+        .and_then(text)
+    {
+        object_attributes.push(("type", class));
+    }
+
+    start(writer, "object", &object_attributes)?;
+    write_properties(writer, object)?;
+
+    let flag = |name: &str| object.get(name).and_then(Value::as_bool).unwrap_or(false);
+
+    if flag("ellipse") {
+        empty(writer, "ellipse", &[])?;
+    } else if flag("point") {
+        empty(writer, "point", &[])?;
+    }
+
+    for shape in ["polygon", "polyline"] {
+        if object.get(shape).is_some() {
+            let points: Vec<String> = array(object, shape)
+                .map(|point| {
+                    let coordinate =
+                        |name: &str| point.get(name).and_then(text).unwrap_or_default();
+                    format!("{},{}", coordinate("x"), coordinate("y"))
+                })
+                .collect();
+
+            empty(writer, shape, &[("points", points.join(" "))])?;
+        }
+    }
+
+    end(writer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prefab::TileMapPrefab;
+    use crate::strategy::CompressedLoad;
+    use crate::TiledFormat;
+    use amethyst::assets::{Directory, Format};
+    use std::sync::Arc;
+    use tiled::{Layer, Map};
+
+    fn load_example(name: &str) -> Map {
+        let source = Arc::new(Directory::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/examples/assets"
+        )));
+
+        let value = <TiledFormat as Format<TileMapPrefab<CompressedLoad>>>::import(
+            &TiledFormat,
+            name.to_owned(),
+            source,
+            None,
+        )
+        .expect("The example map should load");
+
+        match value.data {
+            TileMapPrefab::Map(map, ..) => map,
+            TileMapPrefab::Result(..) => unreachable!("Maps are loaded before being processed"),
+        }
+    }
+
+    #[test]
+    fn json_files_are_found_by_extension() {
+        assert!(is_json("prefab/example_map.tmj"));
+        assert!(is_json("prefab/example_tileset.TSJ"));
+        assert!(is_json("map.json"));
+        assert!(!is_json("prefab/example_map.tmx"));
+        assert!(!is_json("prefab/example_map"));
+    }
+
+    #[test]
+    fn json_example_matches_tmx() {
+        let tmx = load_example("prefab/example_map.tmx");
+        let tmj = load_example("prefab/example_map.tmj");
+
+        assert_eq!(tmx.width, tmj.width);
+        assert_eq!(tmx.height, tmj.height);
+        assert_eq!(tmx.tile_width, tmj.tile_width);
+        assert_eq!(tmx.tile_height, tmj.tile_height);
+        assert_eq!(tmx.orientation, tmj.orientation);
+        assert_eq!(tmx.layers.len(), tmj.layers.len());
+
+        for (a, b) in tmx.layers.iter().zip(&tmj.layers) {
+            assert_eq!(a.name, b.name);
+            assert_eq!(a.visible, b.visible);
+            assert_eq!(a.opacity, b.opacity);
+            assert_eq!(a.layer_index, b.layer_index);
+
+            let gids = |layer: &Layer| -> Vec<Vec<u32>> {
+                layer
+                    .tiles
+                    .iter()
+                    .map(|row| row.iter().map(|x| x.gid).collect())
+                    .collect()
+            };
+            assert_eq!(gids(a), gids(b));
+        }
+
+        assert_eq!(tmx.tilesets.len(), tmj.tilesets.len());
+
+        for (a, b) in tmx.tilesets.iter().zip(&tmj.tilesets) {
+            let (a, b) = (a.unwrap(), b.unwrap());
+            assert_eq!(a.name, b.name);
+            assert_eq!(a.first_gid, b.first_gid);
+            assert_eq!(a.tile_width, b.tile_width);
+            assert_eq!(a.tile_height, b.tile_height);
+            assert_eq!(a.tilecount, b.tilecount);
+            assert_eq!(a.images, b.images);
+        }
+    }
+
+    #[test]
+    fn external_json_tileset_paths_are_shifted() {
+        let map = load_example("prefab/example_map.tmj");
+        let set = &map.tilesets[0];
+
+        assert_eq!(
+            set.unwrap().images[0].source,
+            "prefab/../texture/great_tileset.png"
+        );
+    }
+
+    #[test]
+    fn class_properties_are_skipped() {
+        let tileset = br##"{
+            "type": "tileset",
+            "name": "classes",
+            "tilewidth": 16,
+            "tileheight": 16,
+            "properties": [
+                {"name": "solid", "type": "bool", "value": true},
+                {"name": "spawn", "type": "class", "propertytype": "Spawn", "value": {"x": 1}}
+            ]
+        }"##;
+
+        let document = String::from_utf8(json_to_xml(&tileset[..]).unwrap()).unwrap();
+        assert!(document.contains(r#"name="solid""#));
+        assert!(!document.contains("spawn"));
+    }
+}
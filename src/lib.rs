@@ -1,9 +1,12 @@
 use std::collections::HashMap;
-use std::fs::File;
+use std::fs;
+use std::hash::Hasher;
 use std::path::Path;
 use std::sync::{Arc, Mutex};
 
-use amethyst::assets::{AssetStorage, Directory, Handle, Loader, ProgressCounter, Source};
+use amethyst::assets::{
+    AssetStorage, Directory, Handle, Loader, ProgressCounter, Source, WeakHandle,
+};
 use amethyst::core::math::Point3;
 use amethyst::ecs::World;
 
@@ -20,36 +23,195 @@ use tiled::{parse_tileset, Tileset};
 #[cfg(feature = "profiler")]
 use thread_profiler::profile_scope;
 
+pub mod animation;
+pub mod bake;
+pub mod bounds;
+pub mod cache;
+pub mod chunk;
+pub mod collision;
+pub mod editing;
 mod format;
+pub mod image_layer;
+pub mod json;
+pub mod navigation;
+pub mod object;
+pub mod orientation;
 pub mod packing;
+pub mod pages;
 mod prefab;
+pub mod property;
+pub mod reload;
+pub mod sprite;
 pub mod strategy;
+pub mod wang;
+pub mod writer;
 
-use packing::AmethystOrderedFormat;
+use animation::TileAnimations;
+use cache::{hash_bytes, StableHasher};
+use json::read_document;
+use packing::{pack_tileset, AmethystOrderedFormat};
+use reload::Dependencies;
 
+pub use animation::TileAnimationSystem;
+pub use bake::BakedLoad;
+pub use bounds::MapBounds;
+pub use cache::AtlasCache;
+pub use chunk::{ChunkSettings, ChunkStreamingSystem, ChunkedLoad, ChunkedMap};
+pub use collision::{CollisionShape, MapCollision, SolidCells};
+pub use editing::{set_tile, set_tiles, EditData};
 pub use format::TiledFormat;
+pub use navigation::{NavGrid, NavigationCleanupSystem, NavigationGrids, PathOptions};
+pub use object::{TileObject, TiledProperties};
+pub use orientation::{MapOrientation, StaggerAxis, StaggerIndex};
+pub use packing::PackSettings;
+pub use pages::AtlasPages;
 pub use prefab::*;
-pub use strategy::{CompressedLoad, FlatLoad, StaticLoad};
+pub use property::PropertyRegistry;
+pub use sprite::{SpriteLoad, SpriteMap};
+pub use strategy::{
+    CompressedLoad, FlatLoad, LayeredLoad, LayeredMap, PagedMap, StaticLoad, StaticMap, TileAtlas,
+    TileLayer,
+};
+pub use wang::MapTerrain;
+pub use writer::{write_map, TmxWriter};
+
+/// Bit set by Tiled on a gid when the tile is flipped horizontally
+pub const FLIPPED_HORIZONTALLY_FLAG: u32 = 0x8000_0000;
+/// Bit set by Tiled on a gid when the tile is flipped vertically
+pub const FLIPPED_VERTICALLY_FLAG: u32 = 0x4000_0000;
+/// Bit set by Tiled on a gid when the tile is flipped diagonally (x and y axes swapped)
+pub const FLIPPED_DIAGONALLY_FLAG: u32 = 0x2000_0000;
+
+const FLIP_FLAGS: u32 =
+    FLIPPED_HORIZONTALLY_FLAG | FLIPPED_VERTICALLY_FLAG | FLIPPED_DIAGONALLY_FLAG;
+
+/// The orientation a tile was placed with. Following Tiled, the diagonal flip is applied first and
+/// is followed by the horizontal and vertical flips. Rotations are stored by Tiled as a combination
+/// of these flips.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Flip {
+    pub horizontal: bool,
+    pub vertical: bool,
+    pub diagonal: bool,
+}
+
+impl Flip {
+    pub const NONE: Flip = Flip {
+        horizontal: false,
+        vertical: false,
+        diagonal: false,
+    };
+
+    /// Split a raw gid read from a tile layer into the gid of the tile and its orientation
+    pub fn decode(raw: u32) -> (u32, Flip) {
+        let flip = Flip {
+            horizontal: raw & FLIPPED_HORIZONTALLY_FLAG != 0,
+            vertical: raw & FLIPPED_VERTICALLY_FLAG != 0,
+            diagonal: raw & FLIPPED_DIAGONALLY_FLAG != 0,
+        };
+
+        (raw & !FLIP_FLAGS, flip)
+    }
+
+    /// Store the orientation back in the upper bits of a gid
+    pub fn encode(&self, gid: u32) -> u32 {
+        let mut raw = gid & !FLIP_FLAGS;
+        if self.horizontal {
+            raw |= FLIPPED_HORIZONTALLY_FLAG;
+        }
+        if self.vertical {
+            raw |= FLIPPED_VERTICALLY_FLAG;
+        }
+        if self.diagonal {
+            raw |= FLIPPED_DIAGONALLY_FLAG;
+        }
+        raw
+    }
+
+    pub fn is_flipped(&self) -> bool {
+        self.horizontal || self.vertical || self.diagonal
+    }
+
+    /// The part of the flip that can be expressed by mirroring texture coordinates
+    pub fn mirror(&self) -> Flip {
+        Flip {
+            diagonal: false,
+            ..*self
+        }
+    }
+}
 
 /// The grid id of a tile
-#[repr(transparent)]
-#[derive(Copy, Clone, Hash, Default)]
-pub struct TileGid(usize);
+#[derive(Debug, Copy, Clone, Hash, Default, PartialEq, Eq)]
+pub struct TileGid {
+    sprite: usize,
+    flip: Flip,
+    animation: Option<usize>,
+}
+
+impl TileGid {
+    pub fn new(sprite: usize, flip: Flip) -> Self {
+        Self {
+            sprite,
+            flip,
+            animation: None,
+        }
+    }
+
+    /// Create a tile which is drawn using the current frame of an animation in `TileAnimations`.
+    /// The given sprite is used when the animation can not be found.
+    pub fn animated(sprite: usize, flip: Flip, animation: usize) -> Self {
+        Self {
+            sprite,
+            flip,
+            animation: Some(animation),
+        }
+    }
+
+    /// The index of the sprite used to draw this tile. Flipped tiles are drawn with their own
+    /// sprite so this already accounts for the tile's orientation.
+    pub fn sprite_index(&self) -> usize {
+        self.sprite
+    }
+
+    /// The orientation the tile was placed with in Tiled
+    pub fn flip(&self) -> Flip {
+        self.flip
+    }
+
+    /// The id of the tile's animation within `TileAnimations`
+    pub fn animation(&self) -> Option<usize> {
+        self.animation
+    }
+}
 
 impl From<usize> for TileGid {
     fn from(idx: usize) -> Self {
-        Self(idx)
+        Self::new(idx, Flip::NONE)
     }
 }
 
 impl Tile for TileGid {
-    fn sprite(&self, _: Point3<u32>, _: &World) -> Option<usize> {
-        Some(self.0)
+    fn sprite(&self, _: Point3<u32>, world: &World) -> Option<usize> {
+        // Empty cells are skipped, since the tile maps of other atlas pages can not draw the
+        // placeholder from the first page
+        if *self == TileGid::default() {
+            return None;
+        }
+
+        match self.animation {
+            Some(id) => world
+                .try_fetch::<TileAnimations>()
+                .and_then(|animations| animations.sprite(id))
+                .or(Some(self.sprite)),
+            None => Some(self.sprite),
+        }
     }
 }
 
 fn load_sprite_sheet(
     packed: PackedSpriteSheet,
+    settings: PackSettings,
     loader: &Loader,
     progress: &mut ProgressCounter,
     storage: &AssetStorage<Texture>,
@@ -57,32 +219,58 @@ fn load_sprite_sheet(
     #[cfg(feature = "profiler")]
     profile_scope!("load_sprite_sheet");
 
-    let sprites = encode::<AmethystOrderedFormat>(&packed, ());
+    let sprites = encode::<AmethystOrderedFormat>(&packed, settings);
 
     let (width, height) = packed.dimensions;
 
+    SpriteSheet {
+        texture: load_texture(packed.bytes, width, height, loader, progress, storage),
+        sprites,
+    }
+}
+
+/// Load raw rgba8 pixel data as a texture
+fn load_texture(
+    bytes: Vec<u8>,
+    width: u32,
+    height: u32,
+    loader: &Loader,
+    progress: &mut ProgressCounter,
+    storage: &AssetStorage<Texture>,
+) -> Handle<Texture> {
     let texture_builder = TextureBuilder::new()
         .with_kind(Kind::D2(width, height, 1, 1))
         .with_view_kind(ViewKind::D2)
         .with_data_width(width)
         .with_data_height(height)
         .with_sampler_info(SamplerInfo::new(Filter::Nearest, WrapMode::Clamp))
-        .with_raw_data(packed.bytes, Rgba8Srgb::FORMAT);
+        .with_raw_data(bytes, Rgba8Srgb::FORMAT);
 
-    SpriteSheet {
-        texture: loader.load_from_data(texture_builder.into(), progress, storage),
-        sprites,
-    }
+    loader.load_from_data(texture_builder.into(), progress, storage)
 }
 
 fn load_tileset_inner(
-    _tileset: &Tileset,
-    _source: Arc<dyn Source>,
-    _loader: &Loader,
-    _progress: &mut ProgressCounter,
-    _storage: &AssetStorage<Texture>,
+    tileset: &Tileset,
+    source: Arc<dyn Source>,
+    loader: &Loader,
+    progress: &mut ProgressCounter,
+    storage: &AssetStorage<Texture>,
 ) -> Result<SpriteSheet, Error> {
-    unimplemented!()
+    #[cfg(feature = "profiler")]
+    profile_scope!("load_tileset");
+
+    // Tilesets loaded on their own are packed without padding
+    let settings = PackSettings::default();
+    let packed = pack_tileset(tileset, source, settings)?;
+    Ok(load_sprite_sheet(
+        packed, settings, loader, progress, storage,
+    ))
+}
+
+/// The images of a tileset are stored relative to the directory containing the tileset file
+fn tileset_directory(path: &Path) -> Arc<dyn Source> {
+    let directory = path.parent().unwrap_or_else(|| Path::new(""));
+    Arc::new(Directory::new(directory))
 }
 
 pub fn load_tileset<P: AsRef<Path>>(
@@ -91,11 +279,12 @@ pub fn load_tileset<P: AsRef<Path>>(
     progress: &mut ProgressCounter,
     storage: &AssetStorage<Texture>,
 ) -> Result<SpriteSheet, Error> {
-    let tileset = parse_tileset(File::open(&path)?, 1)?;
+    let bytes = read_document(&path, fs::read(&path)?)?;
+    let tileset = parse_tileset(&bytes[..], 1)?;
 
     load_tileset_inner(
         &tileset,
-        Arc::new(Directory::new(path.as_ref())),
+        tileset_directory(path.as_ref()),
         loader,
         progress,
         storage,
@@ -110,35 +299,409 @@ pub fn load_cached_tileset<P: AsRef<Path>>(
     sprite_sheets: &mut AssetStorage<SpriteSheet>,
     tilesets: &Tilesets,
 ) -> Result<Handle<SpriteSheet>, Error> {
-    let tileset = parse_tileset(File::open(&path)?, 1)?;
+    let bytes = read_document(&path, fs::read(&path)?)?;
+    let tileset = parse_tileset(&bytes[..], 1)?;
+
+    let source = tileset_directory(path.as_ref());
+    let resolved = fs::canonicalize(&path).unwrap_or_else(|_| path.as_ref().to_path_buf());
+    let key = TilesetKey::new(resolved.to_string_lossy().into_owned(), &tileset);
 
-    match tilesets.get(&tileset.name) {
+    match tilesets.get(&key) {
         Some(handle) => Ok(handle),
         None => {
-            let sheet = load_tileset_inner(
-                &tileset,
-                Arc::new(Directory::new(path.as_ref())),
-                loader,
-                progress,
-                storage,
-            )?;
+            let sheet = load_tileset_inner(&tileset, source.clone(), loader, progress, storage)?;
+
+            let mut dependencies = Dependencies::new(source);
+            if let Some(file) = path.as_ref().file_name().and_then(|name| name.to_str()) {
+                dependencies.track(file);
+            }
+            dependencies.track_tileset(&tileset);
+
             let handle = sprite_sheets.insert(sheet);
-            tilesets.push(tileset.name.to_owned(), handle.clone());
+            tilesets.push_tracked(key, &handle, dependencies);
 
             Ok(handle)
         }
     }
 }
 
+/// Identifies the sprite sheet of a tileset by where the tileset was loaded from and a hash of the
+/// parts of it which are used to pack the sheet. The contents of its files are checked by
+/// `Tilesets` once they are modified.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TilesetKey {
+    pub path: String,
+    pub hash: u64,
+}
+
+impl TilesetKey {
+    pub fn new(path: String, tileset: &Tileset) -> Self {
+        let mut hasher = StableHasher::new();
+        hash_settings(tileset, &mut hasher);
+
+        Self {
+            path,
+            hash: hasher.finish(),
+        }
+    }
+
+    /// The key of a tileset which was not loaded from its own file. The tileset is identified by the
+    /// path of its first image instead.
+    pub fn of_tileset(tileset: &Tileset) -> Self {
+        let path = match tileset.images.first() {
+            Some(image) => image.source.clone(),
+            None => tileset.name.clone(),
+        };
+
+        Self::new(path, tileset)
+    }
+}
+
+/// Hash the settings of a tileset which are used to pack it along with the paths of its images
+fn hash_settings<H: Hasher>(tileset: &Tileset, hasher: &mut H) {
+    hash_layout(tileset, hasher);
+
+    for image in &tileset.images {
+        hash_bytes(image.source.as_bytes(), hasher);
+    }
+}
+
+/// Hash the settings of a tileset which are used to pack it. Image paths are left out since they
+/// depend on where the tileset was loaded from.
+fn hash_layout<H: Hasher>(tileset: &Tileset, hasher: &mut H) {
+    hasher.write_u32(tileset.tile_width);
+    hasher.write_u32(tileset.tile_height);
+    hasher.write_u32(tileset.spacing);
+    hasher.write_u32(tileset.margin);
+    match tileset.tilecount {
+        Some(v) => {
+            hasher.write_u8(1);
+            hasher.write_u32(v);
+        }
+        None => hasher.write_u8(0),
+    }
+
+    hasher.write_u64(tileset.images.len() as u64);
+    for image in &tileset.images {
+        hasher.write_u32(image.width as u32);
+        hasher.write_u32(image.height as u32);
+
+        match &image.transparent_colour {
+            Some(colour) => {
+                hasher.write_u8(1);
+                hasher.write(&[colour.red, colour.green, colour.blue]);
+            }
+            None => hasher.write_u8(0),
+        }
+    }
+}
+
+/// Hash the parts of a tileset which are used to pack it, including the contents of its images.
+/// The hash is the same wherever the tileset is loaded from, so an atlas baked next to a map
+/// matches the map when it is loaded from the asset directory.
+pub(crate) fn hash_tileset<H: Hasher>(
+    tileset: &Tileset,
+    source: &dyn Source,
+    hasher: &mut H,
+) -> Result<(), Error> {
+    #[cfg(feature = "profiler")]
+    profile_scope!("hash_tileset");
+
+    hash_layout(tileset, hasher);
+
+    for image in &tileset.images {
+        match source.load(&image.source) {
+            Ok(bytes) => hash_bytes(&bytes[..], hasher),
+            Err(_) => {
+                return Err(Error::from_string(format!(
+                    "Unable to open image path: {:?}",
+                    &image.source
+                )))
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// A sprite sheet in the `Tilesets` cache and the files it was loaded from
+struct CachedSheet {
+    handle: WeakHandle<SpriteSheet>,
+    dependencies: Option<Dependencies>,
+    /// The hash of the contents of the files when the sheet was added
+    contents: Option<u64>,
+}
+
+impl CachedSheet {
+    /// Check the files of the sheet have not changed since it was added. Their contents are only
+    /// hashed again once one of them has been modified.
+    fn is_current(&mut self) -> bool {
+        let dependencies = match &mut self.dependencies {
+            Some(v) => v,
+            None => return true,
+        };
+
+        if !dependencies.is_stale() {
+            return true;
+        }
+
+        match self.contents.is_some() && dependencies.hash_contents() == self.contents {
+            true => {
+                dependencies.refresh();
+                true
+            }
+            false => false,
+        }
+    }
+}
+
+/// Sprite sheets of tilesets shared between maps. The cache does not keep sprite sheets alive, so
+/// entries are dropped once a sheet is no longer referenced anywhere else.
 #[derive(Default)]
-pub struct Tilesets(Mutex<HashMap<String, Handle<SpriteSheet>>>);
+pub struct Tilesets(Mutex<HashMap<TilesetKey, CachedSheet>>);
 
 impl Tilesets {
-    pub fn push(&self, set_name: String, handle: Handle<SpriteSheet>) {
-        self.0.lock().unwrap().insert(set_name, handle);
+    pub fn push(&self, key: TilesetKey, handle: &Handle<SpriteSheet>) {
+        self.insert(key, handle, None);
+    }
+
+    /// Add a sprite sheet which can be invalidated using any of the files it was loaded from
+    pub fn push_tracked(
+        &self,
+        key: TilesetKey,
+        handle: &Handle<SpriteSheet>,
+        dependencies: Dependencies,
+    ) {
+        self.insert(key, handle, Some(dependencies));
+    }
+
+    fn insert(
+        &self,
+        key: TilesetKey,
+        handle: &Handle<SpriteSheet>,
+        dependencies: Option<Dependencies>,
+    ) {
+        let mut sheets = self.0.lock().unwrap();
+        sheets.retain(|_, sheet| !sheet.handle.is_dead());
+
+        let sheet = CachedSheet {
+            handle: handle.downgrade(),
+            contents: dependencies.as_ref().and_then(Dependencies::hash_contents),
+            dependencies,
+        };
+        sheets.insert(key, sheet);
+    }
+
+    /// Find the sprite sheet of a tileset, dropping it if the contents of its files have changed
+    pub fn get(&self, key: &TilesetKey) -> Option<Handle<SpriteSheet>> {
+        let mut sheets = self.0.lock().unwrap();
+        let handle = match sheets.get_mut(key) {
+            Some(sheet) => match sheet.is_current() {
+                true => sheet.handle.upgrade(),
+                false => None,
+            },
+            None => None,
+        };
+
+        if handle.is_none() {
+            sheets.remove(key);
+        }
+
+        handle
+    }
+
+    pub fn remove(&self, key: &TilesetKey) -> Option<Handle<SpriteSheet>> {
+        self.0
+            .lock()
+            .unwrap()
+            .remove(key)
+            .and_then(|sheet| sheet.handle.upgrade())
+    }
+
+    /// Remove every sprite sheet loaded from the given file
+    pub fn invalidate(&self, path: &str) {
+        self.0.lock().unwrap().retain(|key, sheet| {
+            let tracked = match &sheet.dependencies {
+                Some(dependencies) => dependencies.contains(path),
+                None => false,
+            };
+
+            key.path != path && !tracked
+        });
+    }
+
+    /// Remove every sprite sheet whose files have changed since it was loaded
+    pub fn invalidate_stale(&self) {
+        self.0.lock().unwrap().retain(|_, sheet| sheet.is_current());
+    }
+
+    /// Drop the entries of sprite sheets which are no longer referenced
+    pub fn evict_unused(&self) {
+        self.0
+            .lock()
+            .unwrap()
+            .retain(|_, sheet| !sheet.handle.is_dead());
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::time::{Duration, SystemTime};
+
+    fn tileset(image: &str) -> Tileset {
+        let document = format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<tileset version="1.2" name="tiles" tilewidth="16" tileheight="16" tilecount="4" columns="2">
+ <image source="{}" width="32" height="32"/>
+</tileset>"#,
+            image
+        );
+
+        parse_tileset(document.as_bytes(), 1).unwrap()
+    }
+
+    fn write_modified(path: &Path, bytes: &[u8], seconds: u64) {
+        fs::write(path, bytes).unwrap();
+        let file = File::options().write(true).open(path).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(seconds))
+            .unwrap();
+    }
+
+    #[test]
+    fn tilesets_sharing_a_name_get_their_own_sheet() {
+        let storage = AssetStorage::<SpriteSheet>::default();
+        let tilesets = Tilesets::default();
+
+        let (forest, desert) = (tileset("forest/tiles.png"), tileset("desert/tiles.png"));
+        let keys = [
+            TilesetKey::of_tileset(&forest),
+            TilesetKey::of_tileset(&desert),
+            TilesetKey::new("forest/tiles.tsx".to_owned(), &forest),
+            TilesetKey::new("desert/tiles.tsx".to_owned(), &forest),
+        ];
+        let handles: Vec<Handle<SpriteSheet>> = keys.iter().map(|_| storage.allocate()).collect();
+
+        for (key, handle) in keys.iter().zip(&handles) {
+            tilesets.push(key.clone(), handle);
+        }
+
+        assert_eq!(tilesets.len(), keys.len());
+        for (key, handle) in keys.iter().zip(&handles) {
+            assert_eq!(tilesets.get(key).as_ref(), Some(handle));
+        }
+    }
+
+    #[test]
+    fn tileset_keys_are_stable() {
+        let key = TilesetKey::of_tileset(&tileset("tiles.png"));
+        assert_eq!(key.path, "tiles.png");
+        assert_eq!(key.hash, 0xf5d8_7c0f_e694_7d56);
+
+        let moved = TilesetKey::of_tileset(&tileset("other/tiles.png"));
+        assert_ne!(moved.hash, key.hash);
+    }
+
+    #[test]
+    fn modified_sheets_are_dropped_when_their_contents_change() {
+        let directory =
+            std::env::temp_dir().join(format!("amethyst-tiled-tilesets-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let image = directory.join("tiles.png");
+        fs::write(&image, b"first").unwrap();
+
+        let mut dependencies = Dependencies::new(Arc::new(Directory::new(&directory)));
+        dependencies.track("tiles.png");
+
+        let storage = AssetStorage::<SpriteSheet>::default();
+        let handle = storage.allocate();
+        let key = TilesetKey::of_tileset(&tileset("tiles.png"));
+
+        let tilesets = Tilesets::default();
+        tilesets.push_tracked(key.clone(), &handle, dependencies);
+        assert_eq!(tilesets.get(&key).as_ref(), Some(&handle));
+
+        // Saving the file without changing it keeps the sheet
+        write_modified(&image, b"first", 60);
+        assert_eq!(tilesets.get(&key).as_ref(), Some(&handle));
+
+        write_modified(&image, b"second", 120);
+        assert_eq!(tilesets.get(&key), None);
+        assert!(tilesets.is_empty());
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn unused_sheets_are_evicted() {
+        let storage = AssetStorage::<SpriteSheet>::default();
+        let tilesets = Tilesets::default();
+        let key = TilesetKey::of_tileset(&tileset("tiles.png"));
+
+        let handle = storage.allocate();
+        tilesets.push(key.clone(), &handle);
+        drop(handle);
+
+        assert_eq!(tilesets.get(&key), None);
+        assert!(tilesets.is_empty());
     }
 
-    pub fn get(&self, set_name: &str) -> Option<Handle<SpriteSheet>> {
-        self.0.lock().unwrap().get(set_name).cloned()
+    /// Every combination of flip flags
+    fn flips() -> Vec<Flip> {
+        (0..8)
+            .map(|x| Flip {
+                horizontal: x & 1 != 0,
+                vertical: x & 2 != 0,
+                diagonal: x & 4 != 0,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn flip_flags_round_trip() {
+        for flip in flips() {
+            for gid in [0, 1, 25200, FLIPPED_DIAGONALLY_FLAG - 1].iter() {
+                let raw = flip.encode(*gid);
+                assert_eq!(Flip::decode(raw), (*gid, flip));
+            }
+        }
+    }
+
+    #[test]
+    fn flip_flags_use_the_tiled_bits() {
+        let raw = 7 | FLIPPED_HORIZONTALLY_FLAG | FLIPPED_DIAGONALLY_FLAG;
+        let flip = Flip {
+            horizontal: true,
+            vertical: false,
+            diagonal: true,
+        };
+
+        assert_eq!(Flip::decode(raw), (7, flip));
+        assert!(Flip::decode(7 | FLIPPED_VERTICALLY_FLAG).1.vertical);
+
+        // Flags already set on the gid are replaced
+        assert_eq!(Flip::NONE.encode(raw), 7);
+    }
+
+    #[test]
+    fn mirror_drops_the_diagonal_flag() {
+        for flip in flips() {
+            let mirror = flip.mirror();
+            assert!(!mirror.diagonal);
+            assert_eq!(
+                (mirror.horizontal, mirror.vertical),
+                (flip.horizontal, flip.vertical)
+            );
+            assert_eq!(flip.is_flipped(), flip != Flip::NONE);
+        }
     }
 }
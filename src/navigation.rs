@@ -0,0 +1,611 @@
+//! Walkability grids built from tile properties along with A* pathfinding over them
+
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+
+use amethyst::core::math::Point3;
+use amethyst::ecs::{Entities, Entity, System, Write};
+use tiled::{Map, Properties, PropertyValue};
+
+use crate::bounds::{layer_tiles, MapBounds};
+use crate::Flip;
+
+#[cfg(feature = "profiler")]
+use thread_profiler::profile_scope;
+
+/// The boolean property marking a tile or layer as blocking movement when set to false
+pub const WALKABLE_PROPERTY: &str = "walkable";
+
+/// The numeric property giving the cost of moving onto a tile, or every tile of a layer
+pub const COST_PROPERTY: &str = "cost";
+
+/// Which neighbours of a cell can be moved to in a single step
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Movement {
+    /// Only up, down, left and right
+    Four,
+    /// Diagonals are included and cost `sqrt(2)` times as much
+    Eight,
+}
+
+/// When diagonal steps are allowed past blocked cells
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CornerCutting {
+    /// Diagonal steps are always allowed, even squeezing between two blocked cells
+    Always,
+    /// Diagonal steps are allowed as long as one of the two cells beside the step is walkable
+    IfOneOpen,
+    /// Diagonal steps require both cells beside the step to be walkable
+    Never,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PathOptions {
+    pub movement: Movement,
+    pub corner_cutting: CornerCutting,
+}
+
+impl Default for PathOptions {
+    fn default() -> Self {
+        Self {
+            movement: Movement::Four,
+            corner_cutting: CornerCutting::Never,
+        }
+    }
+}
+
+/// A path found by `NavGrid::find_path`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Path {
+    /// Every cell of the path, including the start and the goal
+    pub cells: Vec<(u32, u32)>,
+    /// The total cost of every step along the path
+    pub cost: f32,
+}
+
+/// The walkability and movement cost of a tile, where `None` means the tile blocks movement
+fn tile_cost(properties: &Properties) -> Option<Option<f32>> {
+    let walkable = match properties.get(WALKABLE_PROPERTY) {
+        Some(PropertyValue::BoolValue(v)) => Some(*v),
+        _ => None,
+    };
+
+    let cost = match properties.get(COST_PROPERTY) {
+        Some(PropertyValue::FloatValue(v)) => Some(*v),
+        Some(PropertyValue::IntValue(v)) => Some(*v as f32),
+        _ => None,
+    };
+
+    match (walkable, cost) {
+        (Some(false), _) => Some(None),
+        (_, Some(cost)) => Some(Some(cost.max(0.0))),
+        (Some(true), None) => Some(Some(1.0)),
+        (None, None) => None,
+    }
+}
+
+/// A grid of the movement cost of every cell in a map, built from the `walkable` and `cost`
+/// properties of tiles and layers. Cells default to walkable with a cost of 1, any blocking tile
+/// blocks its cell and otherwise the most expensive tile in the cell is used. Coordinates match
+/// the positions within a `TileMap` loaded from the same map.
+#[derive(Debug, Clone)]
+pub struct NavGrid {
+    width: u32,
+    height: u32,
+    layers: u32,
+    /// The layer index in Tiled of every tile layer, which is used as the z coordinate of a tile
+    /// within a `TileMap`
+    layer_indices: Vec<u32>,
+    /// The gid of the tile in every cell of every layer, kept so single cells can be recomputed
+    gids: Vec<u32>,
+    tile_costs: HashMap<u32, Option<f32>>,
+    layer_costs: Vec<Option<Option<f32>>>,
+    costs: Vec<Option<f32>>,
+    version: u64,
+}
+
+impl NavGrid {
+    pub fn of(map: &Map, bounds: &MapBounds) -> Self {
+        #[cfg(feature = "profiler")]
+        profile_scope!("build_nav_grid");
+
+        let mut tile_costs = HashMap::new();
+        for set in &map.tilesets {
+            let set = set.unwrap();
+            for tile in &set.tiles {
+                if let Some(cost) = tile_cost(&tile.properties) {
+                    tile_costs.insert(set.first_gid + tile.id, cost);
+                }
+            }
+        }
+
+        let size = (bounds.width * bounds.height) as usize;
+        let mut gids = vec![0; size * map.layers.len()];
+
+        for (index, layer) in map.layers.iter().enumerate() {
+            for (x, y, tile) in layer_tiles(layer) {
+                if let Some((x, y)) = bounds.to_local(x, y) {
+                    let (gid, _) = Flip::decode(tile.gid);
+                    gids[index * size + (y * bounds.width + x) as usize] = gid;
+                }
+            }
+        }
+
+        let mut grid = Self {
+            width: bounds.width,
+            height: bounds.height,
+            layers: map.layers.len() as u32,
+            layer_indices: map.layers.iter().map(|x| x.layer_index).collect(),
+            gids,
+            tile_costs,
+            layer_costs: map
+                .layers
+                .iter()
+                .map(|x| tile_cost(&x.properties))
+                .collect(),
+            costs: vec![Some(1.0); size],
+            version: 0,
+        };
+
+        for y in 0..grid.height {
+            for x in 0..grid.width {
+                grid.update_cell(x, y);
+            }
+        }
+
+        grid
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Incremented every time a cell changes so cached paths can be discarded
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// The cost of moving onto a cell, or `None` if the cell blocks movement or is outside of the
+    /// grid
+    pub fn cost(&self, x: u32, y: u32) -> Option<f32> {
+        match x < self.width && y < self.height {
+            true => self.costs[(y * self.width + x) as usize],
+            false => None,
+        }
+    }
+
+    pub fn is_walkable(&self, x: u32, y: u32) -> bool {
+        self.cost(x, y).is_some()
+    }
+
+    /// Override the cost of a cell until one of its tiles is changed with `set_tile`
+    pub fn set_cost(&mut self, x: u32, y: u32, cost: Option<f32>) {
+        assert!(
+            x < self.width && y < self.height,
+            "Cell is outside of the grid"
+        );
+        self.costs[(y * self.width + x) as usize] = cost.map(|x| x.max(0.0));
+        self.version += 1;
+    }
+
+    /// Update the grid after the tile in a cell has changed. The gid is the tile's gid in Tiled
+    /// with or without its flip flags, or 0 for an empty cell.
+    pub fn set_tile(&mut self, x: u32, y: u32, layer: u32, gid: u32) {
+        assert!(
+            x < self.width && y < self.height && layer < self.layers,
+            "Cell is outside of the grid"
+        );
+
+        let (gid, _) = Flip::decode(gid);
+        let size = (self.width * self.height) as usize;
+        self.gids[layer as usize * size + (y * self.width + x) as usize] = gid;
+        self.update_cell(x, y);
+    }
+
+    /// Update the grid after a tile was placed in a cell of a `TileMap` loaded from the same map,
+    /// where the z coordinate is the tile's layer index. Cells outside of the grid are ignored.
+    pub fn place_tile(&mut self, position: &Point3<u32>, gid: u32) {
+        let layer = self.layer_indices.iter().position(|x| *x == position.z);
+
+        if let Some(layer) = layer {
+            if position.x < self.width && position.y < self.height {
+                self.set_tile(position.x, position.y, layer as u32, gid);
+            }
+        }
+    }
+
+    /// Recompute the cost of a cell from the tiles placed in it
+    fn update_cell(&mut self, x: u32, y: u32) {
+        let size = (self.width * self.height) as usize;
+        let index = (y * self.width + x) as usize;
+        let mut cost: Option<Option<f32>> = None;
+
+        for layer in 0..self.layers as usize {
+            let gid = self.gids[layer * size + index];
+            if gid == 0 {
+                continue;
+            }
+
+            let tile = match self.tile_costs.get(&gid) {
+                Some(v) => Some(*v),
+                None => self.layer_costs[layer],
+            };
+
+            cost = match (cost, tile) {
+                (Some(None), _) | (_, Some(None)) => Some(None),
+                (Some(Some(a)), Some(Some(b))) => Some(Some(a.max(b))),
+                (a, b) => a.or(b),
+            };
+        }
+
+        self.costs[index] = cost.unwrap_or(Some(1.0));
+        self.version += 1;
+    }
+
+    fn neighbours(&self, (x, y): (u32, u32), options: &PathOptions) -> Vec<((u32, u32), f32)> {
+        let mut neighbours = Vec::with_capacity(8);
+        let (x, y) = (x as i64, y as i64);
+
+        let walkable = |x: i64, y: i64| x >= 0 && y >= 0 && self.is_walkable(x as u32, y as u32);
+
+        for (dx, dy) in [(0, -1), (-1, 0), (1, 0), (0, 1)] {
+            if walkable(x + dx, y + dy) {
+                neighbours.push((((x + dx) as u32, (y + dy) as u32), 1.0));
+            }
+        }
+
+        if options.movement == Movement::Eight {
+            for (dx, dy) in [(-1, -1), (1, -1), (-1, 1), (1, 1)] {
+                if !walkable(x + dx, y + dy) {
+                    continue;
+                }
+
+                let open = (walkable(x + dx, y), walkable(x, y + dy));
+                let allowed = match options.corner_cutting {
+                    CornerCutting::Always => true,
+                    CornerCutting::IfOneOpen => open.0 || open.1,
+                    CornerCutting::Never => open.0 && open.1,
+                };
+
+                if allowed {
+                    neighbours.push((((x + dx) as u32, (y + dy) as u32), 2f32.sqrt()));
+                }
+            }
+        }
+
+        neighbours
+    }
+
+    /// An estimate of the cost between two cells which never overestimates the real cost
+    fn heuristic(&self, a: (u32, u32), b: (u32, u32), options: &PathOptions, min_cost: f32) -> f32 {
+        let dx = (a.0 as f32 - b.0 as f32).abs();
+        let dy = (a.1 as f32 - b.1 as f32).abs();
+
+        let distance = match options.movement {
+            Movement::Four => dx + dy,
+            Movement::Eight => dx.max(dy) + (2f32.sqrt() - 1.0) * dx.min(dy),
+        };
+
+        distance * min_cost
+    }
+
+    /// Find the cheapest path between two cells with A*. Moving onto a cell costs the cell's cost,
+    /// multiplied by `sqrt(2)` for diagonal steps. Returns `None` if either cell is blocked or the
+    /// goal can not be reached.
+    pub fn find_path(
+        &self,
+        start: (u32, u32),
+        goal: (u32, u32),
+        options: &PathOptions,
+    ) -> Option<Path> {
+        #[cfg(feature = "profiler")]
+        profile_scope!("find_path");
+
+        if !self.is_walkable(start.0, start.1) || !self.is_walkable(goal.0, goal.1) {
+            return None;
+        }
+
+        let min_cost = self.costs.iter().flatten().fold(f32::MAX, |a, b| a.min(*b));
+
+        let mut open = BinaryHeap::new();
+        let mut came_from: HashMap<(u32, u32), (u32, u32)> = HashMap::new();
+        let mut best: HashMap<(u32, u32), f32> = HashMap::new();
+
+        best.insert(start, 0.0);
+        open.push(OpenCell {
+            estimate: self.heuristic(start, goal, options, min_cost),
+            cost: 0.0,
+            cell: start,
+        });
+
+        while let Some(OpenCell { cost, cell, .. }) = open.pop() {
+            if cell == goal {
+                let mut cells = vec![goal];
+                while let Some(previous) = came_from.get(cells.last().unwrap()) {
+                    cells.push(*previous);
+                }
+                cells.reverse();
+
+                return Some(Path { cells, cost });
+            }
+
+            // Skip entries which were replaced by a cheaper route after being queued
+            if cost > best[&cell] {
+                continue;
+            }
+
+            for (next, distance) in self.neighbours(cell, options) {
+                let step = match self.cost(next.0, next.1) {
+                    Some(v) => v * distance,
+                    None => continue,
+                };
+
+                let next_cost = cost + step;
+                if best.get(&next).map(|x| next_cost < *x).unwrap_or(true) {
+                    best.insert(next, next_cost);
+                    came_from.insert(next, cell);
+                    open.push(OpenCell {
+                        estimate: next_cost + self.heuristic(next, goal, options, min_cost),
+                        cost: next_cost,
+                        cell: next,
+                    });
+                }
+            }
+        }
+
+        None
+    }
+}
+
+/// A cell waiting to be visited, ordered so the lowest estimate is popped first
+struct OpenCell {
+    estimate: f32,
+    cost: f32,
+    cell: (u32, u32),
+}
+
+impl PartialEq for OpenCell {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for OpenCell {}
+
+impl PartialOrd for OpenCell {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for OpenCell {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .estimate
+            .total_cmp(&self.estimate)
+            .then(self.cost.total_cmp(&other.cost))
+    }
+}
+
+/// Resource holding the navigation grid of every loaded map, keyed by the map entity. Grids of
+/// deleted maps are removed by `NavigationCleanupSystem`.
+#[derive(Debug, Default)]
+pub struct NavigationGrids(HashMap<Entity, NavGrid>);
+
+impl NavigationGrids {
+    pub fn insert(&mut self, map: Entity, grid: NavGrid) {
+        self.0.insert(map, grid);
+    }
+
+    pub fn get(&self, map: Entity) -> Option<&NavGrid> {
+        self.0.get(&map)
+    }
+
+    pub fn get_mut(&mut self, map: Entity) -> Option<&mut NavGrid> {
+        self.0.get_mut(&map)
+    }
+
+    /// Remove the grid of a map which is no longer used
+    pub fn remove(&mut self, map: Entity) -> Option<NavGrid> {
+        self.0.remove(&map)
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Remove the grids of every map entity which has been deleted
+    pub fn maintain(&mut self, entities: &Entities<'_>) {
+        self.0.retain(|map, _| entities.is_alive(*map));
+    }
+}
+
+/// System which removes the navigation grids of deleted map entities
+#[derive(Debug, Default)]
+pub struct NavigationCleanupSystem;
+
+impl<'a> System<'a> for NavigationCleanupSystem {
+    type SystemData = (Entities<'a>, Write<'a, NavigationGrids>);
+
+    fn run(&mut self, (entities, mut grids): Self::SystemData) {
+        #[cfg(feature = "profiler")]
+        profile_scope!("navigation_cleanup_system");
+
+        grids.maintain(&entities);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build a grid from rows where `#` marks a blocked cell, a digit gives the cost of a cell and
+    /// any other character is a cell with a cost of 1
+    fn grid(rows: &[&str]) -> NavGrid {
+        let width = rows.first().map(|x| x.len()).unwrap_or(0) as u32;
+        let costs = rows
+            .iter()
+            .flat_map(|row| row.chars())
+            .map(|cell| match cell {
+                '#' => None,
+                _ => Some(cell.to_digit(10).unwrap_or(1) as f32),
+            })
+            .collect();
+
+        NavGrid {
+            width,
+            height: rows.len() as u32,
+            layers: 0,
+            layer_indices: Vec::new(),
+            gids: Vec::new(),
+            tile_costs: HashMap::new(),
+            layer_costs: Vec::new(),
+            costs,
+            version: 0,
+        }
+    }
+
+    fn options(movement: Movement, corner_cutting: CornerCutting) -> PathOptions {
+        PathOptions {
+            movement,
+            corner_cutting,
+        }
+    }
+
+    fn assert_cost(path: &Path, cost: f32) {
+        assert!(
+            (path.cost - cost).abs() < 1e-5,
+            "expected a cost of {} but found {}",
+            cost,
+            path.cost
+        );
+    }
+
+    #[test]
+    fn four_neighbours_move_along_axes() {
+        let grid = grid(&["...", "...", "..."]);
+        let path = grid
+            .find_path((0, 0), (2, 2), &PathOptions::default())
+            .expect("The goal should be reachable");
+
+        assert_eq!(path.cells.len(), 5);
+        assert_eq!(path.cells.first(), Some(&(0, 0)));
+        assert_eq!(path.cells.last(), Some(&(2, 2)));
+        assert_cost(&path, 4.0);
+
+        for step in path.cells.windows(2) {
+            let dx = (step[0].0 as i64 - step[1].0 as i64).abs();
+            let dy = (step[0].1 as i64 - step[1].1 as i64).abs();
+            assert_eq!(dx + dy, 1, "{:?} should be a single step", step);
+        }
+    }
+
+    #[test]
+    fn eight_neighbours_move_diagonally() {
+        let grid = grid(&["...", "...", "..."]);
+        let options = options(Movement::Eight, CornerCutting::Never);
+        let path = grid
+            .find_path((0, 0), (2, 2), &options)
+            .expect("The goal should be reachable");
+
+        assert_eq!(path.cells, vec![(0, 0), (1, 1), (2, 2)]);
+        assert_cost(&path, 2.0 * 2f32.sqrt());
+    }
+
+    #[test]
+    fn corner_cutting_between_two_blocked_cells() {
+        let grid = grid(&[".#", "#."]);
+
+        let never = options(Movement::Eight, CornerCutting::Never);
+        assert_eq!(grid.find_path((0, 0), (1, 1), &never), None);
+
+        let one_open = options(Movement::Eight, CornerCutting::IfOneOpen);
+        assert_eq!(grid.find_path((0, 0), (1, 1), &one_open), None);
+
+        let always = options(Movement::Eight, CornerCutting::Always);
+        let path = grid
+            .find_path((0, 0), (1, 1), &always)
+            .expect("The diagonal step should be allowed");
+        assert_eq!(path.cells, vec![(0, 0), (1, 1)]);
+        assert_cost(&path, 2f32.sqrt());
+    }
+
+    #[test]
+    fn corner_cutting_past_one_blocked_cell() {
+        let grid = grid(&[".#", ".."]);
+
+        let never = options(Movement::Eight, CornerCutting::Never);
+        let path = grid
+            .find_path((0, 0), (1, 1), &never)
+            .expect("The goal should be reachable around the corner");
+        assert_eq!(path.cells, vec![(0, 0), (0, 1), (1, 1)]);
+        assert_cost(&path, 2.0);
+
+        let one_open = options(Movement::Eight, CornerCutting::IfOneOpen);
+        let path = grid
+            .find_path((0, 0), (1, 1), &one_open)
+            .expect("The diagonal step should be allowed");
+        assert_eq!(path.cells, vec![(0, 0), (1, 1)]);
+        assert_cost(&path, 2f32.sqrt());
+    }
+
+    #[test]
+    fn expensive_cells_are_avoided() {
+        let grid = grid(&[".5.", "..."]);
+        let path = grid
+            .find_path((0, 0), (2, 0), &PathOptions::default())
+            .expect("The goal should be reachable");
+
+        assert_eq!(path.cells, vec![(0, 0), (0, 1), (1, 1), (2, 1), (2, 0)]);
+        assert_cost(&path, 4.0);
+    }
+
+    #[test]
+    fn expensive_cells_are_crossed_when_cheaper() {
+        let grid = grid(&[".2.", "...", "..."]);
+        let path = grid
+            .find_path((0, 0), (2, 0), &PathOptions::default())
+            .expect("The goal should be reachable");
+
+        assert_eq!(path.cells, vec![(0, 0), (1, 0), (2, 0)]);
+        assert_cost(&path, 3.0);
+    }
+
+    #[test]
+    fn unreachable_goal_has_no_path() {
+        let grid = grid(&[".#.", ".#.", ".#."]);
+        let eight = options(Movement::Eight, CornerCutting::Always);
+
+        assert_eq!(
+            grid.find_path((0, 0), (2, 2), &PathOptions::default()),
+            None
+        );
+        assert_eq!(grid.find_path((0, 0), (2, 2), &eight), None);
+    }
+
+    #[test]
+    fn blocked_endpoints_have_no_path() {
+        let grid = grid(&["..#", "...", "..."]);
+
+        assert_eq!(
+            grid.find_path((0, 0), (2, 0), &PathOptions::default()),
+            None
+        );
+        assert_eq!(
+            grid.find_path((2, 0), (0, 0), &PathOptions::default()),
+            None
+        );
+        assert_eq!(
+            grid.find_path((0, 0), (5, 5), &PathOptions::default()),
+            None
+        );
+    }
+}
@@ -0,0 +1,135 @@
+//! Entities created from the objects placed in a map's object layers
+
+use amethyst::core::Transform;
+use amethyst::ecs::{Component, DenseVecStorage, Entity, WriteStorage};
+use amethyst::Error;
+use tiled::{Map, Object, ObjectShape, Properties};
+
+use crate::bounds::MapBounds;
+use crate::prefab::{create_child, ChildData};
+
+#[cfg(feature = "profiler")]
+use thread_profiler::profile_scope;
+
+/// Component describing an object placed in one of a map's object layers
+#[derive(Debug, Clone)]
+pub struct TileObject {
+    pub id: u32,
+    /// The name of the object layer the object belongs to
+    pub group: String,
+    pub obj_type: String,
+    /// The gid of the tile used to draw the object, or 0 if the object is not a tile
+    pub gid: u32,
+    pub shape: ObjectShape,
+    pub width: f32,
+    pub height: f32,
+    pub visible: bool,
+}
+
+impl Component for TileObject {
+    type Storage = DenseVecStorage<Self>;
+}
+
+/// Component holding the custom properties set in Tiled
+#[derive(Debug, Clone, Default)]
+pub struct TiledProperties(pub Properties);
+
+impl Component for TiledProperties {
+    type Storage = DenseVecStorage<Self>;
+}
+
+/// The data required to spawn the objects of a map
+pub type ObjectData<'a> = (
+    WriteStorage<'a, TileObject>,
+    WriteStorage<'a, TiledProperties>,
+);
+
+struct ObjectSpawn {
+    transform: Transform,
+    name: String,
+    object: TileObject,
+    properties: TiledProperties,
+}
+
+/// The objects found in a map which get spawned as children of the map entity
+#[derive(Default)]
+pub struct MapObjects(Vec<ObjectSpawn>);
+
+impl MapObjects {
+    pub fn new(map: &Map, bounds: &MapBounds) -> Self {
+        #[cfg(feature = "profiler")]
+        profile_scope!("collect_map_objects");
+
+        let mut spawns = Vec::new();
+
+        for group in &map.object_groups {
+            for object in &group.objects {
+                spawns.push(ObjectSpawn {
+                    transform: object_transform(bounds, object),
+                    name: object.name.to_owned(),
+                    object: TileObject {
+                        id: object.id,
+                        group: group.name.to_owned(),
+                        obj_type: object.obj_type.to_owned(),
+                        gid: object.gid,
+                        shape: object.shape.clone(),
+                        width: object.width,
+                        height: object.height,
+                        visible: object.visible,
+                    },
+                    properties: TiledProperties(object.properties.clone()),
+                });
+            }
+        }
+
+        Self(spawns)
+    }
+
+    /// Create an entity for every object as a child of the map entity
+    pub fn spawn(
+        &self,
+        parent: Entity,
+        child_data: &mut ChildData<'_>,
+        data: &mut ObjectData<'_>,
+    ) -> Result<(), Error> {
+        #[cfg(feature = "profiler")]
+        profile_scope!("spawn_map_objects");
+
+        let (objects, properties) = data;
+
+        for spawn in &self.0 {
+            // Objects are not drawn, so their visibility is only stored on the object component
+            let entity = create_child(
+                child_data,
+                parent,
+                &spawn.name,
+                spawn.transform.clone(),
+                true,
+                &spawn.properties.0,
+            )?;
+
+            let class = &spawn.object.obj_type;
+            child_data.registry.apply_class(
+                entity,
+                class,
+                &spawn.properties.0,
+                &child_data.lazy,
+            )?;
+
+            objects.insert(entity, spawn.object.clone())?;
+            properties.insert(entity, spawn.properties.clone())?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Tiled places objects by their top left corner and rotates them clockwise in degrees
+fn object_transform(bounds: &MapBounds, object: &Object) -> Transform {
+    let (x, y) = bounds.object_to_pixel(object.x, object.y);
+
+    let mut transform = Transform::default();
+    transform.set_translation(bounds.pixel_to_world(x, y));
+    transform.set_rotation_2d(-object.rotation.to_radians());
+    transform
+}
@@ -0,0 +1,487 @@
+//! Conversions between tile coordinates and positions for every map orientation supported by Tiled
+
+use amethyst::core::math::Vector3;
+use amethyst::Error;
+use tiled::{Map, Orientation};
+use xml::reader::{EventReader, XmlEvent};
+
+use crate::bounds::MapBounds;
+
+#[cfg(feature = "profiler")]
+use thread_profiler::profile_scope;
+
+/// The axis along which every other row or column of a staggered or hexagonal map is shifted
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum StaggerAxis {
+    X,
+    Y,
+}
+
+/// Whether the odd or even rows or columns of a staggered or hexagonal map are shifted
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum StaggerIndex {
+    Odd,
+    Even,
+}
+
+/// The layout of the tiles in a map
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum MapOrientation {
+    Orthogonal,
+    /// Diamond shaped tiles with the x axis pointing down and to the right
+    Isometric,
+    /// Diamond shaped tiles laid out in shifted rows or columns to fill a rectangle
+    Staggered {
+        axis: StaggerAxis,
+        index: StaggerIndex,
+    },
+    /// Hexagonal tiles where `side_length` is the length in pixels of the flat sides which run
+    /// along the stagger axis
+    Hexagonal {
+        axis: StaggerAxis,
+        index: StaggerIndex,
+        side_length: u32,
+    },
+}
+
+impl Default for MapOrientation {
+    fn default() -> Self {
+        MapOrientation::Orthogonal
+    }
+}
+
+impl MapOrientation {
+    /// Find the orientation of a map. The stagger settings are not read by the tiled crate, so
+    /// the defaults used by Tiled are assumed: rows are staggered, odd rows are shifted and the
+    /// sides of hexagons are half of the tile height. Use `parse_orientation` to read them from the
+    /// map file instead.
+    pub fn of(map: &Map) -> Self {
+        match map.orientation {
+            Orientation::Orthogonal => MapOrientation::Orthogonal,
+            Orientation::Isometric => MapOrientation::Isometric,
+            Orientation::Staggered => MapOrientation::Staggered {
+                axis: StaggerAxis::Y,
+                index: StaggerIndex::Odd,
+            },
+            Orientation::Hexagonal => MapOrientation::Hexagonal {
+                axis: StaggerAxis::Y,
+                index: StaggerIndex::Odd,
+                side_length: map.tile_height / 2,
+            },
+        }
+    }
+
+    /// The stagger settings along with the length of the flat sides, which is 0 for staggered maps
+    fn stagger(&self) -> Option<(StaggerAxis, StaggerIndex, f32)> {
+        match *self {
+            MapOrientation::Staggered { axis, index } => Some((axis, index, 0.0)),
+            MapOrientation::Hexagonal {
+                axis,
+                index,
+                side_length,
+            } => Some((axis, index, side_length as f32)),
+            _ => None,
+        }
+    }
+}
+
+/// Read the orientation of a map from its file, including the stagger settings which are not read
+/// by the tiled crate. Settings missing from the file keep the defaults used by `MapOrientation::of`.
+pub fn parse_orientation(bytes: &[u8], map: &Map) -> Result<MapOrientation, Error> {
+    #[cfg(feature = "profiler")]
+    profile_scope!("parse_orientation");
+
+    let (mut axis, mut index, mut side_length) = match MapOrientation::of(map) {
+        MapOrientation::Hexagonal {
+            axis,
+            index,
+            side_length,
+        } => (axis, index, side_length),
+        MapOrientation::Staggered { axis, index } => (axis, index, 0),
+        orientation => return Ok(orientation),
+    };
+
+    for event in EventReader::new(bytes) {
+        if let XmlEvent::StartElement {
+            name, attributes, ..
+        } = event.map_err(Error::new)?
+        {
+            if name.local_name != "map" {
+                continue;
+            }
+
+            for attribute in &attributes {
+                match (attribute.name.local_name.as_str(), attribute.value.as_str()) {
+                    ("staggeraxis", "x") => axis = StaggerAxis::X,
+                    ("staggeraxis", "y") => axis = StaggerAxis::Y,
+                    ("staggerindex", "odd") => index = StaggerIndex::Odd,
+                    ("staggerindex", "even") => index = StaggerIndex::Even,
+                    ("hexsidelength", value) => side_length = value.parse().map_err(Error::new)?,
+                    _ => {}
+                }
+            }
+            break;
+        }
+    }
+
+    Ok(match map.orientation {
+        Orientation::Hexagonal => MapOrientation::Hexagonal {
+            axis,
+            index,
+            side_length,
+        },
+        _ => MapOrientation::Staggered { axis, index },
+    })
+}
+
+fn is_staggered(index: StaggerIndex, coordinate: i32) -> bool {
+    match index {
+        StaggerIndex::Odd => coordinate.rem_euclid(2) == 1,
+        StaggerIndex::Even => coordinate.rem_euclid(2) == 0,
+    }
+}
+
+impl MapBounds {
+    fn tile_size(&self) -> (f32, f32) {
+        (self.tile_width as f32, self.tile_height as f32)
+    }
+
+    /// The size of the image Tiled would render for the bounds in pixels
+    pub(crate) fn pixel_size(&self) -> (f32, f32) {
+        let (tile_width, tile_height) = self.tile_size();
+        let (width, height) = (self.width as f32, self.height as f32);
+
+        match self.orientation.stagger() {
+            Some((StaggerAxis::Y, _, side)) => (
+                width * tile_width + tile_width / 2.0,
+                height * (tile_height + side) / 2.0 + (tile_height - side) / 2.0,
+            ),
+            Some((StaggerAxis::X, _, side)) => (
+                width * (tile_width + side) / 2.0 + (tile_width - side) / 2.0,
+                height * tile_height + tile_height / 2.0,
+            ),
+            None => match self.orientation {
+                MapOrientation::Isometric => (
+                    (width + height) * tile_width / 2.0,
+                    (width + height) * tile_height / 2.0,
+                ),
+                _ => (width * tile_width, height * tile_height),
+            },
+        }
+    }
+
+    /// The top left corner of a tile's cell where `(x, y)` is measured from the origin of the
+    /// bounds and `(stagger_x, stagger_y)` is the tile coordinate used to decide if it is shifted.
+    fn cell_corner(&self, (x, y): (i32, i32), (stagger_x, stagger_y): (i32, i32)) -> (f32, f32) {
+        let (tile_width, tile_height) = self.tile_size();
+        let (x, y) = (x as f32, y as f32);
+
+        match self.orientation.stagger() {
+            Some((StaggerAxis::Y, index, side)) => {
+                let shift = match is_staggered(index, stagger_y) {
+                    true => tile_width / 2.0,
+                    false => 0.0,
+                };
+                (x * tile_width + shift, y * (tile_height + side) / 2.0)
+            }
+            Some((StaggerAxis::X, index, side)) => {
+                let shift = match is_staggered(index, stagger_x) {
+                    true => tile_height / 2.0,
+                    false => 0.0,
+                };
+                (x * (tile_width + side) / 2.0, y * tile_height + shift)
+            }
+            None => match self.orientation {
+                MapOrientation::Isometric => {
+                    let origin = self.height as f32 * tile_width / 2.0;
+                    (
+                        (x - y) * tile_width / 2.0 + origin - tile_width / 2.0,
+                        (x + y) * tile_height / 2.0,
+                    )
+                }
+                _ => (x * tile_width, y * tile_height),
+            },
+        }
+    }
+
+    /// The offset between Tiled's pixel coordinates and pixels measured from the corner of the
+    /// bounds. This is only non zero for infinite maps with tiles left of or above the origin.
+    fn pixel_offset(&self) -> (f32, f32) {
+        let origin = (self.origin.x, self.origin.y);
+        let (x, y) = self.cell_corner(origin, origin);
+        let (local_x, local_y) = self.cell_corner((0, 0), origin);
+        (x - local_x, y - local_y)
+    }
+
+    /// Find the top left corner of a tile's cell in pixels measured from the top left corner of
+    /// the bounds. Tiles larger than their cell should be aligned to the bottom of the cell.
+    pub fn tile_to_pixel(&self, x: i32, y: i32) -> (f32, f32) {
+        self.cell_corner((x - self.origin.x, y - self.origin.y), (x, y))
+    }
+
+    /// Find the tile coordinate in Tiled of the cell containing a pixel measured from the top left
+    /// corner of the bounds. Positions outside of the bounds give coordinates outside of the
+    /// bounds.
+    pub fn pixel_to_tile(&self, x: f32, y: f32) -> (i32, i32) {
+        let (tile_width, tile_height) = self.tile_size();
+
+        let (local_x, local_y) = match self.orientation.stagger() {
+            Some(_) => return self.nearest_cell(x, y),
+            None => match self.orientation {
+                MapOrientation::Isometric => {
+                    let x = x - self.height as f32 * tile_width / 2.0;
+                    (
+                        y / tile_height + x / tile_width,
+                        y / tile_height - x / tile_width,
+                    )
+                }
+                _ => (x / tile_width, y / tile_height),
+            },
+        };
+
+        (
+            self.origin.x + local_x.floor() as i32,
+            self.origin.y + local_y.floor() as i32,
+        )
+    }
+
+    /// Staggered and hexagonal cells overlap on the grid, so pick the closest cell center among
+    /// the neighbours of a rough guess instead.
+    fn nearest_cell(&self, x: f32, y: f32) -> (i32, i32) {
+        let (tile_width, tile_height) = self.tile_size();
+        let (guess_x, guess_y) = match self.orientation.stagger() {
+            Some((StaggerAxis::Y, _, side)) => (x / tile_width, y / ((tile_height + side) / 2.0)),
+            Some((StaggerAxis::X, _, side)) => (x / ((tile_width + side) / 2.0), y / tile_height),
+            None => (x / tile_width, y / tile_height),
+        };
+
+        let guess = (
+            self.origin.x + guess_x.floor() as i32,
+            self.origin.y + guess_y.floor() as i32,
+        );
+
+        let mut best = guess;
+        let mut best_distance = f32::MAX;
+
+        for offset_y in -1..=1 {
+            for offset_x in -1..=1 {
+                let cell = (guess.0 + offset_x, guess.1 + offset_y);
+                let (left, top) = self.tile_to_pixel(cell.0, cell.1);
+                let dx = (x - left - tile_width / 2.0) / tile_width;
+                let dy = (y - top - tile_height / 2.0) / tile_height;
+
+                // Staggered cells are diamonds, which are the closest cells by manhattan distance
+                let distance = match self.orientation {
+                    MapOrientation::Staggered { .. } => dx.abs() + dy.abs(),
+                    _ => dx * dx + dy * dy,
+                };
+
+                if distance < best_distance {
+                    best = cell;
+                    best_distance = distance;
+                }
+            }
+        }
+
+        best
+    }
+
+    /// Convert an object position stored in Tiled to Tiled's pixel coordinates. Isometric maps
+    /// store objects in a projected space where both axes are measured in tile heights.
+    pub fn object_to_pixel(&self, x: f32, y: f32) -> (f32, f32) {
+        match self.orientation {
+            MapOrientation::Isometric => {
+                let (tile_width, tile_height) = self.tile_size();
+                let (x, y) = (x / tile_height, y / tile_height);
+                (
+                    (x - y) * tile_width / 2.0 + self.height as f32 * tile_width / 2.0,
+                    (x + y) * tile_height / 2.0,
+                )
+            }
+            _ => (x, y),
+        }
+    }
+
+    /// Convert a position in Tiled's pixel coordinates to the local space of the map entity. The
+    /// map is centered on its entity with the y axis pointing up, matching the layout of a
+    /// `TileMap`.
+    pub fn pixel_to_world(&self, x: f32, y: f32) -> Vector3<f32> {
+        let (offset_x, offset_y) = self.pixel_offset();
+        self.local_to_world(x - offset_x, y - offset_y)
+    }
+
+    /// Convert a pixel measured from the top left corner of the bounds to the local space of the
+    /// map entity
+    pub(crate) fn local_to_world(&self, x: f32, y: f32) -> Vector3<f32> {
+        let (width, height) = self.pixel_size();
+        Vector3::new(x - width / 2.0, height / 2.0 - y, 0.0)
+    }
+
+    /// Find the center of a tile's cell relative to the map entity. The z coordinate is the tile's
+    /// draw depth, so tiles further down the screen are drawn in front.
+    pub fn tile_to_world(&self, x: i32, y: i32) -> Vector3<f32> {
+        let (left, top) = self.tile_to_pixel(x, y);
+        let mut position = self.local_to_world(
+            left + self.tile_width as f32 / 2.0,
+            top + self.tile_height as f32 / 2.0,
+        );
+        position.z = self.draw_depth(x, y);
+        position
+    }
+
+    /// Find the tile coordinate in Tiled of the cell at a position relative to the map entity
+    pub fn world_to_tile(&self, position: &Vector3<f32>) -> (i32, i32) {
+        let (width, height) = self.pixel_size();
+        self.pixel_to_tile(position.x + width / 2.0, height / 2.0 - position.y)
+    }
+
+    /// The order tiles of a single layer must be drawn in, between 0 for the back and 1 for the
+    /// front. Tiled draws tiles lower on the screen over the tiles behind them, which matters for
+    /// isometric tiles taller than their cell.
+    pub fn draw_depth(&self, x: i32, y: i32) -> f32 {
+        let (_, top) = self.tile_to_pixel(x, y);
+        let (_, height) = self.pixel_size();
+
+        match height > 0.0 {
+            true => (top / height).clamp(0.0, 1.0) * 0.999,
+            false => 0.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use amethyst::core::math::Point2;
+
+    fn bounds(orientation: MapOrientation, tile_width: u32, tile_height: u32) -> MapBounds {
+        MapBounds {
+            origin: Point2::new(-3, -2),
+            width: 7,
+            height: 6,
+            tile_width,
+            tile_height,
+            orientation,
+        }
+    }
+
+    /// Every orientation, with each stagger axis and index, along with a tile size suited to it
+    fn orientations() -> Vec<MapBounds> {
+        let mut orientations = vec![
+            bounds(MapOrientation::Orthogonal, 16, 16),
+            bounds(MapOrientation::Isometric, 32, 16),
+        ];
+
+        for index in [StaggerIndex::Odd, StaggerIndex::Even].iter().copied() {
+            for axis in [StaggerAxis::X, StaggerAxis::Y].iter().copied() {
+                orientations.push(bounds(MapOrientation::Staggered { axis, index }, 32, 16));
+
+                let hexagonal = MapOrientation::Hexagonal {
+                    axis,
+                    index,
+                    side_length: 14,
+                };
+                orientations.push(match axis {
+                    StaggerAxis::X => bounds(hexagonal, 28, 32),
+                    StaggerAxis::Y => bounds(hexagonal, 32, 28),
+                });
+            }
+        }
+
+        orientations
+    }
+
+    fn tiles(bounds: &MapBounds) -> Vec<(i32, i32)> {
+        let (x, y) = (bounds.origin.x, bounds.origin.y);
+        (y..y + bounds.height as i32)
+            .flat_map(|y| (x..x + bounds.width as i32).map(move |x| (x, y)))
+            .collect()
+    }
+
+    #[test]
+    fn tile_centers_round_trip_through_pixels() {
+        for bounds in orientations() {
+            let (half_width, half_height) = (
+                bounds.tile_width as f32 / 2.0,
+                bounds.tile_height as f32 / 2.0,
+            );
+
+            for (x, y) in tiles(&bounds) {
+                let (left, top) = bounds.tile_to_pixel(x, y);
+                assert_eq!(
+                    bounds.pixel_to_tile(left + half_width, top + half_height),
+                    (x, y),
+                    "{:?}",
+                    bounds.orientation
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn points_near_the_center_stay_in_their_tile() {
+        for bounds in orientations() {
+            // A quarter of the way to the edge of the tile stays inside even diamond shaped cells
+            let (dx, dy) = (
+                bounds.tile_width as f32 / 8.0,
+                bounds.tile_height as f32 / 8.0,
+            );
+
+            for (x, y) in tiles(&bounds) {
+                let (left, top) = bounds.tile_to_pixel(x, y);
+                let (center_x, center_y) = (
+                    left + bounds.tile_width as f32 / 2.0,
+                    top + bounds.tile_height as f32 / 2.0,
+                );
+
+                for (offset_x, offset_y) in [(dx, 0.0), (-dx, 0.0), (0.0, dy), (0.0, -dy)].iter() {
+                    assert_eq!(
+                        bounds.pixel_to_tile(center_x + offset_x, center_y + offset_y),
+                        (x, y),
+                        "{:?}",
+                        bounds.orientation
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn tiles_round_trip_through_world_positions() {
+        for bounds in orientations() {
+            for (x, y) in tiles(&bounds) {
+                let position = bounds.tile_to_world(x, y);
+                assert_eq!(
+                    bounds.world_to_tile(&position),
+                    (x, y),
+                    "{:?}",
+                    bounds.orientation
+                );
+                assert!((0.0..1.0).contains(&position.z));
+            }
+        }
+    }
+
+    #[test]
+    fn stagger_settings_shift_alternate_cells() {
+        let staggered = |axis, index| bounds(MapOrientation::Staggered { axis, index }, 32, 16);
+
+        // Cells in the first column or row of the bounds are only moved by the stagger
+        let rows = staggered(StaggerAxis::Y, StaggerIndex::Odd);
+        assert_eq!(rows.tile_to_pixel(-3, 1).0, 16.0);
+        assert_eq!(rows.tile_to_pixel(-3, 2).0, 0.0);
+        assert_eq!(rows.tile_to_pixel(-3, -1).0, 16.0);
+
+        let rows = staggered(StaggerAxis::Y, StaggerIndex::Even);
+        assert_eq!(rows.tile_to_pixel(-3, 2).0, 16.0);
+        assert_eq!(rows.tile_to_pixel(-3, -1).0, 0.0);
+
+        let columns = staggered(StaggerAxis::X, StaggerIndex::Odd);
+        assert_eq!(columns.tile_to_pixel(1, -2).1, 8.0);
+        assert_eq!(columns.tile_to_pixel(0, -2).1, 0.0);
+
+        let columns = staggered(StaggerAxis::X, StaggerIndex::Even);
+        assert_eq!(columns.tile_to_pixel(0, -2).1, 8.0);
+        assert_eq!(columns.tile_to_pixel(-1, -2).1, 0.0);
+    }
+}
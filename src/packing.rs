@@ -3,39 +3,41 @@
 use amethyst::assets::Source;
 use amethyst::error::Error;
 use amethyst::renderer::sprite::Sprite;
-use image::{load_from_memory, GenericImage, Pixel, Rgba, RgbaImage};
+use image::imageops::{flip_horizontal, flip_vertical, rotate90};
+use image::{load_from_memory, GenericImage, GenericImageView, Pixel, Rgba, RgbaImage};
 use sheep::{
     pack, Format, InputSprite, Packer, PackerResult, SimplePacker, SpriteAnchor, SpriteData,
     SpriteSheet,
 };
+use std::collections::HashMap;
 use std::sync::Arc;
 use tiled::Image as TileImage;
 use tiled::Tileset;
 
+use crate::Flip;
+
 #[cfg(feature = "profiler")]
 use thread_profiler::profile_scope;
 
+/// The sprites of a packed sheet in the order they were given to the packer. The padding given as
+/// the options is removed so each sprite only covers the pixels of its tile.
 pub struct AmethystOrderedFormat;
 
 impl Format for AmethystOrderedFormat {
     type Data = Vec<Sprite>;
-    type Options = ();
+    type Options = PackSettings;
 
     fn encode(
         dimensions: (u32, u32),
         sprites: &[SpriteAnchor],
-        _options: Self::Options,
+        options: Self::Options,
     ) -> Self::Data {
         #[cfg(feature = "profiler")]
         profile_scope!("encode_amethyst_format");
 
-        // Fix ordering issues
-        let mut inputs = sprites.to_vec();
-        inputs.sort_by_key(|x| x.id);
-
         let (width, height) = dimensions;
 
-        inputs
+        OrderedAnchors::encode(dimensions, sprites, options)
             .iter()
             .map(|anchor| {
                 let (pixel_left, pixel_top) = anchor.position;
@@ -49,6 +51,149 @@ impl Format for AmethystOrderedFormat {
     }
 }
 
+/// The position of every sprite within the packed image, ordered by the index of the sprite in the
+/// input so the positions line up with the sprites of `AmethystOrderedFormat`
+pub struct OrderedAnchors;
+
+impl Format for OrderedAnchors {
+    type Data = Vec<SpriteAnchor>;
+    type Options = PackSettings;
+
+    fn encode(
+        _dimensions: (u32, u32),
+        sprites: &[SpriteAnchor],
+        options: Self::Options,
+    ) -> Self::Data {
+        // Fix ordering issues
+        let mut anchors: Vec<SpriteAnchor> = sprites.iter().map(|x| options.trim(x)).collect();
+        anchors.sort_by_key(|x| x.id);
+        anchors
+    }
+}
+
+/// Space left around every tile when packing the sprite sheet of a map, which stops neighbouring
+/// tiles from bleeding into each other when the sheet is sampled at fractional positions. Insert
+/// it as a resource to change how maps are packed.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub struct PackSettings {
+    /// The number of transparent pixels added around every tile
+    pub padding: u32,
+    /// The number of times the edge pixels of every tile are repeated around it
+    pub extrusion: u32,
+    /// The largest width and height of a packed texture. Maps with more tiles than fit in a single
+    /// texture are split into several pages, each drawn by its own `TileMap`. Animated tiles are
+    /// drawn from the page of their first frame, so loading fails when the frames of an animation
+    /// are split over several pages.
+    pub max_size: Option<u32>,
+}
+
+impl PackSettings {
+    /// The number of pixels added to each side of a tile
+    pub fn border(&self) -> u32 {
+        self.padding + self.extrusion
+    }
+
+    /// Surround a sprite with its extruded edges followed by the padding
+    pub fn pad(&self, sprite: InputSprite) -> InputSprite {
+        let (width, height) = sprite.dimensions;
+        if self.border() == 0 || width == 0 || height == 0 {
+            return sprite;
+        }
+
+        let image = RgbaImage::from_raw(width, height, sprite.bytes)
+            .expect("Sprite data should match its dimensions");
+        let border = self.border();
+        let mut padded = RgbaImage::new(width + 2 * border, height + 2 * border);
+
+        let extrusion = self.extrusion as i64;
+        for y in 0..height + 2 * self.extrusion {
+            for x in 0..width + 2 * self.extrusion {
+                let source_x = (x as i64 - extrusion).clamp(0, width as i64 - 1) as u32;
+                let source_y = (y as i64 - extrusion).clamp(0, height as i64 - 1) as u32;
+
+                let pixel = *image.get_pixel(source_x, source_y);
+                padded.put_pixel(self.padding + x, self.padding + y, pixel);
+            }
+        }
+
+        InputSprite {
+            dimensions: padded.dimensions(),
+            bytes: padded.into_raw(),
+        }
+    }
+
+    /// Find the area of a packed sprite which is covered by the tile itself
+    pub fn trim(&self, anchor: &SpriteAnchor) -> SpriteAnchor {
+        let border = self.border();
+        let (x, y) = anchor.position;
+        let (width, height) = anchor.dimensions;
+
+        SpriteAnchor {
+            id: anchor.id,
+            position: (x + border, y + border),
+            dimensions: (
+                width.saturating_sub(2 * border),
+                height.saturating_sub(2 * border),
+            ),
+        }
+    }
+}
+
+/// Pack sprites into a single sprite sheet, adding the padding from the settings around each one
+pub fn pack_sprites(sprites: Vec<InputSprite>, settings: PackSettings) -> SpriteSheet {
+    #[cfg(feature = "profiler")]
+    profile_scope!("sheep_pack_image");
+
+    let sprites = sprites.into_iter().map(|x| settings.pad(x)).collect();
+
+    // There is guaranteed to be exactly one resulting sprite sheet
+    pack::<SimplePacker>(sprites, 4, ()).remove(0)
+}
+
+/// Pack sprites into as many sprite sheets as needed to keep each of them within the maximum size
+/// from the settings. Sprites are only split over several pages when they do not fit in one.
+pub fn pack_pages(
+    sprites: Vec<InputSprite>,
+    settings: PackSettings,
+) -> Result<Vec<SpriteSheet>, Error> {
+    #[cfg(feature = "profiler")]
+    profile_scope!("pack_pages");
+
+    let max_size = match settings.max_size {
+        Some(v) => v,
+        None => return Ok(vec![pack_sprites(sprites, settings)]),
+    };
+
+    let sprites: Vec<InputSprite> = sprites.into_iter().map(|x| settings.pad(x)).collect();
+
+    if let Some(sprite) = sprites
+        .iter()
+        .find(|x| x.dimensions.0 > max_size || x.dimensions.1 > max_size)
+    {
+        return Err(Error::from_string(format!(
+            "Unable to fit a {}x{} tile in a texture of at most {}x{} pixels",
+            sprite.dimensions.0, sprite.dimensions.1, max_size, max_size
+        )));
+    }
+
+    // Only try a single page when the sprites could fit in one
+    let area: u64 = sprites
+        .iter()
+        .map(|x| x.dimensions.0 as u64 * x.dimensions.1 as u64)
+        .sum();
+
+    if area <= max_size as u64 * max_size as u64 {
+        let packed = pack::<SimplePacker>(sprites.clone(), 4, ()).remove(0);
+        let (width, height) = packed.dimensions;
+
+        if width <= max_size && height <= max_size {
+            return Ok(vec![packed]);
+        }
+    }
+
+    Ok(pack::<PagePacker>(sprites, 4, max_size))
+}
+
 /// A sprite packer that can save time on the packing by assuming all sprites will be the exact same
 /// size. Because of this, it can pack everything in a single pass. However, it won't be as easy to
 /// view and look at due to all of the sprites being put in a vertical line.
@@ -93,7 +238,67 @@ impl Packer for TilePacker {
     }
 }
 
-pub fn pack_tileset(set: &Tileset, source: Arc<dyn Source>) -> Result<SpriteSheet, Error> {
+/// A sprite packer which places sprites in rows in the order they are given, starting a new page
+/// whenever the next sprite would not fit within the maximum size given as the options. Keeping
+/// the order means tiles which are next to each other in a tileset end up on the same page.
+pub struct PagePacker;
+
+impl Packer for PagePacker {
+    type Options = u32;
+
+    fn pack(sprites: &[SpriteData], max_size: Self::Options) -> Vec<PackerResult> {
+        #[cfg(feature = "profiler")]
+        profile_scope!("pack_paged_image");
+
+        let empty = || PackerResult {
+            dimensions: (0, 0),
+            anchors: Vec::new(),
+        };
+
+        let mut pages = Vec::new();
+        let mut page = empty();
+        let (mut x, mut y, mut row_height) = (0, 0, 0);
+
+        for sprite in sprites {
+            let (width, height) = sprite.dimensions;
+
+            if x + width > max_size {
+                x = 0;
+                y += row_height;
+                row_height = 0;
+            }
+
+            if y + height > max_size && !page.anchors.is_empty() {
+                pages.push(std::mem::replace(&mut page, empty()));
+                x = 0;
+                y = 0;
+                row_height = 0;
+            }
+
+            page.anchors.push(SpriteAnchor {
+                id: sprite.id,
+                position: (x, y),
+                dimensions: sprite.dimensions,
+            });
+            page.dimensions = (
+                page.dimensions.0.max(x + width),
+                page.dimensions.1.max(y + height),
+            );
+
+            x += width;
+            row_height = row_height.max(height);
+        }
+
+        pages.push(page);
+        pages
+    }
+}
+
+pub fn pack_tileset(
+    set: &Tileset,
+    source: Arc<dyn Source>,
+    settings: PackSettings,
+) -> Result<SpriteSheet, Error> {
     let mut sprites = Vec::new();
 
     for image in &set.images {
@@ -109,10 +314,10 @@ pub fn pack_tileset(set: &Tileset, source: Arc<dyn Source>) -> Result<SpriteShee
         )?);
     }
 
-    // There is guaranteed to be exactly one resulting sprite sheet
-    Ok(pack::<SimplePacker>(sprites, 4, ()).remove(0))
+    Ok(pack_sprites(sprites, settings))
 }
 
+#[derive(Debug, Copy, Clone)]
 pub struct TileSpec {
     pub width: u32,
     pub height: u32,
@@ -120,6 +325,25 @@ pub struct TileSpec {
     pub spacing: u32,
 }
 
+impl TileSpec {
+    /// The number of columns and rows of tiles which fit in an image of the given size
+    pub fn grid_size(&self, image_width: u32, image_height: u32) -> (u32, u32) {
+        let fit = |length: u32, tile: u32| {
+            (length + self.spacing).saturating_sub(2 * self.margin) / (tile + self.spacing)
+        };
+
+        (fit(image_width, self.width), fit(image_height, self.height))
+    }
+
+    /// The pixel position of the top left corner of a tile within the image
+    pub fn tile_position(&self, columns: u32, idx: u32) -> (u32, u32) {
+        (
+            self.margin + (idx % columns) * (self.width + self.spacing),
+            self.margin + (idx / columns) * (self.height + self.spacing),
+        )
+    }
+}
+
 pub fn pack_image(
     img: &TileImage,
     source: Arc<dyn Source>,
@@ -130,21 +354,19 @@ pub fn pack_image(
 
     let mut image = open_image(img, source)?;
 
-    let TileSpec {
-        width,
-        height,
-        margin,
-        spacing,
-    } = spec;
+    let (columns, rows) = spec.grid_size(image.width(), image.height());
 
     let mut sprites = Vec::new();
-    for y in (margin..image.height() + margin).step_by((height + spacing) as usize) {
-        for x in (margin..image.width() + margin).step_by((width + spacing) as usize) {
-            sprites.push(InputSprite {
-                dimensions: (width, height),
-                bytes: image.sub_image(x, y, width, height).to_image().into_raw(),
-            })
-        }
+    for idx in 0..columns * rows {
+        let (x, y) = spec.tile_position(columns, idx);
+
+        sprites.push(InputSprite {
+            dimensions: (spec.width, spec.height),
+            bytes: image
+                .sub_image(x, y, spec.width, spec.height)
+                .to_image()
+                .into_raw(),
+        })
     }
 
     Ok(sprites)
@@ -164,15 +386,8 @@ pub fn pack_sparse_image(
 
     let mut image = open_image(img, source)?;
 
-    let TileSpec {
-        width,
-        height,
-        margin,
-        spacing,
-    } = spec;
-
-    let grid_width = (image.width() - 2 * margin) / (width + spacing);
-    let grid_height = (image.height() - 2 * margin) / (height + spacing);
+    let TileSpec { width, height, .. } = spec;
+    let (grid_width, grid_height) = spec.grid_size(image.width(), image.height());
 
     let mut sprites = Vec::new();
     let mut consumed_tiles = 0;
@@ -183,8 +398,7 @@ pub fn pack_sparse_image(
         }
         consumed_tiles += 1;
 
-        let x = margin + ((idx - first_gid) % grid_width) * (width + spacing);
-        let y = margin + ((idx - first_gid) / grid_width) * (height + spacing);
+        let (x, y) = spec.tile_position(grid_width, idx - first_gid);
 
         sprites.push(InputSprite {
             dimensions: (width, height),
@@ -240,11 +454,17 @@ pub fn open_image(img: &TileImage, source: Arc<dyn Source>) -> Result<RgbaImage,
     Ok(image)
 }
 
+/// Pack the tiles listed in `usage` (which must be sorted and start with gid 0) so the sprite for
+/// `usage[n]` ends up at index `n`. A diagonally flipped copy of every gid in `transposed` is
+/// appended after them in the same order. The sprites are split into pages as needed by
+/// `pack_pages`.
 pub fn pack_sparse_tileset_vec(
     sets: &[Tileset],
     source: Arc<dyn Source>,
     usage: &[u32],
-) -> Result<SpriteSheet, Error> {
+    transposed: &[u32],
+    settings: PackSettings,
+) -> Result<Vec<SpriteSheet>, Error> {
     #[cfg(feature = "profiler")]
     profile_scope!("pack_sparse_tileset_vec");
 
@@ -284,18 +504,31 @@ pub fn pack_sparse_tileset_vec(
         }
     }
 
-    #[cfg(feature = "profiler")]
-    profile_scope!("sheep_pack_image");
+    for gid in transposed {
+        let sprite = match usage.binary_search(gid) {
+            Ok(idx) => transpose_sprite(&sprites[idx]),
+            Err(_) => {
+                return Err(Error::from_string(format!(
+                    "Unable to flip unused tile: {:}",
+                    gid
+                )))
+            }
+        };
+        sprites.push(sprite);
+    }
 
-    // There is guaranteed to be exactly one resulting sprite sheet
-    Ok(pack::<SimplePacker>(sprites, 4, ()).remove(0))
+    pack_pages(sprites, settings)
 }
 
-/// Pack a list of tile sets while paying attention to the first grid id
+/// Pack a list of tile sets while paying attention to the first grid id. A diagonally flipped copy
+/// of every gid in `transposed` is appended after the tiles in the same order. The sprites are
+/// split into pages as needed by `pack_pages`.
 pub fn pack_tileset_vec(
     sets: &[Tileset],
     source: Arc<dyn Source>,
-) -> Result<SpriteSheet, Error> {
+    transposed: &[u32],
+    settings: PackSettings,
+) -> Result<Vec<SpriteSheet>, Error> {
     #[cfg(feature = "profiler")]
     profile_scope!("pack_tileset_vec");
 
@@ -323,9 +556,430 @@ pub fn pack_tileset_vec(
         }
     }
 
-    #[cfg(feature = "profiler")]
-    profile_scope!("sheep_pack_image");
+    for gid in transposed {
+        let sprite = match sprites.get(*gid as usize) {
+            Some(v) => transpose_sprite(v),
+            None => {
+                return Err(Error::from_string(format!(
+                    "Unable to flip missing tile: {:}",
+                    gid
+                )))
+            }
+        };
+        sprites.push(sprite);
+    }
 
-    // There is guaranteed to be exactly one resulting sprite sheet
-    Ok(pack::<SimplePacker>(sprites, 4, ()).remove(0))
+    pack_pages(sprites, settings)
+}
+
+/// Create a copy of a sprite with its x and y axes swapped, as Tiled does for diagonal flips
+fn transpose_sprite(sprite: &InputSprite) -> InputSprite {
+    let (width, height) = sprite.dimensions;
+
+    let image = RgbaImage::from_raw(width, height, sprite.bytes.clone())
+        .expect("Sprite data should match its dimensions");
+
+    let flip = Flip {
+        diagonal: true,
+        ..Flip::NONE
+    };
+
+    InputSprite {
+        dimensions: (height, width),
+        bytes: apply_flip(image, flip).into_raw(),
+    }
+}
+
+/// Apply the orientation of a placed tile to its pixels
+pub fn apply_flip(mut image: RgbaImage, flip: Flip) -> RgbaImage {
+    // Rotating clockwise and mirroring the result swaps the axes
+    if flip.diagonal {
+        image = flip_horizontal(&rotate90(&image));
+    }
+
+    if flip.horizontal {
+        image = flip_horizontal(&image);
+    }
+
+    if flip.vertical {
+        image = flip_vertical(&image);
+    }
+
+    image
+}
+
+struct LoadedImage {
+    first_gid: u32,
+    columns: u32,
+    len: u32,
+    spec: TileSpec,
+    image: RgbaImage,
+}
+
+/// The decoded images of a list of tile sets which can be used to look up the pixels of single
+/// tiles by their gid.
+pub struct TileImages(Vec<LoadedImage>);
+
+impl TileImages {
+    pub fn load(sets: &[Tileset], source: Arc<dyn Source>) -> Result<Self, Error> {
+        #[cfg(feature = "profiler")]
+        profile_scope!("load_tile_images");
+
+        let mut images = Vec::new();
+
+        for set in sets {
+            let mut first_gid = set.first_gid;
+
+            for image in &set.images {
+                let image = open_image(image, source.clone())?;
+                let spec = TileSpec {
+                    width: set.tile_width,
+                    height: set.tile_height,
+                    margin: set.margin,
+                    spacing: set.spacing,
+                };
+
+                let (columns, rows) = spec.grid_size(image.width(), image.height());
+
+                images.push(LoadedImage {
+                    first_gid,
+                    columns,
+                    len: columns * rows,
+                    spec,
+                    image,
+                });
+
+                first_gid += columns * rows;
+            }
+        }
+
+        Ok(Self(images))
+    }
+
+    /// Get a copy of a tile's pixels with the given orientation applied
+    pub fn tile(&self, gid: u32, flip: Flip) -> Option<RgbaImage> {
+        let loaded = self
+            .0
+            .iter()
+            .find(|x| gid >= x.first_gid && gid < x.first_gid + x.len)?;
+
+        let spec = loaded.spec;
+        let (x, y) = spec.tile_position(loaded.columns, gid - loaded.first_gid);
+
+        let pixels = loaded.image.view(x, y, spec.width, spec.height).to_image();
+        Some(apply_flip(pixels, flip))
+    }
+}
+
+/// Copy a sprite while mirroring its texture coordinates
+pub fn mirror_sprite(sprite: &Sprite, flip: Flip) -> Sprite {
+    let mut sprite = sprite.clone();
+    let coords = &mut sprite.tex_coords;
+
+    if flip.horizontal {
+        std::mem::swap(&mut coords.left, &mut coords.right);
+    }
+
+    if flip.vertical {
+        std::mem::swap(&mut coords.top, &mut coords.bottom);
+    }
+
+    sprite
+}
+
+/// Tracks the sprites used to draw flipped tiles. Horizontal and vertical flips reuse the packed
+/// pixels of a tile by mirroring the texture coordinates, so only diagonal flips need their own
+/// pixels in the packed image.
+#[derive(Debug, Clone, Default)]
+pub struct FlipVariants {
+    transposed: HashMap<usize, usize>,
+    variants: HashMap<(usize, Flip), usize>,
+}
+
+impl FlipVariants {
+    /// Create a lookup for a sprite sheet where the diagonally flipped copy of `transposed[n]`
+    /// is found at index `first_copy + n`.
+    pub fn new(transposed: &[usize], first_copy: usize) -> Self {
+        Self {
+            transposed: transposed
+                .iter()
+                .enumerate()
+                .map(|(n, idx)| (*idx, first_copy + n))
+                .collect(),
+            variants: HashMap::new(),
+        }
+    }
+
+    /// Record that the diagonally flipped copy of `base` was added at index `copy`
+    pub fn transpose(&mut self, base: usize, copy: usize) {
+        self.transposed.insert(base, copy);
+    }
+
+    pub fn is_transposed(&self, base: usize) -> bool {
+        self.transposed.contains_key(&base)
+    }
+
+    /// Iterate over every sprite index with its diagonally flipped copy
+    pub fn transposed(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.transposed.iter().map(|(base, copy)| (*base, *copy))
+    }
+
+    /// Iterate over the sprites added by mirroring the texture coordinates of another sprite,
+    /// giving the index of the mirrored sprite, the index of its source and the flip applied
+    pub fn mirrors(&self) -> impl Iterator<Item = (usize, usize, Flip)> + '_ {
+        self.variants
+            .iter()
+            .filter(|((_, flip), _)| flip.mirror().is_flipped())
+            .map(move |((base, flip), idx)| {
+                let source = match flip.diagonal {
+                    true => self.transposed[base],
+                    false => *base,
+                };
+                (*idx, source, *flip)
+            })
+    }
+
+    /// Get the index of the sprite that draws `base` with the given orientation, adding a new
+    /// sprite to the list if this orientation has not been seen before.
+    pub fn resolve(&mut self, sprites: &mut Vec<Sprite>, base: usize, flip: Flip) -> usize {
+        if !flip.is_flipped() {
+            return base;
+        }
+
+        if let Some(idx) = self.variants.get(&(base, flip)) {
+            return *idx;
+        }
+
+        let source = match flip.diagonal {
+            true => *self
+                .transposed
+                .get(&base)
+                .expect("Diagonally flipped tiles must be packed with a transposed copy"),
+            false => base,
+        };
+
+        let idx = match flip.mirror().is_flipped() {
+            true => {
+                sprites.push(mirror_sprite(&sprites[source], flip));
+                sprites.len() - 1
+            }
+            false => source,
+        };
+
+        self.variants.insert((base, flip), idx);
+        idx
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sheep::encode;
+
+    /// A sprite where every pixel has its own colour
+    fn sprite(id: u8, width: u32, height: u32) -> InputSprite {
+        let image = RgbaImage::from_fn(width, height, |x, y| Rgba([id, x as u8, y as u8, 255]));
+
+        InputSprite {
+            dimensions: (width, height),
+            bytes: image.into_raw(),
+        }
+    }
+
+    fn image(sprite: &InputSprite) -> RgbaImage {
+        let (width, height) = sprite.dimensions;
+        RgbaImage::from_raw(width, height, sprite.bytes.clone()).unwrap()
+    }
+
+    #[test]
+    fn padding_extrudes_edge_pixels() {
+        let settings = PackSettings {
+            padding: 1,
+            extrusion: 2,
+            max_size: None,
+        };
+        let tile = image(&sprite(1, 3, 2));
+        let padded = settings.pad(sprite(1, 3, 2));
+        assert_eq!(padded.dimensions, (3 + 6, 2 + 6));
+
+        let padded = image(&padded);
+        let border = settings.border() as i64;
+
+        for y in 1..padded.height() - 1 {
+            for x in 1..padded.width() - 1 {
+                let source_x = (x as i64 - border).clamp(0, 2) as u32;
+                let source_y = (y as i64 - border).clamp(0, 1) as u32;
+                assert_eq!(
+                    padded.get_pixel(x, y),
+                    tile.get_pixel(source_x, source_y),
+                    "Pixel {}, {}",
+                    x,
+                    y
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn padding_is_transparent() {
+        let settings = PackSettings {
+            padding: 2,
+            extrusion: 1,
+            max_size: None,
+        };
+        let padded = image(&settings.pad(sprite(1, 4, 4)));
+        assert_eq!(padded.dimensions(), (10, 10));
+
+        for (x, y, pixel) in padded.enumerate_pixels() {
+            let inside = (2..8).contains(&x) && (2..8).contains(&y);
+            assert_eq!(pixel[3] == 255, inside, "Pixel {}, {}", x, y);
+        }
+    }
+
+    #[test]
+    fn trimmed_sprites_cover_their_tiles() {
+        let settings = PackSettings {
+            padding: 1,
+            extrusion: 1,
+            max_size: None,
+        };
+        let sprites: Vec<InputSprite> = (0..5).map(|x| sprite(x, 4 + x as u32, 3)).collect();
+        let packed = pack_sprites(sprites.clone(), settings);
+        let (width, height) = packed.dimensions;
+        let sheet = RgbaImage::from_raw(width, height, packed.bytes.clone()).unwrap();
+
+        let anchors = encode::<OrderedAnchors>(&packed, settings);
+        let amethyst = encode::<AmethystOrderedFormat>(&packed, settings);
+        assert_eq!(anchors.len(), sprites.len());
+        assert_eq!(amethyst.len(), sprites.len());
+
+        for ((id, tile), (anchor, sprite)) in sprites
+            .iter()
+            .enumerate()
+            .zip(anchors.iter().zip(&amethyst))
+        {
+            assert_eq!(anchor.id, id);
+            assert_eq!(anchor.dimensions, tile.dimensions);
+
+            let (x, y) = anchor.position;
+            let (w, h) = anchor.dimensions;
+            assert_eq!(sheet.view(x, y, w, h).to_image(), image(tile));
+
+            assert_eq!((sprite.width, sprite.height), (w as f32, h as f32));
+            let coords = &sprite.tex_coords;
+            let left = (coords.left.min(coords.right) * width as f32).round() as u32;
+            let top = (coords.top.min(coords.bottom) * height as f32).round() as u32;
+            assert_eq!((left, top), anchor.position);
+        }
+    }
+
+    #[test]
+    fn sprites_are_split_into_pages() {
+        let settings = PackSettings {
+            padding: 1,
+            extrusion: 0,
+            max_size: Some(36),
+        };
+
+        // Two padded 18x18 sprites fit in each row and column of a page
+        let sprites: Vec<InputSprite> = (0..10).map(|x| sprite(x, 16, 16)).collect();
+        let pages = pack_pages(sprites, settings).unwrap();
+        assert_eq!(pages.len(), 3);
+
+        let ids: Vec<Vec<usize>> = pages
+            .iter()
+            .map(|page| {
+                encode::<OrderedAnchors>(page, settings)
+                    .iter()
+                    .map(|x| x.id)
+                    .collect()
+            })
+            .collect();
+        assert_eq!(ids, vec![vec![0, 1, 2, 3], vec![4, 5, 6, 7], vec![8, 9]]);
+
+        for page in &pages {
+            assert!(page.dimensions.0 <= 36 && page.dimensions.1 <= 36);
+        }
+    }
+
+    #[test]
+    fn sprites_which_fit_are_kept_on_one_page() {
+        let settings = PackSettings {
+            padding: 0,
+            extrusion: 0,
+            max_size: Some(32),
+        };
+
+        let sprites: Vec<InputSprite> = (0..4).map(|x| sprite(x, 16, 16)).collect();
+        assert_eq!(pack_pages(sprites, settings).unwrap().len(), 1);
+
+        let large = vec![sprite(0, 16, 16), sprite(1, 40, 16)];
+        assert!(pack_pages(large, settings).is_err());
+    }
+
+    #[test]
+    fn page_packer_fills_rows_in_order() {
+        let sprites: Vec<SpriteData> = [(10, 8), (10, 12), (10, 8), (20, 6), (10, 8)]
+            .iter()
+            .enumerate()
+            .map(|(id, dimensions)| SpriteData {
+                id,
+                dimensions: *dimensions,
+            })
+            .collect();
+
+        let pages = PagePacker::pack(&sprites, 24);
+        let anchors: Vec<Vec<(usize, (u32, u32))>> = pages
+            .iter()
+            .map(|page| page.anchors.iter().map(|x| (x.id, x.position)).collect())
+            .collect();
+
+        assert_eq!(
+            anchors,
+            vec![
+                vec![(0, (0, 0)), (1, (10, 0)), (2, (0, 12))],
+                vec![(3, (0, 0)), (4, (0, 6))],
+            ]
+        );
+        assert_eq!(pages[0].dimensions, (20, 20));
+        assert_eq!(pages[1].dimensions, (20, 14));
+    }
+
+    #[test]
+    fn transposed_sprites_swap_their_axes() {
+        let tile = sprite(1, 3, 2);
+        let transposed = transpose_sprite(&tile);
+        assert_eq!(transposed.dimensions, (2, 3));
+
+        let (tile, transposed) = (image(&tile), image(&transposed));
+        for (x, y, pixel) in transposed.enumerate_pixels() {
+            assert_eq!(pixel, tile.get_pixel(y, x), "Pixel {}, {}", x, y);
+        }
+    }
+
+    #[test]
+    fn flips_mirror_after_transposing() {
+        let tile = image(&sprite(1, 3, 2));
+        let flip = |horizontal, vertical, diagonal| {
+            apply_flip(
+                tile.clone(),
+                Flip {
+                    horizontal,
+                    vertical,
+                    diagonal,
+                },
+            )
+        };
+
+        let horizontal = flip(true, false, false);
+        assert_eq!(horizontal.get_pixel(0, 0), tile.get_pixel(2, 0));
+
+        let vertical = flip(false, true, false);
+        assert_eq!(vertical.get_pixel(0, 0), tile.get_pixel(0, 1));
+
+        // Rotating clockwise is a diagonal flip followed by a horizontal one
+        let rotated = flip(true, false, true);
+        assert_eq!(rotated.dimensions(), (2, 3));
+        assert_eq!(rotated, rotate90(&tile));
+    }
 }
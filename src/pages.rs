@@ -0,0 +1,195 @@
+//! Sprite sheets split over several textures when a map has more tiles than fit within
+//! `PackSettings::max_size`. Every page holds the sprites of the whole atlas so each tile keeps the
+//! same sprite index on every page, but a tile can only be drawn with the sheet of the page its
+//! sprite was packed into.
+
+use std::sync::Arc;
+
+use amethyst::assets::{AssetStorage, Handle, Loader, ProgressCounter};
+use amethyst::core::math::Point3;
+use amethyst::renderer::sprite::Sprite;
+use amethyst::renderer::{SpriteSheet, Texture};
+use amethyst::Error;
+
+use crate::animation::TileAnimations;
+use crate::packing::FlipVariants;
+use crate::strategy::TileAtlas;
+use crate::TileGid;
+
+#[cfg(feature = "profiler")]
+use thread_profiler::profile_scope;
+
+/// The sprites of a packed atlas along with the texture of every page and the page every sprite
+/// was packed into
+#[derive(Debug, Clone)]
+pub struct PagedSheet {
+    pub textures: Vec<Handle<Texture>>,
+    pub sprites: Vec<Sprite>,
+    pub pages: Vec<u32>,
+}
+
+impl PagedSheet {
+    /// Create a sprite sheet for every page. Sprites added by mirroring another sprite are placed
+    /// on the page of their source.
+    pub(crate) fn load(
+        self,
+        variants: &FlipVariants,
+        loader: &Loader,
+        progress: &mut ProgressCounter,
+        storage: &AssetStorage<SpriteSheet>,
+    ) -> AtlasPages {
+        #[cfg(feature = "profiler")]
+        profile_scope!("load_atlas_pages");
+
+        let mut pages = self.pages;
+        pages.resize(self.sprites.len(), 0);
+
+        for (index, source, _) in variants.mirrors() {
+            pages[index] = pages[source];
+        }
+
+        let sheets = self
+            .textures
+            .into_iter()
+            .map(|texture| {
+                let sheet = SpriteSheet {
+                    texture,
+                    sprites: self.sprites.clone(),
+                };
+                loader.load_from_data(sheet, &mut *progress, storage)
+            })
+            .collect();
+
+        AtlasPages {
+            sheets,
+            pages: Arc::new(pages),
+        }
+    }
+}
+
+/// The sprite sheet of every page of a packed atlas
+#[derive(Debug, Clone)]
+pub struct AtlasPages {
+    sheets: Vec<Handle<SpriteSheet>>,
+    /// The page of every sprite in the sheets
+    pages: Arc<Vec<u32>>,
+}
+
+impl AtlasPages {
+    pub fn sheets(&self) -> &[Handle<SpriteSheet>] {
+        &self.sheets
+    }
+
+    pub fn len(&self) -> usize {
+        self.sheets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sheets.is_empty()
+    }
+
+    /// The page a sprite was packed into. Sprites added after the atlas was loaded are on the
+    /// first page, since only atlases with a single page can be extended.
+    pub fn page_of(&self, sprite: usize) -> usize {
+        self.pages.get(sprite).copied().unwrap_or(0) as usize
+    }
+
+    /// The sheet a sprite must be drawn with
+    pub fn sheet_of(&self, sprite: usize) -> &Handle<SpriteSheet> {
+        &self.sheets[self.page_of(sprite)]
+    }
+
+    /// Check every frame of the given animations was packed into the same page, since an animated
+    /// tile is always drawn with the sheet of the page it was placed on
+    pub(crate) fn check_animations(
+        &self,
+        ids: &[usize],
+        animations: &TileAnimations,
+    ) -> Result<(), Error> {
+        for animation in ids.iter().filter_map(|id| animations.get(*id)) {
+            let first = self.page_of(animation.frames()[0].0);
+
+            for (sprite, _) in animation.frames() {
+                if self.page_of(*sprite) != first {
+                    return Err(Error::from_string(format!(
+                        "The frames of an animation were packed into pages {} and {}, use a \
+                         larger max_size so they fit in a single page",
+                        first,
+                        self.page_of(*sprite)
+                    )));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Split tiles by the page they are drawn from
+    pub(crate) fn split(
+        &self,
+        tiles: Vec<(Point3<u32>, TileGid)>,
+    ) -> Vec<Vec<(Point3<u32>, TileGid)>> {
+        let mut pages = vec![Vec::new(); self.len()];
+
+        for (point, tile) in tiles {
+            pages[self.page_of(tile.sprite_index())].push((point, tile));
+        }
+
+        pages
+    }
+
+    /// Split an atlas so each page only holds the tiles which can be drawn from it. Only atlases
+    /// with a single page keep the layout used to extend them.
+    pub(crate) fn split_atlas(&self, atlas: TileAtlas) -> Vec<TileAtlas> {
+        if self.len() == 1 {
+            return vec![atlas];
+        }
+
+        (0..self.len())
+            .map(|page| TileAtlas {
+                tiles: atlas
+                    .tiles
+                    .iter()
+                    .filter(|(_, tile)| self.page_of(tile.sprite_index()) == page)
+                    .map(|(key, tile)| (*key, *tile))
+                    .collect(),
+                ..TileAtlas::default()
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::animation::TileAnimation;
+    use std::time::Duration;
+
+    #[test]
+    fn animations_must_stay_on_one_page() {
+        let pages = AtlasPages {
+            sheets: Vec::new(),
+            pages: Arc::new(vec![0, 0, 1, 1]),
+        };
+
+        let frames = |sprites: &[usize]| {
+            let frames = sprites
+                .iter()
+                .map(|x| (*x, Duration::from_millis(100)))
+                .collect();
+            TileAnimation::new(frames)
+        };
+
+        let mut animations = TileAnimations::default();
+        let first = animations.push(frames(&[0, 1]));
+        let second = animations.push(frames(&[3, 2]));
+        let split = animations.push(frames(&[1, 2]));
+
+        assert!(pages
+            .check_animations(&[first, second], &animations)
+            .is_ok());
+        assert!(pages
+            .check_animations(&[first, split], &animations)
+            .is_err());
+    }
+}
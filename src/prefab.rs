@@ -1,11 +1,28 @@
-use amethyst::assets::{Asset, AssetStorage, Handle, Loader, PrefabData, ProgressCounter, Source};
-use amethyst::ecs::{Component, Entity, Read, ReadExpect, Write, WriteStorage};
-use amethyst::renderer::{SpriteSheet, Texture};
+use amethyst::assets::{AssetStorage, Handle, Loader, PrefabData, ProgressCounter, Source};
+use amethyst::core::{Named, Parent, Transform};
+use amethyst::ecs::{
+    Entities, Entity, LazyUpdate, Read, ReadExpect, SystemData, Write, WriteStorage,
+};
+use amethyst::renderer::resources::Tint;
+use amethyst::renderer::transparent::Transparent;
+use amethyst::renderer::{Hidden, SpriteRender, SpriteSheet, Texture};
+use amethyst::shred;
 use amethyst::Error;
-use tiled::{Map, Tileset};
+use tiled::{parse_tileset, Map, Properties, Tileset};
 
+use crate::bounds::MapBounds;
+use crate::collision::{MapCollision, SolidCells};
+use crate::format::shift_path;
+use crate::image_layer::{ImageLayerData, ImageLayers};
+use crate::json::read_document;
+use crate::navigation::{NavGrid, NavigationGrids};
+use crate::object::{MapObjects, ObjectData};
+use crate::orientation::MapOrientation;
+use crate::property::PropertyRegistry;
+use crate::reload::Dependencies;
 use crate::strategy::{CompressedLoad, LoadStrategy, StrategyDesc};
-use crate::{load_tileset_inner, Tilesets};
+use crate::wang::{MapTerrain, WangSet};
+use crate::{load_tileset_inner, TilesetKey, Tilesets};
 use std::sync::Arc;
 
 #[cfg(feature = "profiler")]
@@ -14,6 +31,9 @@ use thread_profiler::profile_scope;
 pub enum TileSetPrefab {
     Handle(Handle<SpriteSheet>),
     TileSet(Tileset, Arc<dyn Source>),
+    /// The path of a `.tsx` or `.tsj` file within the source, which is tracked along with the
+    /// images of the tileset
+    File(String, Arc<dyn Source>),
 }
 
 impl<'a> PrefabData<'a> for TileSetPrefab {
@@ -46,8 +66,27 @@ impl<'a> PrefabData<'a> for TileSetPrefab {
     ) -> Result<bool, Error> {
         let (tilesets, textures, sheets, loader) = system_data;
 
+        let mut file = None;
+        if let Self::File(path, source) = self {
+            let (bytes, modified) = source.load_with_metadata(path)?;
+            let bytes = read_document(path.as_str(), bytes)?;
+            let mut set = parse_tileset(&bytes[..], 1)?;
+
+            for image in &mut set.images {
+                image.source = shift_path(path, &image.source);
+            }
+
+            file = Some((path.clone(), modified));
+            *self = Self::TileSet(set, source.clone());
+        }
+
         if let Self::TileSet(set, source) = self {
-            match tilesets.get(&set.name) {
+            let key = match &file {
+                Some((path, _)) => TilesetKey::new(path.clone(), set),
+                None => TilesetKey::of_tileset(set),
+            };
+
+            match tilesets.get(&key) {
                 Some(handle) => *self = Self::Handle(handle),
                 None => {
                     let sheet =
@@ -55,8 +94,14 @@ impl<'a> PrefabData<'a> for TileSetPrefab {
                             Ok(v) => v,
                             Err(e) => return Err(Error::from_string(format!("{:}", e))),
                         };
+                    let mut dependencies = Dependencies::new(source.clone());
+                    if let Some((path, modified)) = file {
+                        dependencies.push(path, modified);
+                    }
+                    dependencies.track_tileset(set);
+
                     let handle = sheets.insert(sheet);
-                    tilesets.push(set.name.to_owned(), handle.clone());
+                    tilesets.push_tracked(key, &handle, dependencies);
 
                     *self = Self::Handle(handle);
                     return Ok(true);
@@ -68,16 +113,93 @@ impl<'a> PrefabData<'a> for TileSetPrefab {
     }
 }
 
+/// The data used to create entities as children of the map entity, including the components used
+/// to draw them as sprites and the registry used to turn their properties into components
+#[derive(SystemData)]
+pub struct ChildData<'a> {
+    pub entities: Entities<'a>,
+    pub transforms: WriteStorage<'a, Transform>,
+    pub parents: WriteStorage<'a, Parent>,
+    pub names: WriteStorage<'a, Named>,
+    pub hidden: WriteStorage<'a, Hidden>,
+    pub sprites: WriteStorage<'a, SpriteRender>,
+    pub tints: WriteStorage<'a, Tint>,
+    pub transparent: WriteStorage<'a, Transparent>,
+    pub registry: Read<'a, PropertyRegistry>,
+    pub lazy: Read<'a, LazyUpdate>,
+}
+
+/// Create a new entity as a child of the map entity along with the components of any registered
+/// properties
+pub(crate) fn create_child(
+    data: &mut ChildData<'_>,
+    parent: Entity,
+    name: &str,
+    transform: Transform,
+    visible: bool,
+    properties: &Properties,
+) -> Result<Entity, Error> {
+    let ChildData {
+        entities,
+        transforms,
+        parents,
+        names,
+        hidden,
+        registry,
+        lazy,
+        ..
+    } = data;
+    let entity = entities.create();
+
+    transforms.insert(entity, transform)?;
+    parents.insert(entity, Parent::new(parent))?;
+    names.insert(entity, Named::new(name.to_owned()))?;
+
+    if !visible {
+        hidden.insert(entity, Hidden)?;
+    }
+
+    registry.apply(entity, properties, lazy)?;
+    Ok(entity)
+}
+
+/// Everything loaded from a map alongside the result of its load strategy
+pub struct LoadedMap {
+    bounds: MapBounds,
+    objects: MapObjects,
+    images: ImageLayers,
+    properties: Properties,
+    collision: MapCollision,
+    navigation: NavGrid,
+    terrain: Option<MapTerrain>,
+}
+
 pub enum TileMapPrefab<S: StrategyDesc = CompressedLoad> {
-    Result(S::Result),
-    Map(Map, Arc<dyn Source>),
+    Result(S::Result, LoadedMap),
+    /// A map along with the source and name of the file it was read from and the orientation read
+    /// from the file
+    Map(Map, Arc<dyn Source>, Vec<WangSet>, String, MapOrientation),
+}
+
+/// The data added to the map entity itself
+#[derive(SystemData)]
+pub struct MapData<'a> {
+    pub bounds: WriteStorage<'a, MapBounds>,
+    pub collision: WriteStorage<'a, MapCollision>,
+    pub solid_cells: Read<'a, SolidCells>,
+    pub navigation: Write<'a, NavigationGrids>,
+    pub terrain: WriteStorage<'a, MapTerrain>,
 }
 
-impl<'a, T: LoadStrategy<'a>> PrefabData<'a> for TileMapPrefab<T>
-where
-    T::Result: Clone + Component + Asset,
-{
-    type SystemData = (T::SystemData, WriteStorage<'a, <T as StrategyDesc>::Result>);
+impl<'a, T: LoadStrategy<'a>> PrefabData<'a> for TileMapPrefab<T> {
+    type SystemData = (
+        T::SystemData,
+        T::SpawnData,
+        MapData<'a>,
+        ChildData<'a>,
+        ObjectData<'a>,
+        ImageLayerData<'a>,
+    );
 
     // Don't use a result due to the requirement of cloning the tilemap extra times
     type Result = ();
@@ -92,12 +214,34 @@ where
         #[cfg(feature = "profiler")]
         profile_scope!("add_tilemap_to_entity");
 
-        let (_, storage) = system_data;
+        let (_, spawn_data, map_data, child_data, object_data, _) = system_data;
+        let MapData {
+            bounds,
+            collision,
+            navigation,
+            terrain,
+            ..
+        } = map_data;
 
         match self {
-            TileMapPrefab::Result(v) => {
-                storage.insert(entity, v.clone())?;
-                Ok(())
+            TileMapPrefab::Result(v, loaded) => {
+                T::add_to_entity(v, entity, child_data, spawn_data)?;
+                bounds.insert(entity, loaded.bounds)?;
+                navigation.insert(entity, loaded.navigation.clone());
+
+                if !loaded.collision.is_empty() {
+                    collision.insert(entity, loaded.collision.clone())?;
+                }
+
+                if let Some(map_terrain) = &loaded.terrain {
+                    terrain.insert(entity, map_terrain.clone())?;
+                }
+
+                child_data
+                    .registry
+                    .apply(entity, &loaded.properties, &child_data.lazy)?;
+                loaded.objects.spawn(entity, child_data, object_data)?;
+                loaded.images.spawn(entity, child_data)
             }
             _ => unreachable!("load_sub_assets should be called before add_to_entity"),
         }
@@ -111,8 +255,44 @@ where
         #[cfg(feature = "profiler")]
         profile_scope!("load_tilemap_assets");
         match self {
-            TileMapPrefab::Map(map, source) => {
-                *self = Self::Result(T::load(map, source.clone(), progress, &mut system_data.0)?);
+            TileMapPrefab::Map(map, source, wang_sets, name, orientation) => {
+                let bounds = MapBounds {
+                    orientation: *orientation,
+                    ..MapBounds::of(map)
+                };
+
+                if !T::supports(bounds.orientation) {
+                    return Err(Error::from_string(format!(
+                        "The load strategy can not draw maps with the {:?} orientation of {}",
+                        bounds.orientation, name
+                    )));
+                }
+
+                let images =
+                    ImageLayers::load(map, &bounds, source.clone(), progress, &system_data.5)?;
+                let result = T::load_file(
+                    name,
+                    map,
+                    &bounds,
+                    source.clone(),
+                    progress,
+                    &mut system_data.0,
+                )?;
+
+                let loaded = LoadedMap {
+                    bounds,
+                    objects: MapObjects::new(map, &bounds),
+                    images,
+                    properties: map.properties.clone(),
+                    collision: MapCollision::new(map, &bounds, &system_data.2.solid_cells),
+                    navigation: NavGrid::of(map, &bounds),
+                    terrain: match wang_sets.is_empty() {
+                        true => None,
+                        false => Some(MapTerrain::new(map, &bounds, wang_sets.clone())),
+                    },
+                };
+
+                *self = Self::Result(result, loaded);
                 Ok(true)
             }
             _ => Ok(false),
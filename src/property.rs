@@ -0,0 +1,252 @@
+//! Creation of game components from the custom properties set in Tiled
+
+use std::collections::HashMap;
+
+use amethyst::ecs::{Component, Entity, LazyUpdate};
+use amethyst::Error;
+use tiled::{Properties, PropertyValue};
+
+#[cfg(feature = "profiler")]
+use thread_profiler::profile_scope;
+
+type PropertyDeserializer =
+    Box<dyn Fn(&PropertyValue, Entity, &LazyUpdate) -> Result<(), Error> + Send + Sync>;
+
+type ClassDeserializer =
+    Box<dyn Fn(&Properties, Entity, &LazyUpdate) -> Result<(), Error> + Send + Sync>;
+
+/// Resource mapping custom properties and object classes to the components they should create.
+/// Every entity created by `TileMapPrefab` is checked against the registry, including the map
+/// entity itself, objects, image layers and the layer entities of `LayeredLoad` and `SpriteLoad`.
+/// Components are inserted through `LazyUpdate`, so they are added once the world is maintained.
+///
+/// ```rust,ignore
+/// let mut registry = PropertyRegistry::default();
+/// registry.register("health", |value| match value {
+///     PropertyValue::IntValue(v) => Ok(Health(*v)),
+///     _ => Err(Error::from_string("health must be an int".to_owned())),
+/// });
+/// world.insert(registry);
+/// ```
+#[derive(Default)]
+pub struct PropertyRegistry {
+    properties: HashMap<String, PropertyDeserializer>,
+    classes: HashMap<String, ClassDeserializer>,
+}
+
+impl PropertyRegistry {
+    /// Create a component from every property with the given name. Registering the same name twice
+    /// replaces the previous deserializer. The properties set on tiles in a tileset are only applied
+    /// by `SpriteLoad`, since the other strategies draw tiles with a `TileMap` and do not create an
+    /// entity for each tile.
+    pub fn register<C, F>(&mut self, name: &str, deserialize: F)
+    where
+        C: Component + Send + Sync,
+        F: Fn(&PropertyValue) -> Result<C, Error> + Send + Sync + 'static,
+    {
+        self.properties.insert(
+            name.to_owned(),
+            Box::new(move |value, entity, lazy| {
+                lazy.insert(entity, deserialize(value)?);
+                Ok(())
+            }),
+        );
+    }
+
+    /// Create a component from every object with the given class, which is called the type of the
+    /// object in older versions of Tiled. The deserializer is given all of the object's properties.
+    pub fn register_class<C, F>(&mut self, class: &str, deserialize: F)
+    where
+        C: Component + Send + Sync,
+        F: Fn(&Properties) -> Result<C, Error> + Send + Sync + 'static,
+    {
+        self.classes.insert(
+            class.to_owned(),
+            Box::new(move |properties, entity, lazy| {
+                lazy.insert(entity, deserialize(properties)?);
+                Ok(())
+            }),
+        );
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.properties.is_empty() && self.classes.is_empty()
+    }
+
+    /// Queue the components created by any registered properties for insertion on an entity
+    pub fn apply(
+        &self,
+        entity: Entity,
+        properties: &Properties,
+        lazy: &LazyUpdate,
+    ) -> Result<(), Error> {
+        #[cfg(feature = "profiler")]
+        profile_scope!("apply_properties");
+
+        for (name, value) in properties {
+            if let Some(deserialize) = self.properties.get(name) {
+                deserialize(value, entity, lazy).map_err(|e| {
+                    Error::from_string(format!("Unable to read property {:?}: {:}", name, e))
+                })?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Queue the component created by a registered class for insertion on an entity
+    pub fn apply_class(
+        &self,
+        entity: Entity,
+        class: &str,
+        properties: &Properties,
+        lazy: &LazyUpdate,
+    ) -> Result<(), Error> {
+        match self.classes.get(class) {
+            Some(deserialize) => deserialize(properties, entity, lazy).map_err(|e| {
+                Error::from_string(format!("Unable to read object class {:?}: {:}", class, e))
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use amethyst::ecs::{Builder, VecStorage, World, WorldExt};
+
+    #[derive(Debug, PartialEq)]
+    struct Health(i32);
+
+    impl Component for Health {
+        type Storage = VecStorage<Self>;
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Door {
+        locked: bool,
+    }
+
+    impl Component for Door {
+        type Storage = VecStorage<Self>;
+    }
+
+    fn world() -> World {
+        let mut world = World::new();
+        world.register::<Health>();
+        world.register::<Door>();
+        world
+    }
+
+    fn health(value: &PropertyValue) -> Result<Health, Error> {
+        match value {
+            PropertyValue::IntValue(v) => Ok(Health(*v)),
+            _ => Err(Error::from_string("health must be an int".to_owned())),
+        }
+    }
+
+    fn properties(values: Vec<(&str, PropertyValue)>) -> Properties {
+        values
+            .into_iter()
+            .map(|(name, value)| (name.to_owned(), value))
+            .collect()
+    }
+
+    #[test]
+    fn registered_properties_create_components() {
+        let mut world = world();
+        let entity = world.create_entity().build();
+
+        let mut registry = PropertyRegistry::default();
+        assert!(registry.is_empty());
+        registry.register("health", health);
+        assert!(!registry.is_empty());
+
+        let properties = properties(vec![
+            ("health", PropertyValue::IntValue(5)),
+            ("unknown", PropertyValue::BoolValue(true)),
+        ]);
+        registry
+            .apply(entity, &properties, &world.read_resource::<LazyUpdate>())
+            .unwrap();
+
+        // Components are only added once the world is maintained
+        assert_eq!(world.read_storage::<Health>().get(entity), None);
+        world.maintain();
+        assert_eq!(world.read_storage::<Health>().get(entity), Some(&Health(5)));
+    }
+
+    #[test]
+    fn registering_a_property_again_replaces_it() {
+        let mut world = world();
+        let entity = world.create_entity().build();
+
+        let mut registry = PropertyRegistry::default();
+        registry.register("health", health);
+        registry.register("health", |_| Ok(Health(100)));
+
+        let properties = properties(vec![("health", PropertyValue::IntValue(5))]);
+        registry
+            .apply(entity, &properties, &world.read_resource::<LazyUpdate>())
+            .unwrap();
+        world.maintain();
+
+        assert_eq!(
+            world.read_storage::<Health>().get(entity),
+            Some(&Health(100))
+        );
+    }
+
+    #[test]
+    fn invalid_properties_are_errors() {
+        let mut world = world();
+        let entity = world.create_entity().build();
+
+        let mut registry = PropertyRegistry::default();
+        registry.register("health", health);
+
+        let properties = properties(vec![(
+            "health",
+            PropertyValue::StringValue("full".to_owned()),
+        )]);
+        let error = registry
+            .apply(entity, &properties, &world.read_resource::<LazyUpdate>())
+            .unwrap_err();
+        assert!(error.to_string().contains("health"));
+
+        world.maintain();
+        assert_eq!(world.read_storage::<Health>().get(entity), None);
+    }
+
+    #[test]
+    fn registered_classes_create_components() {
+        let mut world = world();
+        let (door, other) = (world.create_entity().build(), world.create_entity().build());
+
+        let mut registry = PropertyRegistry::default();
+        registry.register_class("Door", |properties| match properties.get("locked") {
+            Some(PropertyValue::BoolValue(locked)) => Ok(Door { locked: *locked }),
+            _ => Ok(Door { locked: false }),
+        });
+
+        let properties = properties(vec![("locked", PropertyValue::BoolValue(true))]);
+        {
+            let lazy = world.read_resource::<LazyUpdate>();
+            registry
+                .apply_class(door, "Door", &properties, &lazy)
+                .unwrap();
+            registry
+                .apply_class(other, "Window", &properties, &lazy)
+                .unwrap();
+
+            // Class properties are not matched against registered property names
+            registry.apply(other, &properties, &lazy).unwrap();
+        }
+        world.maintain();
+
+        let doors = world.read_storage::<Door>();
+        assert_eq!(doors.get(door), Some(&Door { locked: true }));
+        assert_eq!(doors.get(other), None);
+    }
+}
@@ -0,0 +1,277 @@
+//! Hot reloading of maps which tracks every file a map was loaded from
+use std::hash::Hasher;
+use std::sync::Arc;
+
+use amethyst::assets::{Format, FormatValue, Prefab, Reload, Source};
+use amethyst::Error;
+use tiled::{Map, Tileset};
+
+use crate::cache::{hash_bytes, StableHasher};
+
+/// The files something was loaded from along with the time each was last modified
+#[derive(Clone)]
+pub struct Dependencies {
+    source: Arc<dyn Source>,
+    files: Vec<(String, u64)>,
+}
+
+impl Dependencies {
+    pub fn new(source: Arc<dyn Source>) -> Self {
+        Self {
+            source,
+            files: Vec::new(),
+        }
+    }
+
+    /// Track a file using its current modification time
+    pub fn track(&mut self, path: &str) {
+        let modified = self.source.modified(path).unwrap_or(0);
+        self.push(path.to_owned(), modified);
+    }
+
+    /// Track a file which was last modified at the given time. Files without a modification time
+    /// are recorded as 0 and never considered to be changed.
+    pub fn push(&mut self, path: String, modified: u64) {
+        match self.files.iter_mut().find(|(file, _)| *file == path) {
+            Some(entry) => entry.1 = modified,
+            None => self.files.push((path, modified)),
+        }
+    }
+
+    /// Track the images of a tileset
+    pub fn track_tileset(&mut self, tileset: &Tileset) {
+        for image in &tileset.images {
+            self.track(&image.source);
+        }
+    }
+
+    /// Track the images of every tileset and image layer in a map. External tileset files have to
+    /// be tracked separately since their paths are no longer stored in the map once it is loaded.
+    pub fn track_map(&mut self, map: &Map) {
+        for tileset in &map.tilesets {
+            self.track_tileset(tileset.unwrap());
+        }
+
+        for layer in &map.image_layers {
+            if let Some(image) = &layer.image {
+                self.track(&image.source);
+            }
+        }
+    }
+
+    pub fn files(&self) -> impl Iterator<Item = &str> + '_ {
+        self.files.iter().map(|(file, _)| &file[..])
+    }
+
+    pub fn contains(&self, path: &str) -> bool {
+        self.files.iter().any(|(file, _)| file == path)
+    }
+
+    /// The files which have been modified since they were tracked
+    pub fn changed(&self) -> impl Iterator<Item = &str> + '_ {
+        self.files
+            .iter()
+            .filter(move |(file, modified)| {
+                *modified != 0 && self.source.modified(file).unwrap_or(0) > *modified
+            })
+            .map(|(file, _)| &file[..])
+    }
+
+    pub fn is_stale(&self) -> bool {
+        self.changed().next().is_some()
+    }
+
+    /// Record the current modification time of every tracked file
+    pub fn refresh(&mut self) {
+        for (file, modified) in &mut self.files {
+            *modified = self.source.modified(file).unwrap_or(0);
+        }
+    }
+
+    /// Hash the contents of every tracked file, which is `None` when any of them can not be read
+    pub fn hash_contents(&self) -> Option<u64> {
+        let mut hasher = StableHasher::new();
+
+        for (file, _) in &self.files {
+            hash_bytes(&self.source.load(file).ok()?[..], &mut hasher);
+        }
+
+        Some(hasher.finish())
+    }
+}
+
+/// Reloads a map when the map file or any of its tilesets or images are modified
+pub struct MapReload<D> {
+    format: Box<dyn Format<D>>,
+    path: String,
+    dependencies: Dependencies,
+}
+
+impl<D: 'static> MapReload<D> {
+    pub fn new(format: Box<dyn Format<D>>, path: String, dependencies: Dependencies) -> Self {
+        Self {
+            format,
+            path,
+            dependencies,
+        }
+    }
+}
+
+impl<D: 'static> Clone for MapReload<D> {
+    fn clone(&self) -> Self {
+        Self {
+            format: self.format.clone(),
+            path: self.path.clone(),
+            dependencies: self.dependencies.clone(),
+        }
+    }
+}
+
+impl<D: 'static> Reload<D> for MapReload<D> {
+    fn needs_reload(&self) -> bool {
+        self.dependencies.is_stale()
+    }
+
+    fn reload(self: Box<Self>) -> Result<FormatValue<D>, Error> {
+        let MapReload {
+            format,
+            path,
+            dependencies,
+        } = *self;
+
+        format.import(path, dependencies.source, Some(format.clone()))
+    }
+
+    fn name(&self) -> String {
+        self.path.clone()
+    }
+
+    fn format(&self) -> &'static str {
+        self.format.name()
+    }
+}
+
+/// Wraps the reload of the data of a prefab so the prefab itself can be reloaded
+pub(crate) struct PrefabReload<T>(pub Box<dyn Reload<T>>);
+
+impl<T: 'static> Clone for PrefabReload<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T: Send + Sync + 'static> Reload<Prefab<T>> for PrefabReload<T> {
+    fn needs_reload(&self) -> bool {
+        self.0.needs_reload()
+    }
+
+    fn reload(self: Box<Self>) -> Result<FormatValue<Prefab<T>>, Error> {
+        let value = self.0.reload()?;
+
+        Ok(FormatValue {
+            data: Prefab::new_main(value.data),
+            reload: value
+                .reload
+                .map(|reload| Box::new(PrefabReload(reload)) as Box<dyn Reload<Prefab<T>>>),
+        })
+    }
+
+    fn name(&self) -> String {
+        self.0.name()
+    }
+
+    fn format(&self) -> &'static str {
+        self.0.format()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prefab::TileMapPrefab;
+    use crate::strategy::CompressedLoad;
+    use crate::TiledFormat;
+    use amethyst::assets::Directory;
+    use std::fs::{self, File};
+    use std::path::PathBuf;
+    use std::time::{Duration, SystemTime};
+
+    const FILES: [&str; 3] = [
+        "prefab/example_map.tmx",
+        "prefab/example_tileset.tsx",
+        "texture/great_tileset.png",
+    ];
+
+    /// Copy the example map along with its tileset and image into a new directory
+    fn example_directory(name: &str) -> PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("amethyst-tiled-{}-{}", name, std::process::id()));
+        let assets = PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/examples/assets"));
+
+        for file in FILES.iter() {
+            let path = directory.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::copy(assets.join(file), path).unwrap();
+        }
+
+        directory
+    }
+
+    /// Mark a file as modified after every other file in the directory
+    fn touch(path: PathBuf) {
+        let file = File::options().write(true).open(path).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(60))
+            .unwrap();
+    }
+
+    fn map_reload(directory: &PathBuf) -> Box<dyn Reload<TileMapPrefab<CompressedLoad>>> {
+        let source = Arc::new(Directory::new(directory));
+        let format: Box<dyn Format<TileMapPrefab<CompressedLoad>>> = Box::new(TiledFormat);
+
+        let value = format
+            .import(
+                "prefab/example_map.tmx".to_owned(),
+                source,
+                Some(format.clone()),
+            )
+            .expect("The example map should load");
+        value.reload.expect("The map should be reloadable")
+    }
+
+    #[test]
+    fn map_files_are_tracked() {
+        for (n, file) in FILES.iter().enumerate() {
+            let directory = example_directory(&format!("reload-{}", n));
+            let reload = map_reload(&directory);
+            assert!(!reload.needs_reload());
+
+            touch(directory.join(file));
+            assert!(reload.needs_reload(), "{} should be tracked", file);
+
+            fs::remove_dir_all(directory).unwrap();
+        }
+    }
+
+    #[test]
+    fn dependencies_track_changed_files() {
+        let directory = example_directory("dependencies");
+        let mut dependencies = Dependencies::new(Arc::new(Directory::new(&directory)));
+
+        dependencies.track("prefab/example_tileset.tsx");
+        dependencies.push("texture/great_tileset.png".to_owned(), 0);
+        dependencies.track("prefab/missing.tsx");
+        assert!(!dependencies.is_stale());
+
+        // Files without a modification time are never considered changed
+        touch(directory.join("texture/great_tileset.png"));
+        assert!(!dependencies.is_stale());
+
+        touch(directory.join("prefab/example_tileset.tsx"));
+        assert_eq!(
+            dependencies.changed().collect::<Vec<_>>(),
+            vec!["prefab/example_tileset.tsx"]
+        );
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
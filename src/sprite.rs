@@ -0,0 +1,297 @@
+//! A load strategy which draws every tile as its own sprite, allowing maps of any orientation
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use amethyst::assets::{ProgressCounter, Source};
+use amethyst::core::math::{Vector2, Vector3};
+use amethyst::core::Transform;
+use amethyst::ecs::{Entity, WriteStorage};
+use amethyst::renderer::palette::Srgba;
+use amethyst::renderer::resources::Tint;
+use amethyst::renderer::transparent::Transparent;
+use amethyst::renderer::SpriteRender;
+use amethyst::Error;
+use tiled::{Map, Properties};
+
+use crate::bounds::{layer_tiles, MapBounds};
+use crate::orientation::MapOrientation;
+use crate::pages::AtlasPages;
+use crate::prefab::{create_child, ChildData};
+use crate::strategy::{load_compressed, LoadStrategy, PackData, StrategyDesc, TileLayer};
+use crate::{Flip, TileGid};
+
+#[cfg(feature = "profiler")]
+use thread_profiler::profile_scope;
+
+/// Loads a map as a sprite entity per tile. A `TileMap` can only draw orthogonal grids, so this is
+/// the strategy to use for isometric, staggered and hexagonal maps that need to stay editable.
+/// Tiles are placed with `MapBounds::tile_to_world` and given a depth within their layer so taller
+/// tiles overlap the tiles behind them. Animated tiles only show their first frame. Tiles from
+/// tilesets larger than the map's cells are aligned using the tile size of their own tileset, but
+/// tileset tile offsets and object alignment are not read by the tiled crate and are ignored.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct SpriteLoad;
+
+struct SpriteTile {
+    layer: usize,
+    transform: Transform,
+    sprite: usize,
+    /// The custom properties of the tile set in its tileset
+    properties: Properties,
+}
+
+/// The tiles of a map loaded by `SpriteLoad`
+pub struct SpriteMap {
+    pages: AtlasPages,
+    layers: Vec<TileLayer>,
+    tiles: Vec<SpriteTile>,
+}
+
+impl SpriteMap {
+    /// The sprite sheets the tiles are drawn with. Each tile is drawn with the sheet of the page
+    /// its sprite was packed into.
+    pub fn pages(&self) -> &AtlasPages {
+        &self.pages
+    }
+}
+
+impl StrategyDesc for SpriteLoad {
+    type Result = SpriteMap;
+}
+
+impl<'a> LoadStrategy<'a> for SpriteLoad {
+    type SystemData = PackData<'a>;
+    type SpawnData = WriteStorage<'a, TileLayer>;
+
+    fn load(
+        map: &Map,
+        bounds: &MapBounds,
+        source: Arc<dyn Source>,
+        progress: &mut ProgressCounter,
+        system_data: &mut Self::SystemData,
+    ) -> Result<Self::Result, Error> {
+        #[cfg(feature = "profiler")]
+        profile_scope!("SpriteLoad::load");
+
+        let (pages, tiles, _) = load_compressed(map, source, progress, bounds, system_data)?;
+
+        let layers: Vec<TileLayer> = map
+            .layers
+            .iter()
+            .map(|layer| TileLayer {
+                name: layer.name.to_owned(),
+                index: layer.layer_index,
+                visible: layer.visible,
+                opacity: layer.opacity,
+                offset: Vector2::new(layer.offset_x, layer.offset_y),
+                properties: layer.properties.clone(),
+            })
+            .collect();
+
+        // Tiles are placed with the layer index from Tiled, which is not always the position of
+        // the layer within the map's tile layers
+        let positions: HashMap<u32, usize> = layers
+            .iter()
+            .enumerate()
+            .map(|(position, layer)| (layer.index, position))
+            .collect();
+        let properties = tile_properties(map, bounds);
+        let alignments = tile_alignments(map, bounds);
+
+        let tiles = tiles
+            .into_iter()
+            .filter(|(_, tile)| *tile != TileGid::default())
+            .map(|(point, tile)| {
+                let position = positions[&point.z];
+                let layer = &layers[position];
+                let (x, y) = bounds.to_tiled(point.x, point.y);
+                let alignment = alignments.get(&(point.x, point.y, point.z));
+                let mut translation = bounds.tile_to_world(x, y);
+                if let Some(alignment) = alignment {
+                    translation += alignment;
+                }
+                translation.x += layer.offset.x;
+                translation.y -= layer.offset.y;
+                translation.z += layer.index as f32;
+
+                let mut transform = Transform::default();
+                transform.set_translation(translation);
+
+                SpriteTile {
+                    layer: position,
+                    transform,
+                    sprite: tile.sprite_index(),
+                    properties: properties
+                        .get(&(point.x, point.y, point.z))
+                        .map(|x| (*x).clone())
+                        .unwrap_or_default(),
+                }
+            })
+            .collect();
+
+        Ok(SpriteMap {
+            pages,
+            layers,
+            tiles,
+        })
+    }
+
+    fn supports(_orientation: MapOrientation) -> bool {
+        true
+    }
+
+    fn add_to_entity(
+        result: &Self::Result,
+        entity: Entity,
+        child_data: &mut ChildData<'a>,
+        spawn_data: &mut Self::SpawnData,
+    ) -> Result<(), Error> {
+        #[cfg(feature = "profiler")]
+        profile_scope!("SpriteLoad::add_to_entity");
+
+        for tile in &result.tiles {
+            let layer = &result.layers[tile.layer];
+            let child = create_child(
+                child_data,
+                entity,
+                &layer.name,
+                tile.transform.clone(),
+                layer.visible,
+                &tile.properties,
+            )?;
+
+            child_data.sprites.insert(
+                child,
+                SpriteRender {
+                    sprite_sheet: result.pages.sheet_of(tile.sprite).clone(),
+                    sprite_number: tile.sprite,
+                },
+            )?;
+
+            // Sprites are sorted by depth when transparent, which keeps the draw order of the tiles
+            child_data.transparent.insert(child, Transparent)?;
+
+            if layer.opacity < 1.0 {
+                let tint = Tint(Srgba::new(1.0, 1.0, 1.0, layer.opacity));
+                child_data.tints.insert(child, tint)?;
+            }
+
+            spawn_data.insert(child, layer.clone())?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Find the custom properties of every placed tile which has any, keyed by its position within the
+/// bounds and its layer
+fn tile_properties<'m>(
+    map: &'m Map,
+    bounds: &MapBounds,
+) -> HashMap<(u32, u32, u32), &'m Properties> {
+    let mut by_gid = HashMap::new();
+    for set in &map.tilesets {
+        let set = set.unwrap();
+        for tile in set.tiles.iter().filter(|x| !x.properties.is_empty()) {
+            by_gid.insert(set.first_gid + tile.id, &tile.properties);
+        }
+    }
+
+    let mut placed = HashMap::new();
+    if by_gid.is_empty() {
+        return placed;
+    }
+
+    for layer in &map.layers {
+        for (x, y, tile) in layer_tiles(layer) {
+            let (gid, _) = Flip::decode(tile.gid);
+
+            if let (Some(properties), Some((x, y))) = (by_gid.get(&gid), bounds.to_local(x, y)) {
+                placed.insert((x, y, layer.layer_index), *properties);
+            }
+        }
+    }
+
+    placed
+}
+
+/// Find the offset of every placed tile whose tileset has a different tile size to the map, keyed
+/// by its position within the bounds and its layer. Tiles larger than their cell are aligned to its
+/// bottom left corner, using the size of the tileset each gid belongs to.
+fn tile_alignments(map: &Map, bounds: &MapBounds) -> HashMap<(u32, u32, u32), Vector3<f32>> {
+    let mut sets: Vec<(u32, Vector3<f32>)> = map
+        .tilesets
+        .iter()
+        .map(|set| {
+            let set = set.unwrap();
+            let alignment = Vector3::new(
+                (set.tile_width as f32 - map.tile_width as f32) / 2.0,
+                (set.tile_height as f32 - map.tile_height as f32) / 2.0,
+                0.0,
+            );
+            (set.first_gid, alignment)
+        })
+        .collect();
+    sets.sort_by_key(|(first_gid, _)| *first_gid);
+
+    let mut placed = HashMap::new();
+    if sets
+        .iter()
+        .all(|(_, alignment)| *alignment == Vector3::zeros())
+    {
+        return placed;
+    }
+
+    for layer in &map.layers {
+        for (x, y, tile) in layer_tiles(layer) {
+            let (gid, _) = Flip::decode(tile.gid);
+
+            // The tileset of a gid is the last one starting at or before it
+            let alignment = sets.iter().rev().find(|(first_gid, _)| *first_gid <= gid);
+
+            if let (Some((_, alignment)), Some((x, y))) = (alignment, bounds.to_local(x, y)) {
+                if gid != 0 && *alignment != Vector3::zeros() {
+                    placed.insert((x, y, layer.layer_index), *alignment);
+                }
+            }
+        }
+    }
+
+    placed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tiled::parse;
+
+    #[test]
+    fn tiles_are_aligned_by_their_own_tileset() {
+        let document = r#"<?xml version="1.0" encoding="UTF-8"?>
+<map version="1.2" orientation="orthogonal" renderorder="right-down" width="2" height="2" tilewidth="16" tileheight="16" infinite="0" nextlayerid="2" nextobjectid="1">
+ <tileset firstgid="1" name="small" tilewidth="16" tileheight="16" tilecount="4" columns="2">
+  <image source="small.png" width="32" height="32"/>
+ </tileset>
+ <tileset firstgid="5" name="large" tilewidth="32" tileheight="48" tilecount="2" columns="2">
+  <image source="large.png" width="64" height="48"/>
+ </tileset>
+ <layer id="1" name="Ground" width="2" height="2">
+  <data encoding="csv">
+1,5,
+6,0
+</data>
+ </layer>
+</map>"#;
+
+        let map = parse(document.as_bytes()).unwrap();
+        let alignments = tile_alignments(&map, &MapBounds::of(&map));
+        let z = map.layers[0].layer_index;
+
+        let large = Vector3::new(8.0, 16.0, 0.0);
+        assert_eq!(alignments.get(&(0, 0, z)), None);
+        assert_eq!(alignments.get(&(1, 0, z)), Some(&large));
+        assert_eq!(alignments.get(&(0, 1, z)), Some(&large));
+        assert_eq!(alignments.get(&(1, 1, z)), None);
+    }
+}
@@ -1,16 +1,34 @@
 use amethyst::assets::{AssetStorage, Handle, Loader, ProgressCounter, Source};
-use amethyst::core::math::{Point3, Vector3};
-use amethyst::ecs::{Read, ReadExpect, SystemData, Write};
-use amethyst::renderer::{SpriteSheet, Texture};
+use amethyst::core::math::{Point3, Vector2, Vector3};
+use amethyst::core::Transform;
+use amethyst::ecs::{
+    Component, DenseVecStorage, Entity, Read, ReadExpect, SystemData, Write, WriteStorage,
+};
+use amethyst::renderer::transparent::Transparent;
+use amethyst::renderer::{sprite::Sprite, SpriteRender, SpriteSheet, Texture};
 use amethyst::tiles::{CoordinateEncoder, FlatEncoder, MapStorage, TileMap};
 use amethyst::Error;
-use tiled::{Map, Tileset};
-
-use crate::packing::{pack_sparse_tileset_vec, pack_tileset_vec};
-use crate::{load_sprite_sheet, TileGid};
+use image::imageops::overlay;
+use image::RgbaImage;
+use tiled::{Map, Properties, Tileset};
+
+use crate::animation::{collect_frames, TileAnimation, TileAnimations};
+use crate::bake::{load_baked_sheet, load_baked_tiles, BakedTiles, ATLAS_KIND};
+use crate::bounds::{layer_tiles, tile_depth, MapBounds};
+use crate::cache::{atlas_key, cached_atlas, AtlasCache, PackedAtlas};
+use crate::editing::AtlasLayout;
+use crate::orientation::MapOrientation;
+use crate::packing::{
+    pack_sparse_tileset_vec, pack_tileset_vec, FlipVariants, PackSettings, TileImages,
+};
+use crate::pages::AtlasPages;
+use crate::prefab::{create_child, ChildData};
+use crate::{load_texture, Flip, TileGid};
+use std::collections::hash_map::Entry;
 use std::collections::{BTreeSet, HashMap};
 use std::marker::PhantomData;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 #[cfg(feature = "profiler")]
 use thread_profiler::profile_scope;
@@ -24,33 +42,73 @@ pub trait LoadStrategy<'a>: StrategyDesc {
     /// The data to request when loading a map
     type SystemData: SystemData<'a>;
 
-    // Preform the load operation using a given map and source location
+    /// The data to request when adding a loaded map to an entity
+    type SpawnData: SystemData<'a>;
+
+    // Preform the load operation using a given map and source location. The bounds of the map
+    // include its orientation, which is read from the map file.
     fn load(
         map: &Map,
+        bounds: &MapBounds,
         source: Arc<dyn Source>,
         progress: &mut ProgressCounter,
         system_data: &mut Self::SystemData,
     ) -> Result<<Self as StrategyDesc>::Result, Error>;
+
+    /// Load a map which was read from the file `name` within the source. Only strategies which
+    /// look for other files next to the map need to override this.
+    fn load_file(
+        _name: &str,
+        map: &Map,
+        bounds: &MapBounds,
+        source: Arc<dyn Source>,
+        progress: &mut ProgressCounter,
+        system_data: &mut Self::SystemData,
+    ) -> Result<<Self as StrategyDesc>::Result, Error> {
+        Self::load(map, bounds, source, progress, system_data)
+    }
+
+    /// Whether maps with the given orientation can be drawn. Strategies which place their tiles in
+    /// a `TileMap` can only draw orthogonal maps.
+    fn supports(orientation: MapOrientation) -> bool {
+        orientation == MapOrientation::Orthogonal
+    }
+
+    /// Add the result of a load to the map entity along with any child entities it requires
+    fn add_to_entity(
+        result: &<Self as StrategyDesc>::Result,
+        entity: Entity,
+        child_data: &mut ChildData<'a>,
+        spawn_data: &mut Self::SpawnData,
+    ) -> Result<(), Error>;
 }
 
+/// The data used by strategies which pack the tiles of a map into a sprite sheet
+pub type PackData<'a> = (
+    ReadExpect<'a, Loader>,
+    Read<'a, AssetStorage<Texture>>,
+    Read<'a, AssetStorage<SpriteSheet>>,
+    Write<'a, TileAnimations>,
+    Option<Read<'a, AtlasCache>>,
+    Read<'a, PackSettings>,
+);
+
 /// A load strategy that does not take steps to compress the tile sets. This is the most efficient
 /// approach for maps that use all or near all of the tiles in a tileset.
 #[derive(Debug, Copy, Clone, Default)]
 pub struct FlatLoad<E: CoordinateEncoder = FlatEncoder>(PhantomData<E>);
 
 impl<E: CoordinateEncoder> StrategyDesc for FlatLoad<E> {
-    type Result = TileMap<TileGid, E>;
+    type Result = PagedMap<E>;
 }
 
 impl<'a, E: CoordinateEncoder> LoadStrategy<'a> for FlatLoad<E> {
-    type SystemData = (
-        ReadExpect<'a, Loader>,
-        Read<'a, AssetStorage<Texture>>,
-        Write<'a, AssetStorage<SpriteSheet>>,
-    );
+    type SystemData = PackData<'a>;
+    type SpawnData = AtlasData<'a, E>;
 
     fn load(
         map: &Map,
+        bounds: &MapBounds,
         source: Arc<dyn Source>,
         progress: &mut ProgressCounter,
         system_data: &mut Self::SystemData,
@@ -58,32 +116,145 @@ impl<'a, E: CoordinateEncoder> LoadStrategy<'a> for FlatLoad<E> {
         #[cfg(feature = "profiler")]
         profile_scope!("FlatLoad::load");
 
-        let (loader, storage, sheets) = system_data;
-
-        let packed = pack_tileset_vec(
-            &map.tilesets.iter().map(|x| x.unwrap().clone()).collect::<Vec<Tileset>>()[..],
-            source,
+        let (loader, storage, sheets, animations, cache, settings) = system_data;
+        let settings = **settings;
+        let frames = collect_frames(map);
+        let usage = collect_gid_usage(map, &frames);
+        let transposed: Vec<u32> = usage.transposed.into_iter().collect();
+
+        let sets = map
+            .tilesets
+            .iter()
+            .map(|x| x.unwrap().clone())
+            .collect::<Vec<Tileset>>();
+        let atlas = cached_atlas(
+            cache.as_deref(),
+            || atlas_key("flat", &sets[..], &*source, &[], &transposed[..], settings),
+            None,
+            &transposed[..],
+            || {
+                let packed =
+                    pack_tileset_vec(&sets[..], source.clone(), &transposed[..], settings)?;
+                let mut atlas = PackedAtlas::new(packed, settings, Vec::new(), transposed.clone());
+                atlas.gids = (0..(atlas.sprites.len() - transposed.len()) as u32).collect();
+                Ok(atlas)
+            },
         )?;
+        let gids = atlas.gids.clone();
+        let mut sheet = atlas.into_pages(loader, progress, storage);
+
+        let transposed: Vec<usize> = transposed.iter().map(|x| *x as usize).collect();
+        let tile_count = gids.len();
+        let mut variants = FlipVariants::new(&transposed[..], tile_count);
+        let mut resolver = TileResolver {
+            sprites: &mut sheet.sprites,
+            variants: &mut variants,
+            frames: &frames,
+            animations,
+            atlas: TileAtlas::default(),
+        };
+        let index_of = |gid: u32| Some(gid as usize);
+        let tiles = resolver.resolve_map(map, bounds, index_of);
+        let atlas = resolver.finish(0..tile_count as u32, index_of);
+
+        let pages = sheet.load(&variants, loader, progress, sheets);
+        let ids = atlas.animations();
+        animations.set_owners(ids.iter().copied(), pages.sheets());
+        pages.check_animations(&ids, animations)?;
+        let atlas = match pages.len() == 1 {
+            true => {
+                let sheet = pages.sheets()[0].clone();
+                let layout = AtlasLayout::new(sheet, sets, source, frames, &gids[..], variants)
+                    .with_settings(settings);
+                atlas.with_layout(layout)
+            }
+            false => atlas,
+        };
+
+        Ok(PagedMap::new(map, bounds, &pages, tiles, atlas))
+    }
+
+    fn add_to_entity(
+        result: &Self::Result,
+        entity: Entity,
+        child_data: &mut ChildData<'a>,
+        spawn_data: &mut Self::SpawnData,
+    ) -> Result<(), Error> {
+        insert_atlas_map(result, entity, child_data, spawn_data)
+    }
+}
 
-        let map_size = Vector3::new(map.width, map.height, map.layers.len() as u32);
+/// The data used by strategies which add a `TileMap` and its `TileAtlas` to the map entity
+pub type AtlasData<'a, E> = (
+    WriteStorage<'a, TileMap<TileGid, E>>,
+    WriteStorage<'a, TileAtlas>,
+);
+
+/// A map with a `TileMap` and `TileAtlas` for every page of its sprite sheet. The first page is
+/// added to the map entity and every other page to a child entity. Each atlas only holds the tiles
+/// which can be drawn from its page.
+#[derive(Clone)]
+pub struct PagedMap<E: CoordinateEncoder = FlatEncoder> {
+    pub pages: Vec<(TileMap<TileGid, E>, TileAtlas)>,
+}
+
+impl<E: CoordinateEncoder> PagedMap<E> {
+    /// Place every tile in the tile map of the page it is drawn from
+    pub(crate) fn new(
+        map: &Map,
+        bounds: &MapBounds,
+        pages: &AtlasPages,
+        tiles: Vec<(Point3<u32>, TileGid)>,
+        atlas: TileAtlas,
+    ) -> Self {
+        let map_size = Vector3::new(bounds.width, bounds.height, tile_depth(map));
         let tile_size = Vector3::new(map.tile_width, map.tile_height, 1);
-        let sheet = load_sprite_sheet(packed, loader, progress, storage);
 
-        let mut tilemap = TileMap::new(map_size, tile_size, Some(sheets.insert(sheet)));
+        let pages = pages
+            .split(tiles)
+            .into_iter()
+            .zip(pages.split_atlas(atlas))
+            .zip(pages.sheets())
+            .map(|((tiles, atlas), sheet)| {
+                let mut tilemap = TileMap::new(map_size, tile_size, Some(sheet.clone()));
+                fill_tilemap(&mut tilemap, tiles);
+                (tilemap, atlas)
+            })
+            .collect();
+
+        Self { pages }
+    }
+}
 
-        for layer in &map.layers {
-            for y in 0..layer.tiles.len() {
-                for x in 0..layer.tiles[y].len() {
-                    match tilemap.get_mut(&Point3::new(x as u32, y as u32, layer.layer_index)) {
-                        Some(v) => *v = TileGid(layer.tiles[y][x].gid as usize),
-                        None => unreachable!("The map file was corrupt"),
-                    }
-                }
-            }
+pub(crate) fn insert_atlas_map<E: CoordinateEncoder>(
+    result: &PagedMap<E>,
+    entity: Entity,
+    child_data: &mut ChildData<'_>,
+    (tilemaps, atlases): &mut AtlasData<'_, E>,
+) -> Result<(), Error> {
+    let mut targets = vec![entity];
+    for page in 1..result.pages.len() {
+        targets.push(create_child(
+            child_data,
+            entity,
+            &format!("page {}", page),
+            Transform::default(),
+            true,
+            &Properties::default(),
+        )?);
+    }
+
+    for (page, ((tilemap, atlas), target)) in result.pages.iter().zip(&targets).enumerate() {
+        let mut atlas = atlas.clone();
+        if page == 0 {
+            atlas.pages = targets[1..].to_vec();
         }
 
-        Ok(tilemap)
+        tilemaps.insert(*target, tilemap.clone())?;
+        atlases.insert(*target, atlas)?;
     }
+
+    Ok(())
 }
 
 /// A version of FlatLoad that tries to save time and memory by skipping unused tiles when
@@ -97,18 +268,16 @@ impl<'a, E: CoordinateEncoder> LoadStrategy<'a> for FlatLoad<E> {
 pub struct CompressedLoad<E: CoordinateEncoder = FlatEncoder>(PhantomData<E>);
 
 impl<E: CoordinateEncoder> StrategyDesc for CompressedLoad<E> {
-    type Result = TileMap<TileGid, E>;
+    type Result = PagedMap<E>;
 }
 
 impl<'a, E: CoordinateEncoder> LoadStrategy<'a> for CompressedLoad<E> {
-    type SystemData = (
-        ReadExpect<'a, Loader>,
-        Read<'a, AssetStorage<Texture>>,
-        Write<'a, AssetStorage<SpriteSheet>>,
-    );
+    type SystemData = PackData<'a>;
+    type SpawnData = AtlasData<'a, E>;
 
     fn load(
         map: &Map,
+        bounds: &MapBounds,
         source: Arc<dyn Source>,
         progress: &mut ProgressCounter,
         system_data: &mut Self::SystemData,
@@ -116,59 +285,489 @@ impl<'a, E: CoordinateEncoder> LoadStrategy<'a> for CompressedLoad<E> {
         #[cfg(feature = "profiler")]
         profile_scope!("CompressedLoad::load");
 
-        let (loader, storage, sheets) = system_data;
-        let tile_usage: Vec<u32> = collect_gid_usage(map).into_iter().collect();
+        let (pages, tiles, atlas) = load_compressed(map, source, progress, bounds, system_data)?;
+
+        Ok(PagedMap::new(map, bounds, &pages, tiles, atlas))
+    }
+
+    fn add_to_entity(
+        result: &Self::Result,
+        entity: Entity,
+        child_data: &mut ChildData<'a>,
+        spawn_data: &mut Self::SpawnData,
+    ) -> Result<(), Error> {
+        insert_atlas_map(result, entity, child_data, spawn_data)
+    }
+}
+
+/// Pack the tiles used by a map into the pages of a sprite sheet, skipping any tiles which are
+/// never used, and find the tile placed in every cell of the map along with the atlas of the
+/// packed tiles.
+pub(crate) fn load_compressed(
+    map: &Map,
+    source: Arc<dyn Source>,
+    progress: &mut ProgressCounter,
+    bounds: &MapBounds,
+    system_data: &mut PackData<'_>,
+) -> Result<(AtlasPages, Vec<(Point3<u32>, TileGid)>, TileAtlas), Error> {
+    load_packed(map, None, source, progress, bounds, system_data)
+}
 
-        let mut gid_updater = HashMap::new();
+/// A version of `load_compressed` which uses the baked atlas and tiles of the map file `name` when
+/// it has not changed since it was baked, instead of packing the map and reading its layers.
+pub(crate) fn load_packed(
+    map: &Map,
+    name: Option<&str>,
+    source: Arc<dyn Source>,
+    progress: &mut ProgressCounter,
+    bounds: &MapBounds,
+    system_data: &mut PackData<'_>,
+) -> Result<(AtlasPages, Vec<(Point3<u32>, TileGid)>, TileAtlas), Error> {
+    #[cfg(feature = "profiler")]
+    profile_scope!("load_compressed");
+
+    let (loader, storage, sheets, animations, cache, settings) = system_data;
+    let settings = **settings;
+    let frames = collect_frames(map);
+    let sets = map
+        .tilesets
+        .iter()
+        .map(|x| x.unwrap().clone())
+        .collect::<Vec<Tileset>>();
+    let key = |gids: &[u32], transposed: &[u32]| {
+        atlas_key(ATLAS_KIND, &sets, &*source, gids, transposed, settings)
+    };
+
+    let baked_tiles = name
+        .and_then(|name| load_baked_tiles(name, &*source, bounds))
+        .filter(|x| x.packs_frames(&frames));
+    let baked = match (name, baked_tiles) {
+        (Some(name), Some(tiles)) => {
+            let baked_key = || key(&tiles.gids, &tiles.transposed);
+            load_baked_sheet(name, &*source, baked_key, &tiles, loader, progress, storage)?
+                .map(|sheet| (sheet, tiles))
+        }
+        _ => None,
+    };
+
+    let (tile_usage, transposed): (Vec<u32>, Vec<u32>) = match &baked {
+        Some((_, tiles)) => (tiles.gids.clone(), tiles.transposed.clone()),
+        None => {
+            let usage = collect_gid_usage(map, &frames);
+            (
+                usage.gids.into_iter().collect(),
+                usage.transposed.into_iter().collect(),
+            )
+        }
+    };
+
+    let mut gid_updater = HashMap::new();
+
+    for (new_index, old_index) in tile_usage.iter().enumerate() {
+        gid_updater.insert(*old_index, new_index);
+    }
 
-        for (new_index, old_index) in tile_usage.iter().enumerate() {
-            gid_updater.insert(*old_index, new_index);
+    let (mut sheet, baked_tiles) = match baked {
+        Some((sheet, tiles)) => (sheet, Some(tiles)),
+        None => {
+            let sheet = cached_atlas(
+                cache.as_deref(),
+                || key(&tile_usage, &transposed),
+                Some(&tile_usage[..]),
+                &transposed[..],
+                || {
+                    let packed = pack_sparse_tileset_vec(
+                        &sets[..],
+                        source.clone(),
+                        &tile_usage,
+                        &transposed,
+                        settings,
+                    )?;
+                    Ok(PackedAtlas::new(
+                        packed,
+                        settings,
+                        tile_usage.clone(),
+                        transposed.clone(),
+                    ))
+                },
+            )?;
+            (sheet.into_pages(loader, progress, storage), None)
         }
+    };
+
+    let transposed: Vec<usize> = transposed.iter().map(|x| gid_updater[x]).collect();
+    let mut variants = FlipVariants::new(&transposed[..], sheet.sprites.len() - transposed.len());
+    let mut resolver = TileResolver {
+        sprites: &mut sheet.sprites,
+        variants: &mut variants,
+        frames: &frames,
+        animations,
+        atlas: TileAtlas::default(),
+    };
+    let index_of = |gid: u32| gid_updater.get(&gid).copied();
+    let tiles = match &baked_tiles {
+        Some(baked) => resolver.resolve_baked(baked, index_of),
+        None => resolver.resolve_map(map, bounds, index_of),
+    };
+    let atlas = resolver.finish(tile_usage.iter().copied(), index_of);
+
+    let pages = sheet.load(&variants, loader, progress, sheets);
+    let ids = atlas.animations();
+    animations.set_owners(ids.iter().copied(), pages.sheets());
+    pages.check_animations(&ids, animations)?;
+    let atlas = match pages.len() == 1 {
+        true => {
+            let sheet = pages.sheets()[0].clone();
+            let layout = AtlasLayout::new(sheet, sets, source, frames, &tile_usage[..], variants)
+                .with_settings(settings);
+            atlas.with_layout(layout)
+        }
+        false => atlas,
+    };
 
-        let packed = pack_sparse_tileset_vec(
-            &map.tilesets.iter().map(|x| x.unwrap().clone()).collect::<Vec<Tileset>>()[..],
-            source,
-            &tile_usage[..],
-        )?;
+    Ok((pages, tiles, atlas))
+}
+
+/// Component describing a single tile layer of a map loaded with `LayeredLoad`
+#[derive(Debug, Clone)]
+pub struct TileLayer {
+    pub name: String,
+    /// The position of the layer within the map's layers
+    pub index: u32,
+    pub visible: bool,
+    pub opacity: f32,
+    /// The offset of the layer in pixels as set in Tiled
+    pub offset: Vector2<f32>,
+    pub properties: Properties,
+}
+
+impl Component for TileLayer {
+    type Storage = DenseVecStorage<Self>;
+}
+
+/// A map split into a separate `TileMap` for each tile layer
+#[derive(Clone)]
+pub struct LayeredMap<E: CoordinateEncoder = FlatEncoder> {
+    pub layers: Vec<(TileLayer, TileMap<TileGid, E>)>,
+}
+
+/// A version of CompressedLoad which creates a `TileMap` entity for every tile layer as children of
+/// the map entity. This allows layers to be hidden or reordered at runtime. All of the layers share
+/// a single sprite sheet, unless it was split into pages, in which case a layer gets an entity for
+/// every page it has tiles from.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct LayeredLoad<E: CoordinateEncoder = FlatEncoder>(PhantomData<E>);
+
+impl<E: CoordinateEncoder> StrategyDesc for LayeredLoad<E> {
+    type Result = LayeredMap<E>;
+}
+
+impl<'a, E: CoordinateEncoder> LoadStrategy<'a> for LayeredLoad<E> {
+    type SystemData = PackData<'a>;
+    type SpawnData = (
+        WriteStorage<'a, TileMap<TileGid, E>>,
+        WriteStorage<'a, TileLayer>,
+    );
+
+    fn load(
+        map: &Map,
+        bounds: &MapBounds,
+        source: Arc<dyn Source>,
+        progress: &mut ProgressCounter,
+        system_data: &mut Self::SystemData,
+    ) -> Result<Self::Result, Error> {
+        #[cfg(feature = "profiler")]
+        profile_scope!("LayeredLoad::load");
+
+        let (pages, tiles, _) = load_compressed(map, source, progress, bounds, system_data)?;
 
-        let map_size = Vector3::new(map.width, map.height, map.layers.len() as u32);
+        let map_size = Vector3::new(bounds.width, bounds.height, 1);
         let tile_size = Vector3::new(map.tile_width, map.tile_height, 1);
-        let sheet = load_sprite_sheet(packed, loader, progress, storage);
+        let create_tilemap =
+            |page: usize| TileMap::new(map_size, tile_size, Some(pages.sheets()[page].clone()));
 
-        let mut tilemap = TileMap::new(map_size, tile_size, Some(sheets.insert(sheet)));
+        // The position of each layer within the layers for every page it has tiles from
+        let mut positions = HashMap::new();
+        let mut layers = Vec::with_capacity(map.layers.len());
 
         for layer in &map.layers {
-            for y in 0..layer.tiles.len() {
-                for x in 0..layer.tiles[y].len() {
-                    let tile_ref =
-                        tilemap.get_mut(&Point3::new(x as u32, y as u32, layer.layer_index));
-                    let tile_idx = gid_updater.get(&layer.tiles[y][x].gid);
-
-                    match (tile_ref, tile_idx) {
-                        (Some(tile), Some(index)) => *tile = TileGid(*index),
-                        _ => unreachable!("The available tiles should not have changed since the start of the function"),
-                    }
+            positions.insert((layer.layer_index, 0), layers.len());
+            layers.push((
+                TileLayer {
+                    name: layer.name.to_owned(),
+                    index: layer.layer_index,
+                    visible: layer.visible,
+                    opacity: layer.opacity,
+                    offset: Vector2::new(layer.offset_x, layer.offset_y),
+                    properties: layer.properties.clone(),
+                },
+                create_tilemap(0),
+            ));
+        }
+
+        for (point, tile) in tiles {
+            let page = pages.page_of(tile.sprite_index());
+            let position = match positions.get(&(point.z, page)) {
+                Some(v) => *v,
+                None => {
+                    let layer = layers[positions[&(point.z, 0)]].0.clone();
+                    layers.push((layer, create_tilemap(page)));
+                    positions.insert((point.z, page), layers.len() - 1);
+                    layers.len() - 1
                 }
+            };
+            let (_, tilemap) = &mut layers[position];
+
+            match tilemap.get_mut(&Point3::new(point.x, point.y, 0)) {
+                Some(v) => *v = tile,
+                None => unreachable!("The map file was corrupt"),
             }
         }
 
-        Ok(tilemap)
+        Ok(LayeredMap { layers })
+    }
+
+    fn add_to_entity(
+        result: &Self::Result,
+        entity: Entity,
+        child_data: &mut ChildData<'a>,
+        spawn_data: &mut Self::SpawnData,
+    ) -> Result<(), Error> {
+        let (tilemaps, tile_layers) = spawn_data;
+
+        for (layer, tilemap) in &result.layers {
+            let mut transform = Transform::default();
+            transform.set_translation_xyz(layer.offset.x, -layer.offset.y, layer.index as f32);
+
+            let child = create_child(
+                child_data,
+                entity,
+                &layer.name,
+                transform,
+                layer.visible,
+                &layer.properties,
+            )?;
+            tilemaps.insert(child, tilemap.clone())?;
+            tile_layers.insert(child, layer.clone())?;
+        }
+
+        Ok(())
     }
 }
 
-fn collect_gid_usage(map: &Map) -> BTreeSet<u32> {
+/// The tiles placed in a map with their flip flags removed
+#[derive(Debug, Default)]
+pub(crate) struct GidUsage {
+    pub(crate) gids: BTreeSet<u32>,
+    /// Tiles which are placed at least once with a diagonal flip
+    pub(crate) transposed: BTreeSet<u32>,
+}
+
+/// Collect the tiles used by the map. The frames of animated tiles are included even if they are
+/// never placed directly.
+pub(crate) fn collect_gid_usage(
+    map: &Map,
+    frames: &HashMap<u32, Vec<(u32, Duration)>>,
+) -> GidUsage {
     #[cfg(feature = "profiler")]
     profile_scope!("collect_gid_usage");
 
-    let mut gids = BTreeSet::new();
+    let mut usage = GidUsage::default();
     for layer in &map.layers {
-        for row in &layer.tiles {
-            for tile in row {
-                gids.insert(tile.gid);
+        for (_, _, tile) in layer_tiles(layer) {
+            let (gid, flip) = Flip::decode(tile.gid);
+            usage.gids.insert(gid);
+
+            if flip.diagonal {
+                usage.transposed.insert(gid);
+            }
+
+            for (frame, _) in frames.get(&gid).into_iter().flatten() {
+                usage.gids.insert(*frame);
+
+                if flip.diagonal {
+                    usage.transposed.insert(*frame);
+                }
             }
         }
     }
-    gids
+    usage
+}
+
+/// Component added to the map entity by `FlatLoad` and `CompressedLoad` which maps the gids used
+/// in Tiled to the tiles of the packed sprite sheet. Only the orientations of each tile which were
+/// placed in the map are available, along with the unflipped version of every packed tile. Other
+/// tiles can be added with `TileAtlas::pack` or placed directly with `editing::set_tiles`.
+#[derive(Debug, Clone, Default)]
+pub struct TileAtlas {
+    pub(crate) tiles: HashMap<(u32, Flip), TileGid>,
+    /// How the sprite sheet was packed, which is kept so it can be extended
+    pub(crate) layout: Option<Arc<Mutex<AtlasLayout>>>,
+    /// The child entities holding the other pages of the map when its sprite sheet was split. Only
+    /// the atlas of the first page lists them.
+    pub(crate) pages: Vec<Entity>,
+}
+
+impl Component for TileAtlas {
+    type Storage = DenseVecStorage<Self>;
+}
+
+impl TileAtlas {
+    /// Find the tile for a gid from Tiled including its flip flags
+    pub fn get(&self, gid: u32) -> Option<TileGid> {
+        let (gid, flip) = Flip::decode(gid);
+
+        match gid {
+            0 => Some(TileGid::default()),
+            _ => self.tiles.get(&(gid, flip)).copied(),
+        }
+    }
+
+    pub fn contains(&self, gid: u32) -> bool {
+        self.get(gid).is_some()
+    }
+
+    /// Iterate over every available gid, including its flip flags, and its tile
+    pub fn iter(&self) -> impl Iterator<Item = (u32, TileGid)> + '_ {
+        self.tiles
+            .iter()
+            .map(|((gid, flip), tile)| (flip.encode(*gid), *tile))
+    }
+
+    /// The ids of the animations used by the tiles of the atlas
+    pub(crate) fn animations(&self) -> Vec<usize> {
+        self.tiles.values().filter_map(TileGid::animation).collect()
+    }
+}
+
+/// Finds the tile to place in every cell of a map while adding any sprites and animations needed
+/// to draw them.
+pub(crate) struct TileResolver<'s> {
+    pub(crate) sprites: &'s mut Vec<Sprite>,
+    pub(crate) variants: &'s mut FlipVariants,
+    pub(crate) frames: &'s HashMap<u32, Vec<(u32, Duration)>>,
+    pub(crate) animations: &'s mut TileAnimations,
+    /// Placements of a tile with the same orientation share a single tile and animation
+    pub(crate) atlas: TileAtlas,
+}
+
+impl<'s> TileResolver<'s> {
+    /// Resolve every cell of the map. `index_of` gives the sprite index of an unflipped gid within
+    /// the packed sprite sheet.
+    fn resolve_map<F: Fn(u32) -> Option<usize>>(
+        &mut self,
+        map: &Map,
+        bounds: &MapBounds,
+        index_of: F,
+    ) -> Vec<(Point3<u32>, TileGid)> {
+        #[cfg(feature = "profiler")]
+        profile_scope!("resolve_tiles");
+
+        let mut tiles = Vec::new();
+
+        for layer in &map.layers {
+            for (x, y, tile) in layer_tiles(layer) {
+                let (gid, flip) = Flip::decode(tile.gid);
+                let tile = self.resolve(&index_of, gid, flip);
+
+                let (x, y) = local_position(bounds, x, y);
+                tiles.push((Point3::new(x, y, layer.layer_index), tile));
+            }
+        }
+
+        tiles
+    }
+
+    /// Resolve every tile of a baked map, which is placed the same way as the map it was baked from
+    fn resolve_baked<F: Fn(u32) -> Option<usize>>(
+        &mut self,
+        baked: &BakedTiles,
+        index_of: F,
+    ) -> Vec<(Point3<u32>, TileGid)> {
+        #[cfg(feature = "profiler")]
+        profile_scope!("resolve_baked_tiles");
+
+        baked
+            .tiles()
+            .map(|(position, gid)| {
+                let (gid, flip) = Flip::decode(gid);
+                (position, self.resolve(&index_of, gid, flip))
+            })
+            .collect()
+    }
+
+    /// Find the tile for a gid, adding a new animation if it is animated and has not been seen
+    /// with this orientation before
+    pub(crate) fn resolve<F: Fn(u32) -> Option<usize>>(
+        &mut self,
+        index_of: &F,
+        gid: u32,
+        flip: Flip,
+    ) -> TileGid {
+        if let Some(tile) = self.atlas.tiles.get(&(gid, flip)) {
+            return *tile;
+        }
+
+        let sprite = self.sprite(index_of, gid, flip);
+
+        let tile = match self.frames.get(&gid) {
+            Some(frames) => {
+                let frames = frames
+                    .iter()
+                    .map(|(frame, duration)| (self.sprite(index_of, *frame, flip), *duration))
+                    .collect();
+
+                let id = self.animations.push(TileAnimation::new(frames));
+                TileGid::animated(sprite, flip, id)
+            }
+            None => TileGid::new(sprite, flip),
+        };
+
+        self.atlas.tiles.insert((gid, flip), tile);
+        tile
+    }
+
+    fn sprite<F: Fn(u32) -> Option<usize>>(&mut self, index_of: &F, gid: u32, flip: Flip) -> usize {
+        match index_of(gid) {
+            Some(index) => self.variants.resolve(self.sprites, index, flip),
+            None => unreachable!(
+                "The available tiles should not have changed since the start of the function"
+            ),
+        }
+    }
+
+    /// Add the unflipped version of every packed tile to the atlas so they can be placed later
+    fn finish<F: Fn(u32) -> Option<usize>>(
+        mut self,
+        gids: impl Iterator<Item = u32>,
+        index_of: F,
+    ) -> TileAtlas {
+        for gid in gids.filter(|x| *x != 0) {
+            self.resolve(&index_of, gid, Flip::NONE);
+        }
+
+        self.atlas
+    }
+}
+
+/// Find the position of a tile within the bounds of the map it was taken from
+fn local_position(bounds: &MapBounds, x: i32, y: i32) -> (u32, u32) {
+    match bounds.to_local(x, y) {
+        Some(v) => v,
+        None => unreachable!("The map bounds should contain every tile"),
+    }
+}
+
+pub(crate) fn fill_tilemap<E: CoordinateEncoder>(
+    tilemap: &mut TileMap<TileGid, E>,
+    tiles: Vec<(Point3<u32>, TileGid)>,
+) {
+    for (point, tile) in tiles {
+        match tilemap.get_mut(&point) {
+            Some(v) => *v = tile,
+            None => unreachable!("The map file was corrupt"),
+        }
+    }
 }
 
 /// Loads a tilemap into memory as a single texture. This is by far the best option for performance
@@ -177,20 +776,157 @@ fn collect_gid_usage(map: &Map) -> BTreeSet<u32> {
 #[derive(Debug, Copy, Clone, Default)]
 pub struct StaticLoad;
 
+/// A map which has been baked into a single texture by `StaticLoad`. The map entity is drawn with
+/// a `SpriteRender` of `sheet`.
+#[derive(Debug, Clone)]
+pub struct StaticMap {
+    pub texture: Handle<Texture>,
+    /// The sheet holding the single sprite which covers the entire map
+    pub sheet: Handle<SpriteSheet>,
+    /// The width of the texture in pixels
+    pub width: u32,
+    /// The height of the texture in pixels
+    pub height: u32,
+}
+
+impl StaticMap {
+    /// Create a sprite sheet containing a single sprite which covers the entire map
+    pub fn sprite_sheet(&self) -> SpriteSheet {
+        map_sprite_sheet(self.texture.clone(), self.width, self.height)
+    }
+}
+
+fn map_sprite_sheet(texture: Handle<Texture>, width: u32, height: u32) -> SpriteSheet {
+    SpriteSheet {
+        texture,
+        sprites: vec![Sprite::from_pixel_values(
+            width, height, width, height, 0, 0, [0.0; 2], false, false,
+        )],
+    }
+}
+
+impl Component for StaticMap {
+    type Storage = DenseVecStorage<Self>;
+}
+
 impl StrategyDesc for StaticLoad {
-    type Result = Handle<Texture>;
+    type Result = StaticMap;
 }
 
 impl<'a> LoadStrategy<'a> for StaticLoad {
-    type SystemData = ();
+    type SystemData = (
+        ReadExpect<'a, Loader>,
+        Read<'a, AssetStorage<Texture>>,
+        Read<'a, AssetStorage<SpriteSheet>>,
+    );
+    type SpawnData = WriteStorage<'a, StaticMap>;
 
     fn load(
-        _map: &Map,
-        _source: Arc<dyn Source>,
-        _progress: &mut ProgressCounter,
-        _system_data: &mut Self::SystemData,
+        map: &Map,
+        bounds: &MapBounds,
+        source: Arc<dyn Source>,
+        progress: &mut ProgressCounter,
+        system_data: &mut Self::SystemData,
     ) -> Result<Self::Result, Error> {
-        unimplemented!()
+        #[cfg(feature = "profiler")]
+        profile_scope!("StaticLoad::load");
+
+        let (loader, storage, sheets) = system_data;
+
+        let image = bake_map(map, bounds, source)?;
+        let (width, height) = image.dimensions();
+        let texture = load_texture(image.into_raw(), width, height, loader, progress, storage);
+
+        let sheet = map_sprite_sheet(texture.clone(), width, height);
+
+        Ok(StaticMap {
+            texture,
+            sheet: loader.load_from_data(sheet, &mut *progress, &**sheets),
+            width,
+            height,
+        })
+    }
+
+    fn supports(_orientation: MapOrientation) -> bool {
+        true
+    }
+
+    fn add_to_entity(
+        result: &Self::Result,
+        entity: Entity,
+        child_data: &mut ChildData<'a>,
+        spawn_data: &mut Self::SpawnData,
+    ) -> Result<(), Error> {
+        spawn_data.insert(entity, result.clone())?;
+        child_data
+            .sprites
+            .insert(entity, SpriteRender::new(result.sheet.clone(), 0))?;
+        child_data.transparent.insert(entity, Transparent)?;
+        Ok(())
     }
 }
-// ), error: Compat { error: ErrorMessage { msg: "Format Rgba8Srgb is not supported and no suitable conversion found." }
+
+/// Draw every visible tile layer of the map in order onto a single image
+fn bake_map(map: &Map, bounds: &MapBounds, source: Arc<dyn Source>) -> Result<RgbaImage, Error> {
+    #[cfg(feature = "profiler")]
+    profile_scope!("bake_map");
+
+    let sets = map
+        .tilesets
+        .iter()
+        .map(|x| x.unwrap().clone())
+        .collect::<Vec<Tileset>>();
+    let tiles = TileImages::load(&sets[..], source)?;
+
+    let mut image = RgbaImage::new(bounds.pixel_width(), bounds.pixel_height());
+
+    for layer in map.layers.iter().filter(|x| x.visible) {
+        // Tiles are cached per layer since the layer's opacity is applied to them
+        let mut cache: HashMap<u32, RgbaImage> = HashMap::new();
+
+        // Tiles lower on the screen are drawn last so they overlap the tiles behind them
+        let mut cells: Vec<_> = layer_tiles(layer)
+            .map(|(x, y, tile)| (bounds.tile_to_pixel(x, y), tile))
+            .collect();
+        cells.sort_by(|(a, _), (b, _)| a.1.total_cmp(&b.1).then(a.0.total_cmp(&b.0)));
+
+        for ((left, top), tile) in cells {
+            let (gid, flip) = Flip::decode(tile.gid);
+
+            if gid == 0 {
+                continue;
+            }
+
+            let pixels = match cache.entry(tile.gid) {
+                Entry::Occupied(v) => v.into_mut(),
+                Entry::Vacant(v) => match tiles.tile(gid, flip) {
+                    Some(mut pixels) => {
+                        if layer.opacity < 1.0 {
+                            for pixel in pixels.pixels_mut() {
+                                pixel[3] = (pixel[3] as f32 * layer.opacity) as u8;
+                            }
+                        }
+                        v.insert(pixels)
+                    }
+                    None => {
+                        return Err(Error::from_string(format!(
+                            "Unable to find tile with gid: {:}",
+                            gid
+                        )))
+                    }
+                },
+            };
+
+            // Tiles larger than the grid are aligned to the bottom left corner of their cell
+            let bottom = top as i64 + map.tile_height as i64;
+            overlay(
+                &mut image,
+                &*pixels,
+                left as i64,
+                bottom - pixels.height() as i64,
+            );
+        }
+    }
+
+    Ok(image)
+}
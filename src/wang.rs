@@ -0,0 +1,592 @@
+//! Runtime terrain painting using the Wang sets defined in a map's tilesets
+
+use std::collections::HashMap;
+
+use amethyst::core::math::Point3;
+use amethyst::ecs::{Component, DenseVecStorage, Entity};
+use amethyst::tiles::CoordinateEncoder;
+use amethyst::Error;
+use tiled::Map;
+use xml::attribute::OwnedAttribute;
+use xml::reader::{EventReader, XmlEvent};
+
+use crate::bounds::{layer_tiles, MapBounds};
+use crate::editing::{set_tiles, EditData};
+use crate::Flip;
+
+#[cfg(feature = "profiler")]
+use thread_profiler::profile_scope;
+
+/// The position of each entry of a wang id relative to the center of a tile, measured in half
+/// tiles. Entries alternate between edges and corners clockwise starting from the top edge.
+const WANG_POSITIONS: [(i32, i32); 8] = [
+    (0, -1),
+    (1, -1),
+    (1, 0),
+    (1, 1),
+    (0, 1),
+    (-1, 1),
+    (-1, 0),
+    (-1, -1),
+];
+
+/// The colors of the edges and corners of a tile, where 0 means the entry is unset
+pub type WangId = [u8; 8];
+
+/// Which parts of a tile are matched against its neighbours
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WangSetType {
+    Corner,
+    Edge,
+    Mixed,
+}
+
+impl WangSetType {
+    fn uses(&self, index: usize) -> bool {
+        match self {
+            WangSetType::Corner => index % 2 == 1,
+            WangSetType::Edge => index % 2 == 0,
+            WangSetType::Mixed => true,
+        }
+    }
+}
+
+/// A Wang set from a tileset. Colors are numbered from 1 in the order they are listed in Tiled.
+#[derive(Debug, Clone)]
+pub struct WangSet {
+    pub name: String,
+    pub set_type: WangSetType,
+    pub colors: Vec<String>,
+    /// The wang id of every tile in the set keyed by gid
+    pub tiles: HashMap<u32, WangId>,
+}
+
+impl WangSet {
+    /// Find the number of a color from its name
+    pub fn color(&self, name: &str) -> Option<u8> {
+        self.colors
+            .iter()
+            .position(|x| x == name)
+            .map(|x| x as u8 + 1)
+    }
+
+    /// Find the tile which best matches a wang id. Unset entries match anything and the tile with
+    /// the fewest mismatched entries is chosen.
+    pub fn best_tile(&self, wang_id: &WangId) -> Option<u32> {
+        let mut best: Option<(usize, u32)> = None;
+
+        for (gid, tile_id) in &self.tiles {
+            let mismatches = (0..8)
+                .filter(|i| self.set_type.uses(*i) && wang_id[*i] != 0)
+                .filter(|i| tile_id[*i] != wang_id[*i])
+                .count();
+
+            // Ties are broken by gid so the choice does not depend on the order of the map
+            if best.map(|x| (mismatches, *gid) < x).unwrap_or(true) {
+                best = Some((mismatches, *gid));
+            }
+        }
+
+        best.map(|(_, gid)| gid)
+    }
+}
+
+fn attribute<'s>(attributes: &'s [OwnedAttribute], name: &str) -> Option<&'s str> {
+    attributes
+        .iter()
+        .find(|x| x.name.local_name == name)
+        .map(|x| x.value.as_str())
+}
+
+/// Read the Wang sets of every tileset in a tileset or map file. `first_gid` is used for tileset
+/// files, while the tilesets embedded in a map provide their own. The older hexadecimal wang ids
+/// written before Tiled 1.5 are not supported and those tiles are skipped.
+pub fn parse_wang_sets(bytes: &[u8], first_gid: u32) -> Result<Vec<WangSet>, Error> {
+    #[cfg(feature = "profiler")]
+    profile_scope!("parse_wang_sets");
+
+    let mut sets = Vec::new();
+    let mut current: Option<WangSet> = None;
+    let mut gid = first_gid;
+
+    for event in EventReader::new(bytes) {
+        match event.map_err(Error::new)? {
+            XmlEvent::StartElement {
+                name, attributes, ..
+            } => match name.local_name.as_str() {
+                "tileset" => {
+                    if let Some(v) = attribute(&attributes, "firstgid") {
+                        gid = v.parse().map_err(Error::new)?;
+                    }
+                }
+                "wangset" => {
+                    let set_type = match attribute(&attributes, "type") {
+                        Some("edge") => WangSetType::Edge,
+                        Some("mixed") => WangSetType::Mixed,
+                        _ => WangSetType::Corner,
+                    };
+
+                    current = Some(WangSet {
+                        name: attribute(&attributes, "name").unwrap_or("").to_owned(),
+                        set_type,
+                        colors: Vec::new(),
+                        tiles: HashMap::new(),
+                    });
+                }
+                "wangcolor" => {
+                    if let Some(set) = &mut current {
+                        let name = attribute(&attributes, "name").unwrap_or("");
+                        set.colors.push(name.to_owned());
+                    }
+                }
+                "wangtile" => {
+                    let set = match &mut current {
+                        Some(v) => v,
+                        None => continue,
+                    };
+
+                    let id = attribute(&attributes, "tileid").and_then(|x| x.parse::<u32>().ok());
+                    let wang_id = attribute(&attributes, "wangid").and_then(parse_wang_id);
+
+                    if let (Some(id), Some(wang_id)) = (id, wang_id) {
+                        set.tiles.insert(gid + id, wang_id);
+                    }
+                }
+                _ => {}
+            },
+            XmlEvent::EndElement { name } if name.local_name == "wangset" => {
+                sets.extend(current.take());
+            }
+            _ => {}
+        }
+    }
+
+    Ok(sets)
+}
+
+fn parse_wang_id(value: &str) -> Option<WangId> {
+    let mut wang_id = [0; 8];
+    let mut parts = value.split(',');
+
+    for entry in wang_id.iter_mut() {
+        *entry = parts.next()?.trim().parse().ok()?;
+    }
+
+    match parts.next() {
+        Some(_) => None,
+        None => Some(wang_id),
+    }
+}
+
+/// A change to a single cell made while painting terrain
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TileChange {
+    pub x: u32,
+    pub y: u32,
+    /// The position of the layer within the map's tile layers
+    pub layer: u32,
+    /// The new gid of the cell in Tiled
+    pub gid: u32,
+}
+
+/// Component added to the map entity of maps with Wang sets, keeping the gid of every cell so
+/// terrain can be painted at runtime. Coordinates match the positions within a `TileMap` loaded
+/// from the same map.
+#[derive(Debug, Clone)]
+pub struct MapTerrain {
+    sets: Vec<WangSet>,
+    width: u32,
+    height: u32,
+    layers: u32,
+    /// The index of each tile layer among every layer of the map, which is its z coordinate
+    /// within a `TileMap`
+    layer_indices: Vec<u32>,
+    gids: Vec<u32>,
+}
+
+impl Component for MapTerrain {
+    type Storage = DenseVecStorage<Self>;
+}
+
+impl MapTerrain {
+    pub fn new(map: &Map, bounds: &MapBounds, sets: Vec<WangSet>) -> Self {
+        #[cfg(feature = "profiler")]
+        profile_scope!("collect_map_terrain");
+
+        let size = (bounds.width * bounds.height) as usize;
+        let mut gids = vec![0; size * map.layers.len()];
+
+        for (index, layer) in map.layers.iter().enumerate() {
+            for (x, y, tile) in layer_tiles(layer) {
+                if let Some((x, y)) = bounds.to_local(x, y) {
+                    gids[index * size + (y * bounds.width + x) as usize] = tile.gid;
+                }
+            }
+        }
+
+        Self {
+            sets,
+            width: bounds.width,
+            height: bounds.height,
+            layers: map.layers.len() as u32,
+            layer_indices: map.layers.iter().map(|x| x.layer_index).collect(),
+            gids,
+        }
+    }
+
+    pub fn wang_sets(&self) -> &[WangSet] {
+        &self.sets
+    }
+
+    pub fn wang_set(&self, name: &str) -> Option<&WangSet> {
+        self.sets.iter().find(|x| x.name == name)
+    }
+
+    /// The gid of a cell in Tiled including its flip flags
+    pub fn gid(&self, x: u32, y: u32, layer: u32) -> Option<u32> {
+        match x < self.width && y < self.height && layer < self.layers {
+            true => Some(self.gids[self.index(x, y, layer)]),
+            false => None,
+        }
+    }
+
+    /// Record a tile placed without painting terrain so later painting starts from it
+    pub fn set_gid(&mut self, x: u32, y: u32, layer: u32, gid: u32) {
+        assert!(
+            x < self.width && y < self.height && layer < self.layers,
+            "Cell is outside of the grid"
+        );
+
+        let index = self.index(x, y, layer);
+        self.gids[index] = gid;
+    }
+
+    fn index(&self, x: u32, y: u32, layer: u32) -> usize {
+        ((layer * self.height + y) * self.width + x) as usize
+    }
+
+    /// Paint a terrain color onto a cell, choosing new tiles for the cell and any neighbouring
+    /// cells of the same Wang set so their edges and corners line up. Returns every cell which was
+    /// changed.
+    pub fn paint(
+        &mut self,
+        set_name: &str,
+        color: &str,
+        layer: u32,
+        x: u32,
+        y: u32,
+    ) -> Result<Vec<TileChange>, Error> {
+        #[cfg(feature = "profiler")]
+        profile_scope!("paint_terrain");
+
+        if self.gid(x, y, layer).is_none() {
+            return Err(Error::from_string(format!(
+                "Unable to paint terrain outside of the map at ({}, {})",
+                x, y
+            )));
+        }
+
+        let set = match self.wang_set(set_name) {
+            Some(v) => v,
+            None => {
+                return Err(Error::from_string(format!(
+                    "Unable to find wang set: {:?}",
+                    set_name
+                )))
+            }
+        };
+
+        let color = match set.color(color) {
+            Some(v) => v,
+            None => {
+                return Err(Error::from_string(format!(
+                    "Unable to find color {:?} in wang set {:?}",
+                    color, set_name
+                )))
+            }
+        };
+
+        let mut changes = Vec::new();
+
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                let (cell_x, cell_y) = (x as i64 + dx as i64, y as i64 + dy as i64);
+                if cell_x < 0 || cell_y < 0 {
+                    continue;
+                }
+
+                let (cell_x, cell_y) = (cell_x as u32, cell_y as u32);
+                let current = match self.gid(cell_x, cell_y, layer) {
+                    Some(v) => Flip::decode(v).0,
+                    None => continue,
+                };
+
+                // Neighbours only change if they are already part of the terrain
+                let mut wang_id = match set.tiles.get(&current) {
+                    Some(v) => *v,
+                    None if dx == 0 && dy == 0 => [0; 8],
+                    None => continue,
+                };
+
+                // Every entry touching the painted cell takes the new color
+                for (index, (px, py)) in WANG_POSITIONS.iter().enumerate() {
+                    let (px, py) = (2 * dx + px, 2 * dy + py);
+                    if set.set_type.uses(index) && px.abs() <= 1 && py.abs() <= 1 {
+                        wang_id[index] = color;
+                    }
+                }
+
+                if let Some(gid) = set.best_tile(&wang_id) {
+                    if gid != current {
+                        changes.push(TileChange {
+                            x: cell_x,
+                            y: cell_y,
+                            layer,
+                            gid,
+                        });
+                    }
+                }
+            }
+        }
+
+        for change in &changes {
+            self.set_gid(change.x, change.y, change.layer, change.gid);
+        }
+
+        Ok(changes)
+    }
+
+    /// Write painted tiles into the `TileMap` of a map entity, adding any tiles which were never
+    /// packed to its sprite sheet
+    pub fn apply<E: CoordinateEncoder>(
+        &self,
+        changes: &[TileChange],
+        map: Entity,
+        data: &mut EditData<'_, E>,
+    ) -> Result<(), Error> {
+        let tiles: Vec<(Point3<u32>, u32)> = changes
+            .iter()
+            .map(|change| {
+                let z = self.layer_indices[change.layer as usize];
+                (Point3::new(change.x, change.y, z), change.gid)
+            })
+            .collect();
+
+        set_tiles(data, map, &tiles[..])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tiled::parse;
+
+    fn wang_set(set_type: WangSetType, tiles: &[(u32, WangId)]) -> WangSet {
+        WangSet {
+            name: "Ground".to_owned(),
+            set_type,
+            colors: vec!["Grass".to_owned(), "Sand".to_owned()],
+            tiles: tiles.iter().copied().collect(),
+        }
+    }
+
+    /// A corner set with a tile for every combination of grass and sand corners. Each corner of
+    /// a tile adds a bit to its gid, starting from the top right corner and going clockwise.
+    fn corner_set() -> WangSet {
+        let tiles: Vec<(u32, WangId)> = (0..16)
+            .map(|mask| {
+                let mut wang_id = [0; 8];
+                for (bit, index) in [1, 3, 5, 7].iter().enumerate() {
+                    wang_id[*index] = match mask & (1 << bit) != 0 {
+                        true => 2,
+                        false => 1,
+                    };
+                }
+                (mask + 1, wang_id)
+            })
+            .collect();
+
+        wang_set(WangSetType::Corner, &tiles[..])
+    }
+
+    fn terrain(data: &str) -> MapTerrain {
+        let document = format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<map version="1.5" orientation="orthogonal" renderorder="right-down" width="3" height="3" tilewidth="16" tileheight="16" infinite="0" nextlayerid="2" nextobjectid="1">
+ <layer id="1" name="Ground" width="3" height="3">
+  <data encoding="csv">{}</data>
+ </layer>
+</map>"#,
+            data
+        );
+
+        let map = parse(document.as_bytes()).unwrap();
+        MapTerrain::new(&map, &MapBounds::of(&map), vec![corner_set()])
+    }
+
+    fn change(x: u32, y: u32, gid: u32) -> TileChange {
+        TileChange {
+            x,
+            y,
+            layer: 0,
+            gid,
+        }
+    }
+
+    fn sorted(mut changes: Vec<TileChange>) -> Vec<TileChange> {
+        changes.sort_by_key(|x| (x.y, x.x));
+        changes
+    }
+
+    #[test]
+    fn wang_ids_need_eight_entries() {
+        assert_eq!(
+            parse_wang_id("0,1,0,2,0,3,0,4"),
+            Some([0, 1, 0, 2, 0, 3, 0, 4])
+        );
+        assert_eq!(
+            parse_wang_id(" 1, 2,3,4,5,6,7,8 "),
+            Some([1, 2, 3, 4, 5, 6, 7, 8])
+        );
+        assert_eq!(parse_wang_id("0,1,0,2,0,3,0"), None);
+        assert_eq!(parse_wang_id("0,1,0,2,0,3,0,4,0"), None);
+        assert_eq!(parse_wang_id("0,1,0,2,0,3,0,x"), None);
+        assert_eq!(parse_wang_id("0x10101010"), None);
+    }
+
+    #[test]
+    fn wang_sets_skip_hexadecimal_ids() {
+        let document = r##"<?xml version="1.0" encoding="UTF-8"?>
+<tileset version="1.5" name="terrain" tilewidth="16" tileheight="16" tilecount="4" columns="2">
+ <image source="terrain.png" width="32" height="32"/>
+ <wangsets>
+  <wangset name="Ground" type="mixed" tile="-1">
+   <wangcolor name="Grass" color="#00ff00" tile="-1" probability="1"/>
+   <wangcolor name="Sand" color="#ffff00" tile="-1" probability="1"/>
+   <wangtile tileid="0" wangid="1,1,1,1,1,1,1,1"/>
+   <wangtile tileid="1" wangid="0x22222222"/>
+   <wangtile tileid="2" wangid="1,2,1"/>
+   <wangtile tileid="3" wangid="2,2,2,2,2,2,2,2"/>
+  </wangset>
+  <wangset name="Paths" type="edge" tile="-1">
+   <wangcolor name="Dirt" color="#ff0000" tile="-1" probability="1"/>
+   <wangtile tileid="1" wangid="1,0,0,0,1,0,0,0"/>
+  </wangset>
+ </wangsets>
+</tileset>"##;
+
+        let sets = parse_wang_sets(document.as_bytes(), 10).unwrap();
+        assert_eq!(sets.len(), 2);
+
+        let ground = &sets[0];
+        assert_eq!(ground.name, "Ground");
+        assert_eq!(ground.set_type, WangSetType::Mixed);
+        assert_eq!(ground.color("Sand"), Some(2));
+        assert_eq!(ground.color("Dirt"), None);
+
+        let mut gids: Vec<u32> = ground.tiles.keys().copied().collect();
+        gids.sort_unstable();
+        assert_eq!(gids, vec![10, 13]);
+
+        let paths = &sets[1];
+        assert_eq!(paths.set_type, WangSetType::Edge);
+        assert_eq!(paths.tiles.get(&11), Some(&[1, 0, 0, 0, 1, 0, 0, 0]));
+    }
+
+    #[test]
+    fn corner_sets_only_match_corners() {
+        let set = corner_set();
+        assert_eq!(set.best_tile(&[0, 2, 0, 1, 0, 1, 0, 1]), Some(2));
+        assert_eq!(set.best_tile(&[2, 2, 2, 1, 2, 1, 2, 1]), Some(2));
+        assert_eq!(set.best_tile(&[0, 2, 0, 2, 0, 2, 0, 2]), Some(16));
+
+        // Unset entries match anything, so ties go to the lowest gid
+        assert_eq!(set.best_tile(&[0, 2, 0, 0, 0, 0, 0, 0]), Some(2));
+        assert_eq!(set.best_tile(&[0; 8]), Some(1));
+    }
+
+    #[test]
+    fn edge_sets_only_match_edges() {
+        let set = wang_set(
+            WangSetType::Edge,
+            &[(1, [1, 0, 1, 0, 1, 0, 1, 0]), (2, [2, 0, 1, 0, 1, 0, 1, 0])],
+        );
+
+        assert_eq!(set.best_tile(&[2, 1, 1, 1, 1, 1, 1, 1]), Some(2));
+        assert_eq!(set.best_tile(&[1, 2, 1, 2, 1, 2, 1, 2]), Some(1));
+        assert_eq!(set.best_tile(&[2, 0, 2, 0, 2, 0, 2, 0]), Some(2));
+    }
+
+    #[test]
+    fn mixed_sets_match_every_entry() {
+        let set = wang_set(
+            WangSetType::Mixed,
+            &[
+                (1, [1; 8]),
+                (2, [1, 2, 1, 1, 1, 1, 1, 1]),
+                (3, [2, 2, 1, 1, 1, 1, 1, 1]),
+            ],
+        );
+
+        assert_eq!(set.best_tile(&[1, 2, 1, 1, 1, 1, 1, 1]), Some(2));
+        assert_eq!(set.best_tile(&[2, 2, 1, 1, 1, 1, 1, 1]), Some(3));
+        assert_eq!(set.best_tile(&[2, 1, 1, 1, 1, 1, 1, 1]), Some(1));
+        assert_eq!(wang_set(WangSetType::Mixed, &[]).best_tile(&[1; 8]), None);
+    }
+
+    #[test]
+    fn painting_updates_the_corners_of_neighbours() {
+        let mut terrain = terrain("\n1,1,1,\n1,1,1,\n1,1,0\n");
+        let changes = terrain.paint("Ground", "Sand", 0, 1, 1).unwrap();
+
+        // Cells which are not part of the terrain are left alone
+        assert_eq!(
+            sorted(changes),
+            vec![
+                change(0, 0, 3),
+                change(1, 0, 7),
+                change(2, 0, 5),
+                change(0, 1, 4),
+                change(1, 1, 16),
+                change(2, 1, 13),
+                change(0, 2, 2),
+                change(1, 2, 10),
+            ]
+        );
+        assert_eq!(terrain.gid(1, 1, 0), Some(16));
+        assert_eq!(terrain.gid(2, 2, 0), Some(0));
+
+        assert!(terrain.paint("Ground", "Sand", 0, 1, 1).unwrap().is_empty());
+    }
+
+    #[test]
+    fn painting_at_the_edge_of_the_map() {
+        let mut terrain = terrain("\n1,1,1,\n1,1,1,\n1,1,1\n");
+        let changes = terrain.paint("Ground", "Sand", 0, 0, 0).unwrap();
+
+        assert_eq!(
+            sorted(changes),
+            vec![
+                change(0, 0, 16),
+                change(1, 0, 13),
+                change(0, 1, 10),
+                change(1, 1, 9),
+            ]
+        );
+
+        let changes = terrain.paint("Ground", "Sand", 0, 2, 2).unwrap();
+        assert_eq!(
+            sorted(changes),
+            vec![
+                change(1, 1, 11),
+                change(2, 1, 7),
+                change(1, 2, 4),
+                change(2, 2, 16),
+            ]
+        );
+
+        assert!(terrain.paint("Ground", "Sand", 0, 3, 0).is_err());
+        assert!(terrain.paint("Ground", "Dirt", 0, 0, 0).is_err());
+        assert!(terrain.paint("Paths", "Sand", 0, 0, 0).is_err());
+    }
+}
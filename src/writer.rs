@@ -0,0 +1,970 @@
+//! Serialization of maps back into the TMX format used by Tiled
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Component, Path};
+
+use amethyst::core::math::Point3;
+use amethyst::tiles::{CoordinateEncoder, MapStorage, TileMap};
+use amethyst::Error;
+use tiled::{
+    Colour, Image, ImageLayer, Layer, LayerTile, Map, Object, ObjectGroup, ObjectShape, Properties,
+    PropertyValue, Tileset, TilesetRef,
+};
+use xml::common::XmlVersion;
+use xml::writer::{EmitterConfig, EventWriter, XmlEvent};
+
+use crate::bounds::MapBounds;
+use crate::orientation::{MapOrientation, StaggerAxis, StaggerIndex};
+use crate::strategy::TileAtlas;
+use crate::TileGid;
+
+#[cfg(feature = "profiler")]
+use thread_profiler::profile_scope;
+
+/// Writes maps as TMX files which can be opened in Tiled
+#[derive(Debug, Clone, Default)]
+pub struct TmxWriter {
+    /// Tilesets to write as a reference to an external `.tsx` file instead of embedding them in the
+    /// map, keyed by tileset name. Paths are relative to the written map.
+    pub external_tilesets: HashMap<String, String>,
+    /// The orientation of the map including its stagger settings, which the tiled crate does not
+    /// keep. Use the orientation of the `MapBounds` of a loaded map. Staggered and hexagonal maps
+    /// can only be written when it is given.
+    pub orientation: Option<MapOrientation>,
+    /// The path of the written map within the asset directory. Maps loaded through `TiledFormat`
+    /// store image paths relative to the asset directory, so they are rewritten to be relative to
+    /// this path. Image paths are written unchanged when it is not given.
+    pub path: Option<String>,
+}
+
+/// Write an orthogonal or isometric map as TMX, embedding any tilesets which were loaded along
+/// with it
+pub fn write_map<W: Write>(map: &Map, writer: W) -> Result<(), Error> {
+    TmxWriter::default().write(map, writer)
+}
+
+/// Any layer of a map, which Tiled stores in a single list ordered from back to front
+enum AnyLayer<'a> {
+    Tiles(&'a Layer),
+    Image(&'a ImageLayer),
+    Objects(&'a ObjectGroup),
+}
+
+impl TmxWriter {
+    pub fn write<W: Write>(&self, map: &Map, writer: W) -> Result<(), Error> {
+        #[cfg(feature = "profiler")]
+        profile_scope!("write_tmx");
+
+        let mut writer = EmitterConfig::new()
+            .perform_indent(true)
+            .create_writer(writer);
+
+        writer
+            .write(XmlEvent::StartDocument {
+                version: XmlVersion::Version10,
+                encoding: Some("UTF-8"),
+                standalone: None,
+            })
+            .map_err(Error::new)?;
+
+        let mut layers: Vec<(u32, AnyLayer)> = Vec::new();
+        layers.extend(
+            map.layers
+                .iter()
+                .map(|x| (x.layer_index, AnyLayer::Tiles(x))),
+        );
+        layers.extend(
+            map.image_layers
+                .iter()
+                .map(|x| (x.layer_index, AnyLayer::Image(x))),
+        );
+        layers.extend(
+            map.object_groups
+                .iter()
+                .map(|x| (x.layer_index.unwrap_or(u32::MAX), AnyLayer::Objects(x))),
+        );
+        layers.sort_by_key(|(index, _)| *index);
+
+        let next_object = map
+            .object_groups
+            .iter()
+            .flat_map(|x| &x.objects)
+            .map(|x| x.id + 1)
+            .max()
+            .unwrap_or(1);
+
+        let infinite = map.layers.iter().any(|x| !x.chunks.is_empty());
+        let orientation = self.orientation(map)?;
+
+        let mut attributes = vec![
+            ("version", "1.2".to_owned()),
+            ("orientation", orientation_name(orientation).to_owned()),
+            ("renderorder", "right-down".to_owned()),
+            ("width", map.width.to_string()),
+            ("height", map.height.to_string()),
+            ("tilewidth", map.tile_width.to_string()),
+            ("tileheight", map.tile_height.to_string()),
+        ];
+
+        match orientation {
+            MapOrientation::Hexagonal {
+                axis,
+                index,
+                side_length,
+            } => {
+                attributes.push(("hexsidelength", side_length.to_string()));
+                attributes.extend(stagger_attributes(axis, index));
+            }
+            MapOrientation::Staggered { axis, index } => {
+                attributes.extend(stagger_attributes(axis, index));
+            }
+            _ => {}
+        }
+
+        if let Some(colour) = &map.background_colour {
+            attributes.push(("backgroundcolor", colour_value(colour)));
+        }
+
+        attributes.push(("infinite", (infinite as u8).to_string()));
+        attributes.push(("nextlayerid", (layers.len() + 1).to_string()));
+        attributes.push(("nextobjectid", next_object.to_string()));
+
+        start(&mut writer, "map", &attributes)?;
+        write_properties(&mut writer, &map.properties)?;
+
+        for tileset in &map.tilesets {
+            let (first_gid, path) = match tileset {
+                TilesetRef::Path(path, first_gid) => (*first_gid, path),
+                TilesetRef::TileSet(set) => match self.external_tilesets.get(&set.name) {
+                    Some(path) => (set.first_gid, path),
+                    None => {
+                        self.write_tileset(&mut writer, set)?;
+                        continue;
+                    }
+                },
+            };
+
+            let attributes = [
+                ("firstgid", first_gid.to_string()),
+                ("source", path.clone()),
+            ];
+            start(&mut writer, "tileset", &attributes)?;
+            end(&mut writer)?;
+        }
+
+        for (id, (_, layer)) in layers.iter().enumerate() {
+            let id = (id + 1).to_string();
+
+            match layer {
+                AnyLayer::Tiles(layer) => write_layer(&mut writer, map, layer, id)?,
+                AnyLayer::Image(layer) => self.write_image_layer(&mut writer, layer, id)?,
+                AnyLayer::Objects(group) => write_object_group(&mut writer, group, Some(id))?,
+            }
+        }
+
+        end(&mut writer)
+    }
+
+    /// Find the orientation to write, which must be given for maps with stagger settings
+    fn orientation(&self, map: &Map) -> Result<MapOrientation, Error> {
+        match (self.orientation, MapOrientation::of(map)) {
+            (Some(v), _) => Ok(v),
+            (None, v @ MapOrientation::Orthogonal) | (None, v @ MapOrientation::Isometric) => Ok(v),
+            (None, _) => Err(Error::from_string(
+                "Unable to write the stagger settings of a map without its orientation".to_owned(),
+            )),
+        }
+    }
+
+    /// The path of an image as it is written in the map
+    fn image_source(&self, source: &str) -> String {
+        match &self.path {
+            Some(path) => relative_path(path, source),
+            None => source.to_owned(),
+        }
+    }
+
+    fn write_image<W: Write>(
+        &self,
+        writer: &mut EventWriter<W>,
+        image: &Image,
+    ) -> Result<(), Error> {
+        let mut attributes = vec![("source", self.image_source(&image.source))];
+
+        if let Some(colour) = &image.transparent_colour {
+            attributes.push(("trans", colour_value(colour)[1..].to_owned()));
+        }
+
+        attributes.push(("width", image.width.to_string()));
+        attributes.push(("height", image.height.to_string()));
+
+        start(writer, "image", &attributes)?;
+        end(writer)
+    }
+
+    fn write_tileset<W: Write>(
+        &self,
+        writer: &mut EventWriter<W>,
+        set: &Tileset,
+    ) -> Result<(), Error> {
+        let mut attributes = vec![
+            ("firstgid", set.first_gid.to_string()),
+            ("name", set.name.clone()),
+            ("tilewidth", set.tile_width.to_string()),
+            ("tileheight", set.tile_height.to_string()),
+        ];
+
+        if set.spacing != 0 {
+            attributes.push(("spacing", set.spacing.to_string()));
+        }
+
+        if set.margin != 0 {
+            attributes.push(("margin", set.margin.to_string()));
+        }
+
+        if let Some(count) = set.tilecount {
+            attributes.push(("tilecount", count.to_string()));
+        }
+
+        // Tiled expects the number of columns for tilesets made from a single image
+        if let [image] = &set.images[..] {
+            let columns = (image.width as u32 + set.spacing).saturating_sub(2 * set.margin)
+                / (set.tile_width + set.spacing);
+            attributes.push(("columns", columns.to_string()));
+        }
+
+        start(writer, "tileset", &attributes)?;
+        write_properties(writer, &set.properties)?;
+
+        for image in &set.images {
+            self.write_image(writer, image)?;
+        }
+
+        for tile in &set.tiles {
+            let mut attributes = vec![("id", tile.id.to_string())];
+
+            if let Some(tile_type) = &tile.tile_type {
+                attributes.push(("type", tile_type.clone()));
+            }
+
+            if tile.probability != 1.0 {
+                attributes.push(("probability", tile.probability.to_string()));
+            }
+
+            start(writer, "tile", &attributes)?;
+            write_properties(writer, &tile.properties)?;
+
+            for image in &tile.images {
+                self.write_image(writer, image)?;
+            }
+
+            if let Some(group) = &tile.objectgroup {
+                write_object_group(writer, group, None)?;
+            }
+
+            if let Some(frames) = &tile.animation {
+                start(writer, "animation", &[])?;
+
+                for frame in frames {
+                    let attributes = [
+                        ("tileid", frame.tile_id.to_string()),
+                        ("duration", frame.duration.to_string()),
+                    ];
+                    start(writer, "frame", &attributes)?;
+                    end(writer)?;
+                }
+
+                end(writer)?;
+            }
+
+            end(writer)?;
+        }
+
+        end(writer)
+    }
+
+    fn write_image_layer<W: Write>(
+        &self,
+        writer: &mut EventWriter<W>,
+        layer: &ImageLayer,
+        id: String,
+    ) -> Result<(), Error> {
+        let mut attributes = vec![("id", id), ("name", layer.name.clone())];
+        layer_attributes(
+            &mut attributes,
+            layer.visible,
+            layer.opacity,
+            (layer.offset_x, layer.offset_y),
+        );
+
+        start(writer, "imagelayer", &attributes)?;
+        write_properties(writer, &layer.properties)?;
+
+        if let Some(image) = &layer.image {
+            self.write_image(writer, image)?;
+        }
+
+        end(writer)
+    }
+}
+
+/// Find the path of a file relative to the directory of another file, where both are relative to
+/// the same directory
+fn relative_path(reference: &str, path: &str) -> String {
+    let normalize = |path: &Path| {
+        let mut parts: Vec<String> = Vec::new();
+
+        for component in path.components() {
+            match component {
+                Component::CurDir => {}
+                Component::ParentDir => match parts.last().map(String::as_str) {
+                    Some("..") | None => parts.push("..".to_owned()),
+                    Some(_) => {
+                        parts.pop();
+                    }
+                },
+                other => parts.push(other.as_os_str().to_string_lossy().into_owned()),
+            }
+        }
+
+        parts
+    };
+
+    let directory = match Path::new(reference).parent() {
+        Some(v) => normalize(v),
+        None => Vec::new(),
+    };
+    let path = normalize(Path::new(path));
+
+    let common = directory
+        .iter()
+        .zip(&path)
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let parts: Vec<&str> = directory[common..]
+        .iter()
+        .map(|_| "..")
+        .chain(path[common..].iter().map(String::as_str))
+        .collect();
+    parts.join("/")
+}
+
+fn orientation_name(orientation: MapOrientation) -> &'static str {
+    match orientation {
+        MapOrientation::Orthogonal => "orthogonal",
+        MapOrientation::Isometric => "isometric",
+        MapOrientation::Staggered { .. } => "staggered",
+        MapOrientation::Hexagonal { .. } => "hexagonal",
+    }
+}
+
+fn stagger_attributes(axis: StaggerAxis, index: StaggerIndex) -> [(&'static str, String); 2] {
+    let axis = match axis {
+        StaggerAxis::X => "x",
+        StaggerAxis::Y => "y",
+    };
+
+    let index = match index {
+        StaggerIndex::Odd => "odd",
+        StaggerIndex::Even => "even",
+    };
+
+    [
+        ("staggeraxis", axis.to_owned()),
+        ("staggerindex", index.to_owned()),
+    ]
+}
+
+fn colour_value(colour: &Colour) -> String {
+    format!("#{:02x}{:02x}{:02x}", colour.red, colour.green, colour.blue)
+}
+
+pub(crate) fn start<W: Write>(
+    writer: &mut EventWriter<W>,
+    name: &str,
+    attributes: &[(&str, String)],
+) -> Result<(), Error> {
+    let mut element = XmlEvent::start_element(name);
+
+    for (key, value) in attributes {
+        element = element.attr(*key, value);
+    }
+
+    writer.write(element).map_err(Error::new)
+}
+
+pub(crate) fn end<W: Write>(writer: &mut EventWriter<W>) -> Result<(), Error> {
+    writer.write(XmlEvent::end_element()).map_err(Error::new)
+}
+
+/// Add the attributes Tiled uses for the visibility, opacity and offset of a layer when they are
+/// not the defaults
+fn layer_attributes(
+    attributes: &mut Vec<(&str, String)>,
+    visible: bool,
+    opacity: f32,
+    offset: (f32, f32),
+) {
+    if !visible {
+        attributes.push(("visible", "0".to_owned()));
+    }
+
+    if opacity < 1.0 {
+        attributes.push(("opacity", opacity.to_string()));
+    }
+
+    if offset != (0.0, 0.0) {
+        attributes.push(("offsetx", offset.0.to_string()));
+        attributes.push(("offsety", offset.1.to_string()));
+    }
+}
+
+fn write_properties<W: Write>(
+    writer: &mut EventWriter<W>,
+    properties: &Properties,
+) -> Result<(), Error> {
+    if properties.is_empty() {
+        return Ok(());
+    }
+
+    // Sorted so the same properties always produce the same file
+    let mut properties: Vec<_> = properties.iter().collect();
+    properties.sort_by_key(|(name, _)| name.as_str());
+
+    start(writer, "properties", &[])?;
+
+    for (name, value) in properties {
+        let (kind, value) = match value {
+            PropertyValue::BoolValue(v) => ("bool", v.to_string()),
+            PropertyValue::FloatValue(v) => ("float", v.to_string()),
+            PropertyValue::IntValue(v) => ("int", v.to_string()),
+            PropertyValue::ColorValue(v) => ("color", format!("#{:08x}", v)),
+            PropertyValue::StringValue(v) => ("string", v.clone()),
+            PropertyValue::FileValue(v) => ("file", v.clone()),
+        };
+
+        let attributes = [
+            ("name", name.clone()),
+            ("type", kind.to_owned()),
+            ("value", value),
+        ];
+        start(writer, "property", &attributes)?;
+        end(writer)?;
+    }
+
+    end(writer)
+}
+
+/// Format rows of tiles as the comma separated values used by Tiled, with one row per line
+fn csv(rows: &[Vec<LayerTile>]) -> String {
+    let rows: Vec<String> = rows
+        .iter()
+        .map(|row| {
+            row.iter()
+                .map(|tile| tile.gid.to_string())
+                .collect::<Vec<_>>()
+                .join(",")
+        })
+        .collect();
+
+    format!("\n{}\n", rows.join(",\n"))
+}
+
+fn write_layer<W: Write>(
+    writer: &mut EventWriter<W>,
+    map: &Map,
+    layer: &Layer,
+    id: String,
+) -> Result<(), Error> {
+    let mut attributes = vec![
+        ("id", id),
+        ("name", layer.name.clone()),
+        ("width", map.width.to_string()),
+        ("height", map.height.to_string()),
+    ];
+    layer_attributes(
+        &mut attributes,
+        layer.visible,
+        layer.opacity,
+        (layer.offset_x, layer.offset_y),
+    );
+
+    start(writer, "layer", &attributes)?;
+    write_properties(writer, &layer.properties)?;
+    start(writer, "data", &[("encoding", "csv".to_owned())])?;
+
+    match layer.chunks.is_empty() {
+        true => {
+            let data = csv(&layer.tiles[..]);
+            writer
+                .write(XmlEvent::characters(&data))
+                .map_err(Error::new)?;
+        }
+        false => {
+            for chunk in &layer.chunks {
+                let chunk_width = chunk.tiles.get(0).map_or(0, |x| x.len());
+                let attributes = [
+                    ("x", chunk.x.to_string()),
+                    ("y", chunk.y.to_string()),
+                    ("width", chunk_width.to_string()),
+                    ("height", chunk.tiles.len().to_string()),
+                ];
+
+                start(writer, "chunk", &attributes)?;
+                let data = csv(&chunk.tiles[..]);
+                writer
+                    .write(XmlEvent::characters(&data))
+                    .map_err(Error::new)?;
+                end(writer)?;
+            }
+        }
+    }
+
+    end(writer)?;
+    end(writer)
+}
+
+/// Write an object layer, or the collision shapes of a tile when no id is given
+fn write_object_group<W: Write>(
+    writer: &mut EventWriter<W>,
+    group: &ObjectGroup,
+    id: Option<String>,
+) -> Result<(), Error> {
+    let mut attributes = Vec::new();
+
+    match id {
+        Some(id) => {
+            attributes.push(("id", id));
+            attributes.push(("name", group.name.clone()));
+        }
+        None => attributes.push(("draworder", "index".to_owned())),
+    }
+
+    if let Some(colour) = &group.colour {
+        attributes.push(("color", colour_value(colour)));
+    }
+
+    layer_attributes(&mut attributes, group.visible, group.opacity, (0.0, 0.0));
+
+    start(writer, "objectgroup", &attributes)?;
+    write_properties(writer, &group.properties)?;
+
+    for object in &group.objects {
+        write_object(writer, object)?;
+    }
+
+    end(writer)
+}
+
+fn write_object<W: Write>(writer: &mut EventWriter<W>, object: &Object) -> Result<(), Error> {
+    let mut attributes = vec![("id", object.id.to_string())];
+
+    if !object.name.is_empty() {
+        attributes.push(("name", object.name.clone()));
+    }
+
+    if !object.obj_type.is_empty() {
+        attributes.push(("type", object.obj_type.clone()));
+    }
+
+    if object.gid != 0 {
+        attributes.push(("gid", object.gid.to_string()));
+    }
+
+    attributes.push(("x", object.x.to_string()));
+    attributes.push(("y", object.y.to_string()));
+
+    if object.width != 0.0 || object.height != 0.0 {
+        attributes.push(("width", object.width.to_string()));
+        attributes.push(("height", object.height.to_string()));
+    }
+
+    if object.rotation != 0.0 {
+        attributes.push(("rotation", object.rotation.to_string()));
+    }
+
+    if !object.visible {
+        attributes.push(("visible", "0".to_owned()));
+    }
+
+    start(writer, "object", &attributes)?;
+    write_properties(writer, &object.properties)?;
+
+    let points = |points: &[(f32, f32)]| {
+        let points: Vec<String> = points.iter().map(|(x, y)| format!("{},{}", x, y)).collect();
+        [("points", points.join(" "))]
+    };
+
+    match &object.shape {
+        ObjectShape::Rect { .. } => {}
+        ObjectShape::Ellipse { .. } => {
+            start(writer, "ellipse", &[])?;
+            end(writer)?;
+        }
+        ObjectShape::Point(..) => {
+            start(writer, "point", &[])?;
+            end(writer)?;
+        }
+        ObjectShape::Polygon { points: v } => {
+            start(writer, "polygon", &points(v))?;
+            end(writer)?;
+        }
+        ObjectShape::Polyline { points: v } => {
+            start(writer, "polyline", &points(v))?;
+            end(writer)?;
+        }
+    }
+
+    end(writer)
+}
+
+/// Copy the tiles of a map loaded with `FlatLoad` or `CompressedLoad` back into the layers of the
+/// map it was loaded from, so changes made at runtime are kept when the map is written. `pages`
+/// holds the `TileMap` and `TileAtlas` of every page of the map's sprite sheet, starting with the
+/// ones on the map entity.
+pub fn store_tiles<E: CoordinateEncoder>(
+    map: &mut Map,
+    pages: &[(&TileMap<TileGid, E>, &TileAtlas)],
+) -> Result<(), Error> {
+    #[cfg(feature = "profiler")]
+    profile_scope!("store_tiles");
+
+    let bounds = MapBounds::of(map);
+
+    let gids: Vec<HashMap<TileGid, u32>> = pages
+        .iter()
+        .map(|(_, atlas)| atlas.iter().map(|(gid, tile)| (tile, gid)).collect())
+        .collect();
+
+    for layer in &mut map.layers {
+        let z = layer.layer_index;
+        let rows = layer
+            .tiles
+            .iter_mut()
+            .enumerate()
+            .map(|(y, row)| (0, y as i32, row));
+        let chunks = layer.chunks.iter_mut().flat_map(|chunk| {
+            let (chunk_x, chunk_y) = (chunk.x, chunk.y);
+            chunk
+                .tiles
+                .iter_mut()
+                .enumerate()
+                .map(move |(y, row)| (chunk_x, chunk_y + y as i32, row))
+        });
+
+        for (left, y, row) in rows.chain(chunks) {
+            for (x, tile) in row.iter_mut().enumerate() {
+                let (x, y) = match bounds.to_local(left + x as i32, y) {
+                    Some(v) => v,
+                    None => continue,
+                };
+
+                // Each cell is drawn by at most one page, which holds the tile placed in it
+                let position = Point3::new(x, y, z);
+                let placed = pages
+                    .iter()
+                    .zip(&gids)
+                    .filter_map(|((tilemap, _), gids)| Some((tilemap.get(&position)?, gids)))
+                    .find(|(placed, _)| **placed != TileGid::default());
+
+                tile.gid = match placed {
+                    None => 0,
+                    Some((placed, gids)) => match gids.get(placed) {
+                        Some(v) => *v,
+                        None => {
+                            return Err(Error::from_string(format!(
+                                "Unable to find the gid of the tile at ({}, {}) in layer {:?}",
+                                x, y, layer.name
+                            )))
+                        }
+                    },
+                };
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bounds::layer_tiles;
+    use crate::orientation::parse_orientation;
+    use crate::prefab::TileMapPrefab;
+    use crate::strategy::CompressedLoad;
+    use crate::Flip;
+    use crate::TiledFormat;
+    use amethyst::assets::{Directory, Format};
+    use amethyst::core::math::Vector3;
+    use amethyst::tiles::FlatEncoder;
+    use std::sync::Arc;
+    use tiled::{parse, Orientation};
+
+    fn load_example() -> Map {
+        let source = Arc::new(Directory::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/examples/assets"
+        )));
+
+        let value = <TiledFormat as Format<TileMapPrefab<CompressedLoad>>>::import(
+            &TiledFormat,
+            "prefab/example_map.tmx".to_owned(),
+            source,
+            None,
+        )
+        .expect("The example map should load");
+
+        match value.data {
+            TileMapPrefab::Map(map, ..) => map,
+            TileMapPrefab::Result(..) => unreachable!("Maps are loaded before being processed"),
+        }
+    }
+
+    fn round_trip(map: &Map, writer: &TmxWriter) -> Map {
+        let mut bytes = Vec::new();
+        writer.write(map, &mut bytes).expect("The map should write");
+        parse(&bytes[..]).expect("The written map should parse")
+    }
+
+    fn assert_layers_eq(a: &Map, b: &Map) {
+        assert_eq!(a.layers.len(), b.layers.len());
+
+        for (a, b) in a.layers.iter().zip(&b.layers) {
+            assert_eq!(a.name, b.name);
+            assert_eq!(a.visible, b.visible);
+            assert_eq!(a.opacity, b.opacity);
+            assert_eq!(a.layer_index, b.layer_index);
+            assert_eq!(a.properties, b.properties);
+
+            let gids = |layer: &Layer| -> Vec<Vec<u32>> {
+                layer
+                    .tiles
+                    .iter()
+                    .map(|row| row.iter().map(|x| x.gid).collect())
+                    .collect()
+            };
+            assert_eq!(gids(a), gids(b));
+        }
+    }
+
+    #[test]
+    fn example_map_round_trip() {
+        let map = load_example();
+
+        let mut writer = TmxWriter::default();
+        writer.external_tilesets.insert(
+            "example_tileset".to_owned(),
+            "example_tileset.tsx".to_owned(),
+        );
+        let written = round_trip(&map, &writer);
+
+        assert_eq!(map.width, written.width);
+        assert_eq!(map.height, written.height);
+        assert_eq!(map.tile_width, written.tile_width);
+        assert_eq!(map.tile_height, written.tile_height);
+        assert_eq!(map.orientation, written.orientation);
+        assert_eq!(map.properties, written.properties);
+        assert_layers_eq(&map, &written);
+
+        match &written.tilesets[..] {
+            [TilesetRef::Path(path, first_gid)] => {
+                assert_eq!(path, "example_tileset.tsx");
+                assert_eq!(*first_gid, 1);
+            }
+            _ => panic!("The tileset should be written as a reference"),
+        }
+    }
+
+    #[test]
+    fn embedded_tilesets_round_trip() {
+        let map = load_example();
+        let written = round_trip(&map, &TmxWriter::default());
+
+        assert_layers_eq(&map, &written);
+        assert_eq!(map.tilesets.len(), written.tilesets.len());
+
+        for (a, b) in map.tilesets.iter().zip(&written.tilesets) {
+            let (a, b) = (a.unwrap(), b.unwrap());
+            assert_eq!(a.name, b.name);
+            assert_eq!(a.first_gid, b.first_gid);
+            assert_eq!(a.tile_width, b.tile_width);
+            assert_eq!(a.tile_height, b.tile_height);
+            assert_eq!(a.images, b.images);
+        }
+    }
+
+    #[test]
+    fn objects_and_properties_round_trip() {
+        let mut map = load_example();
+
+        let mut properties = Properties::new();
+        properties.insert("solid".to_owned(), PropertyValue::BoolValue(true));
+        properties.insert("cost".to_owned(), PropertyValue::FloatValue(2.5));
+        properties.insert(
+            "name".to_owned(),
+            PropertyValue::StringValue("A \"quoted\" <name>".to_owned()),
+        );
+        map.properties = properties.clone();
+        map.layers[0].properties = properties.clone();
+
+        map.object_groups.push(ObjectGroup {
+            name: "Objects".to_owned(),
+            opacity: 1.0,
+            visible: true,
+            objects: vec![
+                Object {
+                    id: 1,
+                    gid: 0,
+                    name: "spawn".to_owned(),
+                    obj_type: "Spawn".to_owned(),
+                    width: 0.0,
+                    height: 0.0,
+                    x: 32.0,
+                    y: 48.0,
+                    rotation: 0.0,
+                    visible: true,
+                    shape: ObjectShape::Point(32.0, 48.0),
+                    properties: properties.clone(),
+                },
+                Object {
+                    id: 2,
+                    gid: 0,
+                    name: "wall".to_owned(),
+                    obj_type: String::new(),
+                    width: 0.0,
+                    height: 0.0,
+                    x: 16.0,
+                    y: 16.0,
+                    rotation: 45.0,
+                    visible: false,
+                    shape: ObjectShape::Polygon {
+                        points: vec![(0.0, 0.0), (16.0, 0.0), (16.0, 8.5)],
+                    },
+                    properties: Properties::new(),
+                },
+            ],
+            colour: None,
+            layer_index: Some(map.layers.len() as u32),
+            properties: Properties::new(),
+        });
+
+        let written = round_trip(&map, &TmxWriter::default());
+
+        assert_eq!(written.properties, properties);
+        assert_layers_eq(&map, &written);
+        assert_eq!(written.object_groups.len(), 1);
+
+        let (a, b) = (&map.object_groups[0], &written.object_groups[0]);
+        assert_eq!(a.name, b.name);
+        assert_eq!(a.objects.len(), b.objects.len());
+
+        for (a, b) in a.objects.iter().zip(&b.objects) {
+            assert_eq!(a.id, b.id);
+            assert_eq!(a.name, b.name);
+            assert_eq!(a.obj_type, b.obj_type);
+            assert_eq!((a.x, a.y), (b.x, b.y));
+            assert_eq!(a.rotation, b.rotation);
+            assert_eq!(a.visible, b.visible);
+            assert_eq!(a.shape, b.shape);
+            assert_eq!(a.properties, b.properties);
+        }
+    }
+
+    #[test]
+    fn image_paths_are_relative_to_the_written_map() {
+        let map = load_example();
+
+        let image_source = |path: &str| {
+            let writer = TmxWriter {
+                path: Some(path.to_owned()),
+                ..TmxWriter::default()
+            };
+            let written = round_trip(&map, &writer);
+            let set = &written.tilesets[0];
+            set.unwrap().images[0].source.clone()
+        };
+
+        assert_eq!(
+            image_source("prefab/example_map.tmx"),
+            "../texture/great_tileset.png"
+        );
+        assert_eq!(image_source("written.tmx"), "texture/great_tileset.png");
+        assert_eq!(image_source("texture/written.tmx"), "great_tileset.png");
+        assert_eq!(
+            image_source("maps/saved/written.tmx"),
+            "../../texture/great_tileset.png"
+        );
+    }
+
+    #[test]
+    fn stagger_settings_are_written() {
+        let mut map = load_example();
+        map.orientation = Orientation::Hexagonal;
+
+        let mut bytes = Vec::new();
+        assert!(TmxWriter::default().write(&map, &mut bytes).is_err());
+
+        let orientation = MapOrientation::Hexagonal {
+            axis: StaggerAxis::X,
+            index: StaggerIndex::Even,
+            side_length: 6,
+        };
+        let writer = TmxWriter {
+            orientation: Some(orientation),
+            ..TmxWriter::default()
+        };
+
+        let mut bytes = Vec::new();
+        writer
+            .write(&map, &mut bytes)
+            .expect("The map should write");
+        let written = parse(&bytes[..]).expect("The written map should parse");
+
+        assert_eq!(
+            parse_orientation(&bytes[..], &written).unwrap(),
+            orientation
+        );
+    }
+
+    #[test]
+    fn stored_tiles_are_read_from_every_page() {
+        let mut map = load_example();
+        let bounds = MapBounds::of(&map);
+        let z = map.layers[0].layer_index;
+
+        let map_size = Vector3::new(bounds.width, bounds.height, map.layers.len() as u32);
+        let tile_size = Vector3::new(map.tile_width, map.tile_height, 1);
+        let mut pages = Vec::new();
+
+        for (page, gid) in [5, 7].iter().enumerate() {
+            let tile = TileGid::new(page + 1, Flip::NONE);
+            let atlas = TileAtlas {
+                tiles: vec![((*gid, Flip::NONE), tile)].into_iter().collect(),
+                ..TileAtlas::default()
+            };
+
+            let mut tilemap = TileMap::<TileGid, FlatEncoder>::new(map_size, tile_size, None);
+            *tilemap.get_mut(&Point3::new(page as u32, 0, z)).unwrap() = tile;
+            pages.push((tilemap, atlas));
+        }
+
+        let pages: Vec<_> = pages
+            .iter()
+            .map(|(tilemap, atlas)| (tilemap, atlas))
+            .collect();
+        store_tiles(&mut map, &pages).unwrap();
+
+        for (x, y, tile) in layer_tiles(&map.layers[0]) {
+            let expected = match bounds.to_local(x, y) {
+                Some((0, 0)) => 5,
+                Some((1, 0)) => 7,
+                _ => 0,
+            };
+
+            assert_eq!(tile.gid, expected, "Tile {}, {}", x, y);
+        }
+    }
+}